@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE legal_holds (
+    id INTEGER NOT NULL PRIMARY KEY,
+    profile_id INTEGER NOT NULL,
+    held_by TEXT NOT NULL,
+    hold_reason TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    released_by TEXT,
+    release_reason TEXT,
+    released_at DATETIME,
+    FOREIGN KEY(profile_id) REFERENCES profiles(id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE FILE_CHUNKS;
+DROP TABLE CHUNKS;
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE chunks (
+    digest TEXT NOT NULL PRIMARY KEY,
+    size INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE file_chunks (
+    id INTEGER NOT NULL PRIMARY KEY,
+    file_id INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    chunk_digest TEXT NOT NULL,
+    FOREIGN KEY(file_id) REFERENCES files(id),
+    FOREIGN KEY(chunk_digest) REFERENCES chunks(digest)
+);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE profiles DROP COLUMN storage_url;
\ No newline at end of file
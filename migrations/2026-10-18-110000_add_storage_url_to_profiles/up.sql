@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE profiles ADD COLUMN storage_url TEXT;
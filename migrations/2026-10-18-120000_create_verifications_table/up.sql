@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE verifications (
+    id INTEGER NOT NULL PRIMARY KEY,
+    file_id INTEGER NOT NULL,
+    verified_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ok BOOLEAN NOT NULL,
+    detail TEXT,
+    FOREIGN KEY(file_id) REFERENCES files(id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE files DROP COLUMN last_verified_at;
+DROP TABLE scrub_schedules;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE scrub_schedules (
+    id INTEGER NOT NULL PRIMARY KEY,
+    profile_id INTEGER NOT NULL UNIQUE,
+    interval_hours INTEGER NOT NULL DEFAULT 24,
+    percent_per_run INTEGER NOT NULL DEFAULT 5,
+    cycle_days INTEGER NOT NULL DEFAULT 30,
+    read_limit_mb INTEGER NOT NULL DEFAULT 0,
+    last_run_at DATETIME,
+    FOREIGN KEY(profile_id) REFERENCES profiles(id)
+);
+ALTER TABLE files ADD COLUMN last_verified_at DATETIME;
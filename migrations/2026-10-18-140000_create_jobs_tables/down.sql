@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE job_items;
+DROP TABLE jobs;
\ No newline at end of file
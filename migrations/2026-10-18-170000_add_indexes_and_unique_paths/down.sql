@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX job_items_job_id_idx;
+DROP INDEX jobs_profile_id_idx;
+DROP INDEX legal_holds_profile_id_idx;
+DROP INDEX verifications_file_id_idx;
+DROP INDEX file_chunks_chunk_digest_idx;
+DROP INDEX file_chunks_file_id_idx;
+DROP INDEX files_sha256_idx;
+DROP INDEX files_profile_id_idx;
+DROP INDEX files_profile_path_idx;
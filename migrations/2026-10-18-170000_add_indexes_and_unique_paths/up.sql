@@ -0,0 +1,33 @@
+-- Your SQL goes here
+
+-- SQLite catalogs written before this may track a path more than once. The
+-- newest row of such a path stays current and the others are marked removed
+-- when the next one was added, as adding the path again does now. Nothing is
+-- deleted, so this holds for profiles under legal hold too: their rows become
+-- history, which the hold keeps.
+CREATE TEMPORARY TABLE superseded_files AS
+SELECT files.id, MIN(newer.created_at) AS removed_at
+FROM files
+JOIN files AS newer
+    ON newer.profile_id = files.profile_id
+    AND newer.file_name = files.file_name
+    AND newer.removed_at IS NULL
+    AND (newer.created_at, newer.id) > (files.created_at, files.id)
+WHERE files.removed_at IS NULL
+GROUP BY files.id;
+UPDATE files
+SET removed_at = (SELECT removed_at FROM superseded_files WHERE superseded_files.id = files.id)
+WHERE id IN (SELECT id FROM superseded_files);
+DROP TABLE superseded_files;
+
+-- A path is tracked at most once per profile. Removed rows are history and
+-- may repeat.
+CREATE UNIQUE INDEX files_profile_path_idx ON files(profile_id, file_name) WHERE removed_at IS NULL;
+CREATE INDEX files_profile_id_idx ON files(profile_id);
+CREATE INDEX files_sha256_idx ON files(sha256);
+CREATE INDEX file_chunks_file_id_idx ON file_chunks(file_id, seq);
+CREATE INDEX file_chunks_chunk_digest_idx ON file_chunks(chunk_digest);
+CREATE INDEX verifications_file_id_idx ON verifications(file_id);
+CREATE INDEX legal_holds_profile_id_idx ON legal_holds(profile_id);
+CREATE INDEX jobs_profile_id_idx ON jobs(profile_id);
+CREATE INDEX job_items_job_id_idx ON job_items(job_id, seq);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE profiles (
+    id SERIAL PRIMARY KEY,
+    profile_name TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    updated_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE files (
+    id SERIAL PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    updated_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    profile_id INTEGER NOT NULL,
+    FOREIGN KEY(profile_id) REFERENCES profiles(id)
+);
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE legal_holds (
+    id SERIAL PRIMARY KEY,
+    profile_id INTEGER NOT NULL,
+    held_by TEXT NOT NULL,
+    hold_reason TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    released_by TEXT,
+    release_reason TEXT,
+    released_at TIMESTAMP,
+    FOREIGN KEY(profile_id) REFERENCES profiles(id)
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE chunks (
+    digest TEXT NOT NULL PRIMARY KEY,
+    size INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC')
+);
+
+CREATE TABLE file_chunks (
+    id SERIAL PRIMARY KEY,
+    file_id INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    chunk_digest TEXT NOT NULL,
+    FOREIGN KEY(file_id) REFERENCES files(id),
+    FOREIGN KEY(chunk_digest) REFERENCES chunks(digest)
+);
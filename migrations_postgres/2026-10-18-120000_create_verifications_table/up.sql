@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE verifications (
+    id SERIAL PRIMARY KEY,
+    file_id INTEGER NOT NULL,
+    verified_at TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP AT TIME ZONE 'UTC'),
+    ok BOOLEAN NOT NULL,
+    detail TEXT,
+    FOREIGN KEY(file_id) REFERENCES files(id)
+);
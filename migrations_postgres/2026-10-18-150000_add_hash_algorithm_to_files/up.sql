@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE files ADD COLUMN hash_algorithm TEXT NOT NULL DEFAULT 'sha3-256';
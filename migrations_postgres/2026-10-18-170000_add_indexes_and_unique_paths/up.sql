@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- PostgreSQL catalogs start at this schema, so no path is tracked more than
+-- once yet.
+
+-- A path is tracked at most once per profile. Removed rows are history and
+-- may repeat.
+CREATE UNIQUE INDEX files_profile_path_idx ON files(profile_id, file_name) WHERE removed_at IS NULL;
+CREATE INDEX files_profile_id_idx ON files(profile_id);
+CREATE INDEX files_sha256_idx ON files(sha256);
+CREATE INDEX file_chunks_file_id_idx ON file_chunks(file_id, seq);
+CREATE INDEX file_chunks_chunk_digest_idx ON file_chunks(chunk_digest);
+CREATE INDEX verifications_file_id_idx ON verifications(file_id);
+CREATE INDEX legal_holds_profile_id_idx ON legal_holds(profile_id);
+CREATE INDEX jobs_profile_id_idx ON jobs(profile_id);
+CREATE INDEX job_items_job_id_idx ON job_items(job_id, seq);
@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::StorageBackend;
+use crate::error::ArchiverError;
+
+/// Objects stored as files below a local directory.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: PathBuf) -> Self {
+        LocalBackend { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        let path = self.path(key);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+
+        // Write to a temporary name first so a crash never leaves a truncated
+        // object under its final name. The name is unique to this write, as
+        // two threads or processes may store the same chunk at once.
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let tmp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArchiverError> {
+        Ok(fs::read(self.path(key))?)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, ArchiverError> {
+        // Unlike `Path::exists`, an unreadable directory is an error rather
+        // than a missing object
+        Ok(self.path(key).try_exists()?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ArchiverError> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), ArchiverError> {
+        let path = self.path(to);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::rename(self.path(from), path)?;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        // The same directory always reads the same, however it was named
+        fs::canonicalize(&self.root)
+            .unwrap_or_else(|_| self.root.clone())
+            .display()
+            .to_string()
+    }
+}
@@ -0,0 +1,71 @@
+mod local;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sftp")]
+mod sftp;
+pub use local::LocalBackend;
+#[cfg(feature = "s3")]
+pub use s3::S3Backend;
+#[cfg(feature = "sftp")]
+pub use sftp::SftpBackend;
+
+use std::path::PathBuf;
+
+use crate::error::ArchiverError;
+
+/// Somewhere archived objects can live. Keys are `/`-separated relative paths
+/// such as `config` or `chunks/ab/ab01..`; a backend never interprets them.
+pub trait StorageBackend: Send + Sync {
+    /// Store an object, replacing any existing object with the same key. A
+    /// reader must never observe a partially written object.
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError>;
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArchiverError>;
+
+    fn exists(&self, key: &str) -> Result<bool, ArchiverError>;
+
+    fn delete(&self, key: &str) -> Result<(), ArchiverError>;
+
+    /// Move an object to another key, replacing any object there. Backends
+    /// without a rename copy the object, which `put` already makes atomic.
+    fn rename(&self, from: &str, to: &str) -> Result<(), ArchiverError> {
+        self.put(to, &self.get(from)?)?;
+        self.delete(from)
+    }
+
+    /// Human readable location, used in messages.
+    fn describe(&self) -> String;
+}
+
+/// Open the backend named by a storage URL.
+///
+/// - a plain path or `file:///path` is a local directory
+/// - `s3://bucket/prefix?endpoint=http://localhost:9000&region=us-east-1` is
+///   S3-compatible object storage, authenticated with `AWS_ACCESS_KEY_ID` and
+///   `AWS_SECRET_ACCESS_KEY`
+/// - `sftp://user@host:22/path` is a directory on an SFTP server,
+///   authenticated through ssh-agent, `SFTP_IDENTITY_FILE` or `SFTP_PASSWORD`
+pub fn open_backend(storage_url: &str) -> Result<Box<dyn StorageBackend>, ArchiverError> {
+    let url = match url::Url::parse(storage_url) {
+        Ok(url) if url.scheme().len() > 1 => url,
+        // Not a URL (or a Windows drive letter), so treat it as a local path
+        _ => return Ok(Box::new(LocalBackend::new(PathBuf::from(storage_url)))),
+    };
+
+    match url.scheme() {
+        "file" => {
+            let path = url.to_file_path().map_err(|_| {
+                ArchiverError::InvalidInput(format!("Invalid file URL: {}", storage_url))
+            })?;
+            Ok(Box::new(LocalBackend::new(path)))
+        }
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Box::new(S3Backend::from_url(&url)?)),
+        #[cfg(feature = "sftp")]
+        "sftp" => Ok(Box::new(SftpBackend::from_url(&url)?)),
+        scheme => Err(ArchiverError::InvalidInput(format!(
+            "Unsupported storage backend '{}' (is the feature enabled?)",
+            scheme
+        ))),
+    }
+}
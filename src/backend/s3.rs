@@ -0,0 +1,196 @@
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io::Read;
+
+use super::StorageBackend;
+use crate::error::ArchiverError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Objects stored in an S3-compatible bucket (AWS, MinIO, Ceph RGW, ...).
+/// Requests use path-style addressing and are signed with AWS Signature V4.
+pub struct S3Backend {
+    endpoint: String,
+    host: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    agent: ureq::Agent,
+}
+
+impl S3Backend {
+    pub fn from_url(url: &url::Url) -> Result<Self, ArchiverError> {
+        let bucket = url
+            .host_str()
+            .filter(|bucket| !bucket.is_empty())
+            .ok_or_else(|| ArchiverError::InvalidInput(format!("No bucket in {}", url)))?
+            .to_string();
+
+        let mut prefix = percent_decode_str(url.path())
+            .decode_utf8_lossy()
+            .trim_matches('/')
+            .to_string();
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let region = query("region")
+            .or_else(|| env::var("AWS_REGION").ok())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = query("endpoint")
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region))
+            .trim_end_matches('/')
+            .to_string();
+        let host = url::Url::parse(&endpoint)
+            .ok()
+            .and_then(|endpoint| {
+                let host = endpoint.host_str()?.to_string();
+                Some(match endpoint.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                })
+            })
+            .ok_or_else(|| ArchiverError::InvalidInput(format!("Invalid endpoint {}", endpoint)))?;
+
+        let credential = |name: &str| {
+            env::var(name).map_err(|_| ArchiverError::InvalidInput(format!("{} is not set", name)))
+        };
+
+        Ok(S3Backend {
+            endpoint,
+            host,
+            bucket,
+            prefix,
+            region,
+            access_key: credential("AWS_ACCESS_KEY_ID")?,
+            secret_key: credential("AWS_SECRET_ACCESS_KEY")?,
+            agent: ureq::AgentBuilder::new().build(),
+        })
+    }
+
+    fn canonical_uri(&self, key: &str) -> String {
+        let object = format!("{}{}", self.prefix, key);
+        let encoded: Vec<String> = object.split('/').map(uri_encode).collect();
+        format!("/{}/{}", uri_encode(&self.bucket), encoded.join("/"))
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        body: &[u8],
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        let uri = self.canonical_uri(key);
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(body));
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, uri, self.host, payload_hash, amz_date, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [self.region.as_str(), "s3", "aws4_request"].iter().fold(
+            hmac(
+                format!("AWS4{}", self.secret_key).as_bytes(),
+                date.as_bytes(),
+            ),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key, scope, signature
+        );
+
+        self.agent
+            .request(method, &format!("{}{}", self.endpoint, uri))
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &amz_date)
+            .set("authorization", &authorization)
+            .send_bytes(body)
+            .map_err(Box::new)
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        // S3 PUTs are atomic, so no temporary object is needed.
+        self.request("PUT", key, data).map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArchiverError> {
+        let response = self.request("GET", key, &[]).map_err(storage_error)?;
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, ArchiverError> {
+        match self.request("HEAD", key, &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(*e, ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ArchiverError> {
+        match self.request("DELETE", key, &[]) {
+            Ok(_) => Ok(()),
+            Err(e) if matches!(*e, ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("s3://{}/{} at {}", self.bucket, self.prefix, self.endpoint)
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// RFC 3986 encoding of everything except unreserved characters, as SigV4
+// requires for each path segment.
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn storage_error(e: Box<ureq::Error>) -> ArchiverError {
+    match *e {
+        ureq::Error::Status(404, response) => ArchiverError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} not found", response.get_url()),
+        )),
+        e => ArchiverError::Storage(e.to_string()),
+    }
+}
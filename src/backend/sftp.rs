@@ -0,0 +1,350 @@
+use percent_encoding::percent_decode_str;
+use ssh2::{ErrorCode, FileStat, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+use std::env;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::StorageBackend;
+use crate::error::ArchiverError;
+
+// LIBSSH2_FX_NO_SUCH_FILE, the SFTP status of a path that is not there
+const SFTP_NO_SUCH_FILE: i32 = 2;
+
+/// Objects stored as files below a directory on an SFTP server.
+///
+/// An SFTP channel handles one request at a time, so the backend keeps a
+/// connection per concurrent caller: idle connections are reused and another
+/// one is opened whenever all of them are busy.
+pub struct SftpBackend {
+    host: String,
+    port: u16,
+    user: String,
+    idle: Mutex<Vec<Connection>>,
+    location: String,
+    root: PathBuf,
+}
+
+struct Connection {
+    sftp: Sftp,
+    // The session must outlive the SFTP channel opened on it.
+    _session: Session,
+}
+
+impl SftpBackend {
+    pub fn from_url(url: &url::Url) -> Result<Self, ArchiverError> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| ArchiverError::InvalidInput(format!("No host in {}", url)))?;
+        let port = url.port().unwrap_or(22);
+        let user = if url.username().is_empty() {
+            env::var("USER").unwrap_or_default()
+        } else {
+            url.username().to_string()
+        };
+
+        let root = percent_decode_str(url.path())
+            .decode_utf8_lossy()
+            .into_owned();
+
+        let backend = SftpBackend {
+            host: host.to_string(),
+            port,
+            user: user.clone(),
+            idle: Mutex::new(Vec::new()),
+            location: format!("sftp://{}@{}:{}{}", user, host, port, root),
+            root: PathBuf::from(root),
+        };
+        // Connect right away so a wrong host or credentials fail early
+        let connection = backend.connect()?;
+        backend.idle.lock().unwrap().push(connection);
+        Ok(backend)
+    }
+
+    fn connect(&self) -> Result<Connection, ArchiverError> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))?;
+        let mut session = Session::new().map_err(ssh_error)?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(ssh_error)?;
+
+        if let Ok(identity) = env::var("SFTP_IDENTITY_FILE") {
+            session
+                .userauth_pubkey_file(&self.user, None, Path::new(&identity), None)
+                .map_err(ssh_error)?;
+        } else if let Ok(password) = env::var("SFTP_PASSWORD") {
+            session
+                .userauth_password(&self.user, &password)
+                .map_err(ssh_error)?;
+        } else {
+            session.userauth_agent(&self.user).map_err(ssh_error)?;
+        }
+
+        let sftp = session.sftp().map_err(ssh_error)?;
+        Ok(Connection {
+            sftp,
+            _session: session,
+        })
+    }
+
+    /// Run `request` on an idle connection, or a new one if none is idle.
+    fn with_sftp<T>(
+        &self,
+        request: impl FnOnce(&Sftp) -> Result<T, ArchiverError>,
+    ) -> Result<T, ArchiverError> {
+        let idle = self.idle.lock().unwrap().pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => self.connect()?,
+        };
+        let result = request(&connection.sftp);
+        // A failed request may have been a dropped connection; only keep
+        // connections that still answer
+        if result.is_ok() || connection.sftp.stat(&self.root).is_ok() {
+            self.idle.lock().unwrap().push(connection);
+        }
+        result
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+/// Look a path up, with `None` only when the server says it is not there.
+/// Any other failure, such as a dropped connection or a permission problem,
+/// is an error: taking it for a missing object could have a repository
+/// initialised again over the real one.
+fn stat(sftp: &Sftp, path: &Path) -> Result<Option<FileStat>, ArchiverError> {
+    match sftp.stat(path) {
+        Ok(stat) => Ok(Some(stat)),
+        Err(e) if is_no_such_file(&e) => Ok(None),
+        Err(e) => Err(ssh_error(e)),
+    }
+}
+
+fn is_no_such_file(e: &ssh2::Error) -> bool {
+    e.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE)
+}
+
+fn create_dir_all(sftp: &Sftp, dir: &Path) -> Result<(), ArchiverError> {
+    if dir.as_os_str().is_empty() || stat(sftp, dir)?.is_some() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dir_all(sftp, parent)?;
+    }
+    sftp.mkdir(dir, 0o755).map_err(ssh_error)
+}
+
+impl StorageBackend for SftpBackend {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        let path = self.path(key);
+        // Write to a temporary name first so a dropped connection never leaves
+        // a truncated object under its final name. The name is unique to this
+        // write, as two threads or processes may store the same chunk at once.
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let tmp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        self.with_sftp(|sftp| {
+            create_dir_all(sftp, path.parent().unwrap())?;
+            let mut tmp = sftp
+                .open_mode(
+                    &tmp_path,
+                    OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                    0o644,
+                    OpenType::File,
+                )
+                .map_err(ssh_error)?;
+            tmp.write_all(data)?;
+            tmp.fsync().map_err(ssh_error)?;
+            drop(tmp);
+
+            rename(sftp, &tmp_path, &path).inspect_err(|_| {
+                let _ = sftp.unlink(&tmp_path);
+            })
+        })
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ArchiverError> {
+        self.with_sftp(|sftp| {
+            let mut file = sftp.open(self.path(key)).map_err(ssh_error)?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, ArchiverError> {
+        self.with_sftp(|sftp| Ok(stat(sftp, &self.path(key))?.is_some()))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ArchiverError> {
+        let path = self.path(key);
+        self.with_sftp(|sftp| match sftp.unlink(&path) {
+            Err(e) if !is_no_such_file(&e) => Err(ssh_error(e)),
+            _ => Ok(()),
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), ArchiverError> {
+        let (from, to) = (self.path(from), self.path(to));
+        self.with_sftp(|sftp| {
+            create_dir_all(sftp, to.parent().unwrap())?;
+            rename(sftp, &from, &to)
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.location.clone()
+    }
+}
+
+fn rename(sftp: &Sftp, from: &Path, to: &Path) -> Result<(), ArchiverError> {
+    sftp.rename(
+        from,
+        to,
+        Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE),
+    )
+    .map_err(ssh_error)
+}
+
+fn ssh_error(e: ssh2::Error) -> ArchiverError {
+    ArchiverError::Storage(e.to_string())
+}
+
+// These run against a real server, e.g. a local OpenSSH or an
+// atmoz/sftp container, named by SFTP_TEST_URL such as
+// `sftp://foo@localhost:2222/upload/tsk-test` (with SFTP_PASSWORD or
+// SFTP_IDENTITY_FILE as for any other use). Without it they do nothing.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn test_backend() -> Option<SftpBackend> {
+        let Ok(url) = env::var("SFTP_TEST_URL") else {
+            eprintln!("SFTP_TEST_URL not set, skipping");
+            return None;
+        };
+        let url = url::Url::parse(&url).unwrap();
+        Some(SftpBackend::from_url(&url).unwrap())
+    }
+
+    #[test]
+    fn only_a_missing_path_reads_as_absent() {
+        let missing = ssh2::Error::new(ErrorCode::SFTP(SFTP_NO_SUCH_FILE), "no such file");
+        assert!(is_no_such_file(&missing));
+        // LIBSSH2_FX_PERMISSION_DENIED and a session timeout
+        let denied = ssh2::Error::new(ErrorCode::SFTP(3), "permission denied");
+        let timeout = ssh2::Error::new(ErrorCode::Session(-9), "timed out");
+        assert!(!is_no_such_file(&denied));
+        assert!(!is_no_such_file(&timeout));
+    }
+
+    #[test]
+    fn an_unreadable_directory_is_not_an_absent_object() {
+        let Some(backend) = test_backend() else {
+            return;
+        };
+        let dir = format!("unreadable-{}", process::id());
+        let key = format!("{}/object", dir);
+        backend.put(&key, b"contents").unwrap();
+        backend
+            .with_sftp(|sftp| {
+                sftp.setstat(
+                    &backend.path(&dir),
+                    FileStat {
+                        size: None,
+                        uid: None,
+                        gid: None,
+                        perm: Some(0o000),
+                        atime: None,
+                        mtime: None,
+                    },
+                )
+                .map_err(ssh_error)
+            })
+            .unwrap();
+
+        let exists = backend.exists(&key);
+        backend
+            .with_sftp(|sftp| {
+                sftp.setstat(
+                    &backend.path(&dir),
+                    FileStat {
+                        size: None,
+                        uid: None,
+                        gid: None,
+                        perm: Some(0o755),
+                        atime: None,
+                        mtime: None,
+                    },
+                )
+                .map_err(ssh_error)
+            })
+            .unwrap();
+        assert!(exists.is_err(), "{:?}", exists);
+        assert!(!backend.exists(&format!("{}/missing", dir)).unwrap());
+        backend.delete(&key).unwrap();
+    }
+
+    #[test]
+    fn objects_round_trip() {
+        let Some(backend) = test_backend() else {
+            return;
+        };
+        let key = format!("round-trip-{}/object", process::id());
+        assert!(!backend.exists(&key).unwrap());
+        backend.put(&key, b"contents").unwrap();
+        assert!(backend.exists(&key).unwrap());
+        assert_eq!(backend.get(&key).unwrap(), b"contents");
+
+        let moved = format!("round-trip-{}/moved/object", process::id());
+        backend.rename(&key, &moved).unwrap();
+        assert!(!backend.exists(&key).unwrap());
+        assert_eq!(backend.get(&moved).unwrap(), b"contents");
+
+        backend.delete(&moved).unwrap();
+        backend.delete(&moved).unwrap();
+        assert!(!backend.exists(&moved).unwrap());
+    }
+
+    #[test]
+    fn concurrent_writers_do_not_clobber_each_other() {
+        let Some(backend) = test_backend() else {
+            return;
+        };
+        let backend = Arc::new(backend);
+        let dir = format!("concurrent-{}", process::id());
+        let key = format!("{}/object", dir);
+        let object = vec![7u8; 256 * 1024];
+
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let backend = Arc::clone(&backend);
+                let (key, object) = (key.clone(), object.clone());
+                thread::spawn(move || backend.put(&key, &object))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+
+        assert_eq!(backend.get(&key).unwrap(), object);
+        // Every writer used its own temporary file and renamed it away
+        let entries = backend
+            .with_sftp(|sftp| sftp.readdir(backend.path(&dir)).map_err(ssh_error))
+            .unwrap();
+        assert_eq!(entries.len(), 1, "{:?}", entries);
+        assert!(backend.idle.lock().unwrap().len() > 1);
+        backend.delete(&key).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use rs_timeskip_archiver::config::{self, Config};
+#[cfg(unix)]
+use rs_timeskip_archiver::daemon::client::DaemonClient;
+use rs_timeskip_archiver::daemon::{http, socket_path, Daemon};
+use rs_timeskip_archiver::establish_connection;
+use std::env;
+use std::sync::Arc;
+use std::thread;
+
+fn main() {
+    let args = match Config::parse_flags(env::args().collect()) {
+        Ok((overrides, args)) => {
+            config::set_overrides(overrides);
+            args
+        }
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    #[cfg(unix)]
+    if args.len() > 1 && args[1] == "stop" {
+        match DaemonClient::connect_to(&socket_path()).and_then(|mut client| client.shutdown()) {
+            Ok(()) => println!("archiverd is stopping once running jobs finish."),
+            Err(e) => println!("Failed to stop archiverd: {}", e),
+        }
+        return;
+    }
+
+    // ARCHIVERD_WORKERS sizes the daemon apart from the other programs
+    let workers = env::var("ARCHIVERD_WORKERS")
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or_else(|| config::settings().workers);
+    let daemon = Daemon::new(establish_connection(), workers);
+
+    let http_server = env::var("ARCHIVERD_HTTP").ok().map(|addr| {
+        let token = env::var("ARCHIVERD_HTTP_TOKEN").unwrap_or_else(|_| {
+            let token = http::generate_token();
+            println!("ARCHIVERD_HTTP_TOKEN is not set, using token {}", token);
+            token
+        });
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            if let Err(e) = http::serve(&daemon, &addr, &token) {
+                println!("HTTP API failed: {}", e);
+            }
+        })
+    });
+
+    #[cfg(unix)]
+    if let Err(e) = rs_timeskip_archiver::daemon::socket::serve(&daemon, &socket_path()) {
+        println!("archiverd failed: {}", e);
+        std::process::exit(1);
+    }
+    #[cfg(not(unix))]
+    if let Some(http_server) = http_server.as_ref() {
+        // Without a control socket the HTTP API is the only way in
+        while !http_server.is_finished() {
+            thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    println!("Waiting for running jobs to finish...");
+    daemon.wait_for_jobs();
+    if let Some(http_server) = http_server {
+        let _ = http_server.join();
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Read};
+
+/// Chunks are never cut before this many bytes (except at end of input).
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+/// The size chunks are normalized towards.
+pub const AVG_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks are always cut at this many bytes.
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+// FastCDC normalized chunking: a stricter mask before the average size and a
+// looser one after it pulls the chunk size distribution towards the average.
+// The gear hash shifts left, so its top bits cover the most recent 64 bytes.
+const MASK_STRICT: u64 = !(u64::MAX >> 18);
+const MASK_LOOSE: u64 = !(u64::MAX >> 14);
+
+// The gear table is fixed: changing it moves every chunk boundary, which costs
+// deduplication against existing archives but never correctness.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x7469_6d65_736b_6970;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Find the length of the first chunk in `data`.
+pub fn cut_point(data: &[u8]) -> usize {
+    let len = data.len();
+    if len <= MIN_CHUNK_SIZE {
+        return len;
+    }
+
+    let normal = AVG_CHUNK_SIZE.min(len);
+    let max = MAX_CHUNK_SIZE.min(len);
+    let mut hash: u64 = 0;
+    let mut i = MIN_CHUNK_SIZE;
+
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_STRICT == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < max {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_LOOSE == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    max
+}
+
+/// Splits a reader into content-defined chunks.
+///
+/// Boundaries depend only on the bytes around them, so an insertion or
+/// deletion in a large file only changes the chunks next to the edit.
+pub struct Chunker<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Chunker {
+            reader,
+            buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+            eof: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        // Grow the buffer once and read into it until full, rather than
+        // zeroing the free space again after every short read.
+        let mut filled = self.buf.len();
+        self.buf.resize(MAX_CHUNK_SIZE, 0);
+        let mut result = Ok(());
+        while !self.eof && filled < MAX_CHUNK_SIZE {
+            match self.reader.read(&mut self.buf[filled..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.buf.truncate(filled);
+        result
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let cut = cut_point(&self.buf);
+        let rest = self.buf.split_off(cut);
+        let chunk = std::mem::replace(&mut self.buf, rest);
+        Some(Ok(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic bytes without long repeats, so boundaries land where the
+    // content puts them rather than at MAX_CHUNK_SIZE.
+    fn sample(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::new(data).collect::<io::Result<_>>().unwrap()
+    }
+
+    // Hands out at most a few bytes per read, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn empty_input_has_no_chunks() {
+        assert!(chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn small_input_is_one_chunk() {
+        let data = sample(MIN_CHUNK_SIZE, 1);
+        assert_eq!(chunks(&data), vec![data]);
+    }
+
+    #[test]
+    fn chunks_cover_input_within_size_limits() {
+        let data = sample(4 * 1024 * 1024, 2);
+        let chunks = chunks(&data);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert!(
+                chunk.len() > MIN_CHUNK_SIZE,
+                "chunk of {} bytes",
+                chunk.len()
+            );
+            assert!(
+                chunk.len() <= MAX_CHUNK_SIZE,
+                "chunk of {} bytes",
+                chunk.len()
+            );
+        }
+        assert!(!last.is_empty() && last.len() <= MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn uniform_input_is_cut_at_max_size() {
+        let data = vec![0u8; 3 * MAX_CHUNK_SIZE];
+        let sizes: Vec<usize> = chunks(&data).iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![MAX_CHUNK_SIZE; 3]);
+    }
+
+    #[test]
+    fn boundaries_do_not_depend_on_read_sizes() {
+        let data = sample(1024 * 1024, 3);
+        let trickled: Vec<Vec<u8>> = Chunker::new(Trickle(&data))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(trickled, chunks(&data));
+    }
+
+    #[test]
+    fn insertion_only_changes_nearby_chunks() {
+        let data = sample(4 * 1024 * 1024, 4);
+        let mut edited = data.clone();
+        edited.splice(100_000..100_000, b"inserted bytes".iter().copied());
+
+        let before = chunks(&data);
+        let after = chunks(&edited);
+        let shared = after.iter().filter(|chunk| before.contains(chunk)).count();
+        // Only the chunk holding the edit, and perhaps its neighbour, differ
+        assert!(
+            shared + 2 >= after.len(),
+            "{} of {} chunks shared",
+            shared,
+            after.len()
+        );
+        assert_eq!(before.last(), after.last());
+    }
+
+    #[test]
+    fn cut_point_is_deterministic() {
+        let data = sample(MAX_CHUNK_SIZE, 5);
+        let cut = cut_point(&data);
+        assert!(cut > MIN_CHUNK_SIZE && cut <= MAX_CHUNK_SIZE);
+        assert_eq!(cut_point(&data[..]), cut);
+        assert_eq!(cut_point(&data[..cut]), cut);
+    }
+}
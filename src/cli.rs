@@ -1,16 +1,47 @@
-use dialoguer::{theme::ColorfulTheme, Input, Select};
-use rs_timeskip_archiver::{add_file, create_profile, get_files, get_profiles};
+use console::{truncate_str, Term};
+use dialoguer::{theme::ColorfulTheme, Input, Password, Select};
+use rs_timeskip_archiver::config::{self, config_path, load_file, lookup, save_file, Source, KEYS};
+use rs_timeskip_archiver::db::DbPool;
+use rs_timeskip_archiver::error::ArchiverError;
+use rs_timeskip_archiver::jobs::{
+    cancel_job, create_ingest_job, get_job_items, interrupted_jobs, list_jobs, run_job, JobSummary,
+    ITEM_FAILED,
+};
+use rs_timeskip_archiver::maintenance::{
+    backup_catalog, check_catalog, repair_catalog, CatalogReport, RepairReport,
+};
+use rs_timeskip_archiver::models::NewScrubSchedule;
+use rs_timeskip_archiver::models::Profile;
+use rs_timeskip_archiver::parity::DEFAULT_REDUNDANCY_PERCENT;
+use rs_timeskip_archiver::progress::Progress;
+use rs_timeskip_archiver::replication::{compare_replicas, replicate_profile, Replica};
+use rs_timeskip_archiver::scrub::{
+    get_scrub_schedule, remove_scrub_schedule, run_scrub, set_scrub_schedule,
+};
+use rs_timeskip_archiver::{
+    copy_catalog, create_profile, create_recovery_data, establish_connection,
+    establish_connection_to, get_files, get_legal_hold, get_profiles, has_recovery_data,
+    place_legal_hold, release_legal_hold, remove_file, repair_file, restore_file,
+    rotate_repository_key, set_profile_storage, set_repository_compression_level, verify_profile,
+};
 use tabled::{builder::Builder, settings::Style};
 
-use diesel::prelude::*;
+#[cfg(unix)]
+use rs_timeskip_archiver::daemon::client::DaemonClient;
 use std::fs::File;
 use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
 
-pub fn run_cli(connection: SqliteConnection) {
-    let connection = Arc::new(Mutex::new(connection));
+pub fn run_cli(connection: DbPool) {
+    offer_to_resume_jobs(connection.clone());
     loop {
-        let mainmenu = &["Create Profile", "Select Profile", "Exit"];
+        let mainmenu = &[
+            "Create Profile",
+            "Select Profile",
+            "Repository",
+            "Background jobs",
+            "Exit",
+        ];
 
         let selection_mainmenu = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Welcome, Please select from the following options:")
@@ -20,23 +51,18 @@ pub fn run_cli(connection: SqliteConnection) {
             .unwrap();
 
         if selection_mainmenu == 0 {
-            loop {
-                let input: String = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Please type your profile name. (Type 'Back' to go back)")
-                    .interact_text()
-                    .unwrap();
-
-                if input.to_lowercase() == "back" {
-                    break;
-                }
+            let input: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Please type your profile name. (Type 'Back' to go back)")
+                .interact_text()
+                .unwrap();
 
-                let conn = Arc::clone(&connection);
-                let _ = create_profile(conn, &input);
-                break;
+            if input.to_lowercase() != "back" {
+                let conn = connection.clone();
+                create_profile(conn, &input);
             }
         } else if selection_mainmenu == 1 {
             loop {
-                let conn = Arc::clone(&connection);
+                let conn = connection.clone();
                 let profiles_response = get_profiles(conn);
                 let mut profiles: Vec<String> = profiles_response
                     .iter()
@@ -53,7 +79,7 @@ pub fn run_cli(connection: SqliteConnection) {
                 let selection_profile = Select::with_theme(&ColorfulTheme::default())
                     .with_prompt("Please select your profile.")
                     .default(0)
-                    .items(&profiles_list[..])
+                    .items(profiles_list)
                     .interact()
                     .unwrap();
 
@@ -64,7 +90,28 @@ pub fn run_cli(connection: SqliteConnection) {
                 let selected_profile = &profiles_response[selection_profile];
 
                 loop {
-                    let profile_menu = &["Display all files", "Enter a new file path", "Back"];
+                    let conn = connection.clone();
+                    if let Some(hold) = get_legal_hold(conn, &selected_profile.id) {
+                        println!(
+                            "Profile '{}' is under legal hold, set by {} at {}: {}",
+                            selected_profile.profile_name,
+                            hold.held_by,
+                            hold.created_at,
+                            hold.hold_reason
+                        );
+                    }
+
+                    let profile_menu = &[
+                        "Display all files",
+                        "Enter a new file path",
+                        "Remove a file",
+                        "Restore a file",
+                        "Legal hold",
+                        "Storage backend",
+                        "Replication",
+                        "Integrity",
+                        "Back",
+                    ];
 
                     let selection_profile_menu = Select::with_theme(&ColorfulTheme::default())
                         .with_prompt("Please select an option:")
@@ -87,7 +134,7 @@ pub fn run_cli(connection: SqliteConnection) {
 
                         if selection_files_display_choice_menu == 0 {
                             println!("Files in profile '{}':", selected_profile.profile_name);
-                            let conn = Arc::clone(&connection);
+                            let conn = connection.clone();
                             let files_display = get_files(conn, &selected_profile.id);
                             let mut builder = Builder::new();
 
@@ -113,7 +160,7 @@ pub fn run_cli(connection: SqliteConnection) {
                             println!("{}", table);
                         } else if selection_files_display_choice_menu == 1 {
                             println!("Files in profile '{}':", selected_profile.profile_name);
-                            let conn = Arc::clone(&connection);
+                            let conn = connection.clone();
                             let files_display = get_files(conn, &selected_profile.id);
                             let mut builder = Builder::new();
 
@@ -144,21 +191,84 @@ pub fn run_cli(connection: SqliteConnection) {
                             break;
                         }
 
-                        let (tx, _rx) = futures::channel::mpsc::channel::<(usize, usize)>(1);
-                        let mut tx_clone = tx.clone();
-                        let conn = Arc::clone(&connection);
-                        match add_file(
-                            conn,
-                            filepath_input,
-                            &selected_profile.id,
-                            &mut tx_clone,
-                            1,
-                            1,
-                        ) {
-                            Ok(file_find_response) => println!("{}", file_find_response),
+                        if add_file_via_daemon(selected_profile.id, &filepath_input) {
+                            continue;
+                        }
+
+                        let conn = connection.clone();
+                        match create_ingest_job(conn, &selected_profile.id, &[filepath_input]) {
+                            Ok(job) => run_local_job(connection.clone(), job.id),
                             Err(e) => println!("Failed to add file: {}", e),
                         }
                     } else if selection_profile_menu == 2 {
+                        let Some(file) = select_file(
+                            connection.clone(),
+                            &selected_profile.id,
+                            "Please select the file to stop tracking.",
+                        ) else {
+                            continue;
+                        };
+
+                        let conn = connection.clone();
+                        match remove_file(conn, &file.id) {
+                            Ok(()) => println!("File removed."),
+                            Err(e) => println!("Failed to remove file: {}", e),
+                        }
+                    } else if selection_profile_menu == 3 {
+                        let Some(file) = select_file(
+                            connection.clone(),
+                            &selected_profile.id,
+                            "Please select the file to restore.",
+                        ) else {
+                            continue;
+                        };
+
+                        let dest_input: String = Input::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Type in the path to restore the file to.")
+                            .interact_text()
+                            .unwrap();
+
+                        let conn = connection.clone();
+                        match restore_file(conn, &file.id, Path::new(&dest_input)) {
+                            Ok(()) => println!("File restored and verified."),
+                            Err(e) => println!("Failed to restore file: {}", e),
+                        }
+                    } else if selection_profile_menu == 4 {
+                        legal_hold_menu(connection.clone(), &selected_profile.id);
+                    } else if selection_profile_menu == 5 {
+                        println!(
+                            "Profile '{}' archives to: {}",
+                            selected_profile.profile_name,
+                            selected_profile
+                                .storage_url
+                                .as_deref()
+                                .unwrap_or("the default repository")
+                        );
+
+                        let storage_input: String = Input::with_theme(&ColorfulTheme::default())
+                            .with_prompt("New storage URL (leave empty for the default repository, type 'Back' to go back)")
+                            .allow_empty(true)
+                            .interact_text()
+                            .unwrap();
+
+                        if storage_input.to_lowercase() == "back" {
+                            continue;
+                        }
+
+                        let storage_url = Some(storage_input.trim()).filter(|url| !url.is_empty());
+                        let conn = connection.clone();
+                        match set_profile_storage(conn, &selected_profile.id, storage_url) {
+                            Ok(()) => {
+                                println!("Storage backend updated.");
+                                break;
+                            }
+                            Err(e) => println!("Failed to update storage backend: {}", e),
+                        }
+                    } else if selection_profile_menu == 6 {
+                        replication_menu(connection.clone(), selected_profile);
+                    } else if selection_profile_menu == 7 {
+                        integrity_menu(connection.clone(), &selected_profile.id);
+                    } else if selection_profile_menu == 8 {
                         break;
                     } else {
                         println!("Error");
@@ -166,9 +276,996 @@ pub fn run_cli(connection: SqliteConnection) {
                 }
             }
         } else if selection_mainmenu == 2 {
+            repository_menu();
+        } else if selection_mainmenu == 3 {
+            jobs_menu(connection.clone());
+        } else if selection_mainmenu == 4 {
             break;
         } else {
             println!("Error");
         }
     }
 }
+
+fn select_file(
+    connection: DbPool,
+    profile_id: &i32,
+    prompt: &str,
+) -> Option<rs_timeskip_archiver::models::File> {
+    let mut files_response = get_files(connection, profile_id);
+    let mut file_names: Vec<String> = files_response
+        .iter()
+        .map(|file| file.file_name.clone())
+        .collect();
+    file_names.push("Back".to_string());
+
+    let selection_file = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(0)
+        .items(&file_names[..])
+        .interact()
+        .unwrap();
+
+    if selection_file == files_response.len() {
+        None
+    } else {
+        Some(files_response.swap_remove(selection_file))
+    }
+}
+
+fn legal_hold_menu(connection: DbPool, profile_id: &i32) {
+    let conn = connection.clone();
+    let hold = get_legal_hold(conn, profile_id);
+
+    match &hold {
+        Some(hold) => println!(
+            "Legal hold active since {}, set by {}. Reason: {}",
+            hold.created_at, hold.held_by, hold.hold_reason
+        ),
+        None => println!("No legal hold on this profile."),
+    }
+
+    let action = if hold.is_some() {
+        "Release legal hold"
+    } else {
+        "Place legal hold"
+    };
+    let hold_menu = &[action, "Back"];
+
+    let selection_hold_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Please select an option:")
+        .default(0)
+        .items(&hold_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_hold_menu != 0 {
+        return;
+    }
+
+    let name: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Your name")
+        .default(std::env::var("USER").unwrap_or_default())
+        .interact_text()
+        .unwrap();
+    let reason: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Reason")
+        .interact_text()
+        .unwrap();
+
+    let conn = connection.clone();
+    let result = if hold.is_some() {
+        release_legal_hold(conn, profile_id, &name, &reason)
+    } else {
+        place_legal_hold(conn, profile_id, &name, &reason)
+    };
+
+    match result {
+        Ok(hold) if hold.released_at.is_some() => println!("Legal hold released."),
+        Ok(_) => println!("Legal hold placed."),
+        Err(e) => println!("Failed to update legal hold: {}", e),
+    }
+}
+
+fn repository_menu() {
+    let repository_menu = &["Change passphrase", "Set compression level", "Back"];
+
+    let selection_repository_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Please select an option:")
+        .default(0)
+        .items(&repository_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_repository_menu == 2 {
+        return;
+    }
+
+    let storage_input: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Storage URL (leave empty for the default repository)")
+        .allow_empty(true)
+        .interact_text()
+        .unwrap();
+    let storage_url = Some(storage_input.trim()).filter(|url| !url.is_empty());
+
+    let passphrase = Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("Current passphrase")
+        .interact()
+        .unwrap();
+
+    let result = if selection_repository_menu == 0 {
+        let new_passphrase = Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("New passphrase")
+            .with_confirmation("Confirm new passphrase", "Passphrases don't match")
+            .interact()
+            .unwrap();
+        rotate_repository_key(storage_url, &passphrase, &new_passphrase)
+    } else {
+        let level: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Compression level (zstd)")
+            .interact_text()
+            .unwrap();
+        set_repository_compression_level(storage_url, &passphrase, level)
+    };
+
+    match result {
+        Ok(()) => println!("Repository updated."),
+        Err(e) => println!("Failed to update repository: {}", e),
+    }
+}
+
+fn replication_menu(connection: DbPool, profile: &Profile) {
+    let replication_menu = &[
+        "Replicate to another catalog",
+        "Check replicas agree",
+        "Back",
+    ];
+
+    let selection_replication_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Please select an option:")
+        .default(0)
+        .items(&replication_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_replication_menu == 2 {
+        return;
+    }
+
+    let replica_input: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(if selection_replication_menu == 0 {
+            "Replica database path"
+        } else {
+            "Replica database paths, separated by commas"
+        })
+        .interact_text()
+        .unwrap();
+
+    let mut replicas = vec![Replica {
+        name: String::from("this catalog"),
+        conn: connection.clone(),
+    }];
+    for database_url in replica_input.split(',').map(str::trim) {
+        match establish_connection_to(database_url) {
+            Ok(conn) => replicas.push(Replica {
+                name: database_url.to_string(),
+                conn,
+            }),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+
+    if selection_replication_menu == 0 {
+        let storage_input: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Replica storage URL (leave empty for the default repository)")
+            .allow_empty(true)
+            .interact_text()
+            .unwrap();
+        let storage_url = Some(storage_input.trim()).filter(|url| !url.is_empty());
+
+        match replicate_profile(
+            connection.clone(),
+            &profile.id,
+            replicas[1].conn.clone(),
+            storage_url,
+        ) {
+            Ok(report) => {
+                println!(
+                    "Copied {} files ({} already present, {} removals), {} chunks ({} already present).",
+                    report.files_copied,
+                    report.files_already_present,
+                    report.files_removed,
+                    report.chunks_copied,
+                    report.chunks_already_present
+                );
+                for (file_name, reason) in &report.failures {
+                    println!("Failed to replicate {}: {}", file_name, reason);
+                }
+            }
+            Err(e) => {
+                println!("Failed to replicate profile: {}", e);
+                return;
+            }
+        }
+    }
+
+    match compare_replicas(&replicas, &profile.profile_name) {
+        Ok(report) => {
+            let mut builder = Builder::new();
+            builder.set_header(["Replica", "Files", "Missing files", "Missing chunks"]);
+            for replica in &report.replicas {
+                builder.push_record([
+                    replica.name.clone(),
+                    replica
+                        .file_count
+                        .map(|count| count.to_string())
+                        .unwrap_or_else(|| String::from("no profile")),
+                    replica.missing_files.len().to_string(),
+                    replica.missing_chunks.len().to_string(),
+                ]);
+            }
+            println!("{}", builder.build().with(Style::ascii_rounded()));
+
+            if report.all_agree() {
+                println!("All replicas agree.");
+            } else {
+                println!("Replicas do NOT agree.");
+            }
+        }
+        Err(e) => println!("Failed to compare replicas: {}", e),
+    }
+}
+
+fn integrity_menu(connection: DbPool, profile_id: &i32) {
+    let integrity_menu = &[
+        "Verify all files",
+        "Create recovery data",
+        "Repair a file",
+        "Scrub schedule",
+        "Run scrub now",
+        "Back",
+    ];
+
+    let selection_integrity_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Please select an option:")
+        .default(0)
+        .items(&integrity_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_integrity_menu == 0 {
+        let results = match verify_profile(connection.clone(), profile_id) {
+            Ok(results) => results,
+            Err(e) => {
+                println!("Failed to verify files: {}", e);
+                return;
+            }
+        };
+
+        let mut builder = Builder::new();
+        builder.set_header(["File", "Status", "Recovery data"]);
+        for (file, verification) in &results {
+            let recovery = match has_recovery_data(connection.clone(), &file.id) {
+                Ok(true) => "yes".to_string(),
+                Ok(false) => "no".to_string(),
+                Err(e) => e.to_string(),
+            };
+            builder.push_record([
+                file.file_name.clone(),
+                verification
+                    .detail
+                    .clone()
+                    .unwrap_or_else(|| String::from("ok")),
+                recovery,
+            ]);
+        }
+        println!("{}", builder.build().with(Style::ascii_rounded()));
+
+        let damaged = results.iter().filter(|(_, v)| !v.ok).count();
+        println!(
+            "{} of {} files failed verification.",
+            damaged,
+            results.len()
+        );
+    } else if selection_integrity_menu == 1 {
+        let percent: u32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Redundancy percentage")
+            .default(DEFAULT_REDUNDANCY_PERCENT)
+            .interact_text()
+            .unwrap();
+
+        for file in get_files(connection.clone(), profile_id) {
+            match create_recovery_data(connection.clone(), &file.id, percent) {
+                Ok(()) => println!("Created recovery data for {}", file.file_name),
+                Err(e) => println!(
+                    "Failed to create recovery data for {}: {}",
+                    file.file_name, e
+                ),
+            }
+        }
+    } else if selection_integrity_menu == 2 {
+        let Some(file) = select_file(
+            connection.clone(),
+            profile_id,
+            "Please select the file to repair.",
+        ) else {
+            return;
+        };
+
+        match repair_file(connection, &file.id) {
+            Ok((outcome, verification)) => {
+                println!(
+                    "Rebuilt {} of {} blocks and {} chunks and restored {}.",
+                    outcome.damaged_blocks,
+                    outcome.total_blocks,
+                    outcome.repaired_chunks,
+                    file.file_name
+                );
+                match verification.detail {
+                    None => println!("The file verified after repair."),
+                    Some(detail) => println!("The file still fails verification: {}", detail),
+                }
+            }
+            Err(e) => println!("Failed to repair file: {}", e),
+        }
+    } else if selection_integrity_menu == 3 {
+        scrub_schedule_menu(connection, profile_id);
+    } else if selection_integrity_menu == 4 {
+        if run_scrub_via_daemon(*profile_id) {
+            return;
+        }
+        match run_scrub(connection, profile_id) {
+            Ok(report) => {
+                println!(
+                    "Verified {} files, {} failed.",
+                    report.files_verified,
+                    report.failures.len()
+                );
+                for (file_name, detail) in &report.failures {
+                    println!("{}: {}", file_name, detail);
+                }
+            }
+            Err(e) => println!("Failed to run scrub: {}", e),
+        }
+    }
+}
+
+fn scrub_schedule_menu(connection: DbPool, profile_id: &i32) {
+    let schedule = get_scrub_schedule(connection.clone(), profile_id);
+
+    match &schedule {
+        Some(schedule) => println!(
+            "Every {} hours, verify {}% of files (all within {} days), reading at most {}. Last run: {}",
+            schedule.interval_hours,
+            schedule.percent_per_run,
+            schedule.cycle_days,
+            if schedule.read_limit_mb > 0 {
+                format!("{} MB/s", schedule.read_limit_mb)
+            } else {
+                String::from("unlimited")
+            },
+            schedule
+                .last_run_at
+                .map(|last_run_at| last_run_at.to_string())
+                .unwrap_or_else(|| String::from("never"))
+        ),
+        None => println!("No scrub schedule on this profile."),
+    }
+
+    let schedule_menu = &["Set schedule", "Remove schedule", "Back"];
+
+    let selection_schedule_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Please select an option:")
+        .default(0)
+        .items(&schedule_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_schedule_menu == 0 {
+        let interval_hours: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Run every how many hours")
+            .default(schedule.as_ref().map_or(24, |s| s.interval_hours))
+            .interact_text()
+            .unwrap();
+        let percent_per_run: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Percentage of files per run")
+            .default(schedule.as_ref().map_or(5, |s| s.percent_per_run))
+            .interact_text()
+            .unwrap();
+        let cycle_days: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Verify every file within how many days")
+            .default(schedule.as_ref().map_or(30, |s| s.cycle_days))
+            .interact_text()
+            .unwrap();
+        let read_limit_mb: i32 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Read limit in MB/s (0 for unlimited)")
+            .default(schedule.as_ref().map_or(0, |s| s.read_limit_mb))
+            .interact_text()
+            .unwrap();
+
+        match set_scrub_schedule(
+            connection,
+            &NewScrubSchedule {
+                profile_id: *profile_id,
+                interval_hours,
+                percent_per_run,
+                cycle_days,
+                read_limit_mb,
+            },
+        ) {
+            Ok(_) => println!(
+                "Scrub schedule saved. Run `rs-timeskip-archiver scrub` to keep it running."
+            ),
+            Err(e) => println!("Failed to save scrub schedule: {}", e),
+        }
+    } else if selection_schedule_menu == 1 {
+        match remove_scrub_schedule(connection, profile_id) {
+            Ok(()) => println!("Scrub schedule removed."),
+            Err(e) => println!("Failed to remove scrub schedule: {}", e),
+        }
+    }
+}
+
+// Long operations are handed to archiverd when it is running, so they carry on
+// after the CLI exits. These return false when the work should run in-process.
+
+#[cfg(unix)]
+fn add_file_via_daemon(profile_id: i32, file_path: &str) -> bool {
+    let Ok(mut client) = DaemonClient::connect() else {
+        return false;
+    };
+    // The daemon resolves relative paths against its own directory
+    let file_path = std::fs::canonicalize(file_path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file_path.to_string());
+    match client.add_files(profile_id, vec![file_path]) {
+        Ok(job_id) => follow_daemon_job(&mut client, job_id),
+        Err(e) => println!("Failed to add file: {}", e),
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn add_file_via_daemon(_profile_id: i32, _file_path: &str) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn run_scrub_via_daemon(profile_id: i32) -> bool {
+    let Ok(mut client) = DaemonClient::connect() else {
+        return false;
+    };
+    match client.run_scrub(profile_id) {
+        Ok(job_id) => follow_daemon_job(&mut client, job_id),
+        Err(e) => println!("Failed to run scrub: {}", e),
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn run_scrub_via_daemon(_profile_id: i32) -> bool {
+    false
+}
+
+// Print a job's progress until it finishes. Interrupting the CLI leaves the
+// job running in the daemon.
+#[cfg(unix)]
+fn follow_daemon_job(client: &mut DaemonClient, job_id: i32) {
+    println!("Started background job {}.", job_id);
+    let term = Term::stdout();
+    loop {
+        match client.job_status(job_id) {
+            Ok((job, progress)) => {
+                if let Some(progress) = progress {
+                    draw_progress(&term, &progress);
+                }
+                if job.is_finished() {
+                    let _ = term.write_line("");
+                    println!(
+                        "Job {} {}: {} done, {} failed.",
+                        job_id, job.job.status, job.done, job.failed
+                    );
+                    if let Ok(items) = client.job_items(job_id) {
+                        for item in items.iter().filter(|item| item.state == ITEM_FAILED) {
+                            println!(
+                                "{}: {}",
+                                item.target,
+                                item.reason.as_deref().unwrap_or_default()
+                            );
+                        }
+                    }
+                    return;
+                }
+            }
+            Err(e) => {
+                println!("Lost track of job {}: {}", job_id, e);
+                return;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+}
+
+#[cfg(unix)]
+fn daemon_is_running() -> bool {
+    DaemonClient::connect().is_ok()
+}
+
+#[cfg(not(unix))]
+fn daemon_is_running() -> bool {
+    false
+}
+
+// Run a job in this process, printing progress and any failed items.
+fn run_local_job(connection: DbPool, job_id: i32) {
+    let term = Term::stdout();
+    let result = run_job(connection.clone(), &job_id, |progress| {
+        draw_progress(&term, progress)
+    });
+    let _ = term.write_line("");
+    match result {
+        Ok(job) => {
+            println!(
+                "Job {} {}: {} done, {} failed.",
+                job_id, job.job.status, job.done, job.failed
+            );
+            print_failed_items(connection, job_id);
+        }
+        Err(e) => println!("Job {} stopped: {}", job_id, e),
+    }
+}
+
+// Redraw a one-line progress bar in place, cut to the terminal width
+fn draw_progress(term: &Term, progress: &Progress) {
+    const BAR_WIDTH: usize = 30;
+    let filled = (progress.ratio() * BAR_WIDTH as f32) as usize;
+    let line = format!(
+        "[{}{}] {:>3}% {}",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        (progress.ratio() * 100.0) as u32,
+        progress
+    );
+    let width = term.size().1 as usize;
+    let _ = term.clear_line();
+    let _ = term.write_str(&truncate_str(&line, width.saturating_sub(1), "..."));
+}
+
+fn print_failed_items(connection: DbPool, job_id: i32) {
+    match get_job_items(connection, &job_id) {
+        Ok(items) => {
+            for item in items.iter().filter(|item| item.state == ITEM_FAILED) {
+                println!(
+                    "{}: {}",
+                    item.target,
+                    item.reason.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        Err(e) => println!("Failed to load job items: {}", e),
+    }
+}
+
+// Jobs left pending or running by a process that stopped. When archiverd is
+// running it has already picked them up.
+fn offer_to_resume_jobs(connection: DbPool) {
+    let jobs = match interrupted_jobs(connection.clone()) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            println!("Failed to load interrupted jobs: {}", e);
+            return;
+        }
+    };
+    if jobs.is_empty() || daemon_is_running() {
+        return;
+    }
+
+    let resume_menu = &["Resume them now", "Leave them for later"];
+    let selection_resume_menu = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} jobs were interrupted.", jobs.len()))
+        .default(0)
+        .items(&resume_menu[..])
+        .interact()
+        .unwrap();
+
+    if selection_resume_menu == 0 {
+        for job in jobs {
+            println!("Resuming {} job {}.", job.kind, job.id);
+            run_local_job(connection.clone(), job.id);
+        }
+    }
+}
+
+fn print_jobs(jobs: &[JobSummary]) {
+    let mut builder = Builder::new();
+    builder.set_header(["Job", "Kind", "Profile", "Progress", "Status", "Failed"]);
+    for job in jobs {
+        builder.push_record([
+            job.job.id.to_string(),
+            job.job.kind.clone(),
+            job.job.profile_id.to_string(),
+            format!("{} / {}", job.processed(), job.total),
+            job.job.status.clone(),
+            job.failed.to_string(),
+        ]);
+    }
+    println!("{}", builder.build().with(Style::ascii_rounded()));
+}
+
+fn jobs_menu(connection: DbPool) {
+    loop {
+        let jobs = match list_jobs(connection.clone(), None) {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                println!("Failed to load jobs: {}", e);
+                return;
+            }
+        };
+        print_jobs(&jobs);
+
+        let jobs_menu = &["Cancel a job", "Show failed items", "Resume a job", "Back"];
+        let selection_jobs_menu = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Please select from the following options:")
+            .default(0)
+            .items(&jobs_menu[..])
+            .interact()
+            .unwrap();
+
+        if selection_jobs_menu == 3 {
+            break;
+        }
+
+        // Only unfinished jobs can be cancelled or resumed
+        let candidates: Vec<&JobSummary> = jobs
+            .iter()
+            .filter(|job| selection_jobs_menu == 1 || !job.is_finished())
+            .collect();
+        if candidates.is_empty() {
+            println!("There are no matching jobs.");
+            continue;
+        }
+        let job_names: Vec<String> = candidates
+            .iter()
+            .map(|job| format!("{} ({} {})", job.job.id, job.job.kind, job.job.status))
+            .collect();
+        let selection_job = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Please select a job.")
+            .default(0)
+            .items(&job_names[..])
+            .interact()
+            .unwrap();
+        let job_id = candidates[selection_job].job.id;
+
+        if selection_jobs_menu == 0 {
+            match cancel_job(connection.clone(), &job_id) {
+                Ok(_) => println!("Job {} cancelled.", job_id),
+                Err(e) => println!("Failed to cancel job: {}", e),
+            }
+        } else if selection_jobs_menu == 1 {
+            print_failed_items(connection.clone(), job_id);
+        } else if selection_jobs_menu == 2 {
+            if daemon_is_running() {
+                println!("archiverd is running and resumes interrupted jobs itself.");
+            } else {
+                run_local_job(connection.clone(), job_id);
+            }
+        } else {
+            println!("Error");
+        }
+    }
+}
+
+// `config get [KEY]`, `config set KEY VALUE`, `config unset KEY` and
+// `config path`, which work without a database
+pub fn run_config_command(args: &[String]) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["path"] => {
+            println!("{}", config_path().display());
+            Ok(())
+        }
+        ["get"] => print_settings(),
+        ["get", key] => lookup(key).map(|(value, source)| match value {
+            Some(value) => println!("{} ({})", value, source.as_str()),
+            None => println!("{} is not set", key),
+        }),
+        ["set", key, value] => set_setting(key, value),
+        ["unset", key] => set_setting(key, ""),
+        _ => {
+            println!(
+                "Usage: config get [KEY] | config set KEY VALUE | config unset KEY | config path"
+            );
+            println!("Settings: {}", KEYS.join(", "));
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Run a catalog maintenance command: `db backup [DIR]`, `db check`,
+/// `db repair`, or `db copy [FROM] TO`, which copies a catalog into a new one
+/// (FROM defaults to the configured catalog). Backups, checks and repairs are
+/// done by archiverd when it is running.
+pub fn run_db_command(args: &[String]) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let database_url = config::settings().database_url;
+    let result = match args[..] {
+        ["backup"] => backup_catalog_command(None),
+        ["backup", dir] => backup_catalog_command(Some(Path::new(dir))),
+        ["check"] => check_catalog_command(),
+        ["repair"] => repair_catalog_command(),
+        ["copy", to] => copy_catalog_to(&database_url, to),
+        ["copy", from, to] => copy_catalog_to(from, to),
+        _ => {
+            println!("Usage: db backup [DIR] | db check | db repair | db copy [FROM_URL] TO_URL");
+            println!(
+                "A URL is an SQLite file, or a postgres:// URL if built with PostgreSQL support."
+            );
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// `watch` subcommands. Watching needs archiverd, which checks the folders
+/// for as long as it runs.
+pub fn run_watch_command(args: &[String]) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["list"] => list_watches_via_daemon(),
+        ["stop", profile, dir] => parse_profile_id(profile)
+            .and_then(|profile_id| unwatch_via_daemon(profile_id, Path::new(dir))),
+        [profile, dir] => parse_profile_id(profile)
+            .and_then(|profile_id| watch_via_daemon(profile_id, Path::new(dir))),
+        _ => {
+            println!("Usage: watch PROFILE_ID DIR | watch stop PROFILE_ID DIR | watch list");
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_profile_id(profile: &str) -> Result<i32, ArchiverError> {
+    profile
+        .parse()
+        .map_err(|_| ArchiverError::Daemon(format!("{} is not a profile id", profile)))
+}
+
+#[cfg(unix)]
+fn connect_for_watching() -> Result<DaemonClient, ArchiverError> {
+    DaemonClient::connect().map_err(|_| {
+        ArchiverError::Daemon("watching folders needs archiverd to be running".to_string())
+    })
+}
+
+#[cfg(unix)]
+fn watch_via_daemon(profile_id: i32, dir: &Path) -> Result<(), ArchiverError> {
+    let mut client = connect_for_watching()?;
+    // The daemon resolves relative paths against its own directory
+    client.watch(profile_id, &std::path::absolute(dir)?)?;
+    println!("archiverd now archives changes in {}.", dir.display());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn watch_via_daemon(_profile_id: i32, _dir: &Path) -> Result<(), ArchiverError> {
+    Err(ArchiverError::Daemon(
+        "watching folders needs archiverd".to_string(),
+    ))
+}
+
+#[cfg(unix)]
+fn unwatch_via_daemon(profile_id: i32, dir: &Path) -> Result<(), ArchiverError> {
+    let mut client = connect_for_watching()?;
+    client.unwatch(profile_id, &std::path::absolute(dir)?)?;
+    println!("Stopped watching {}.", dir.display());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn unwatch_via_daemon(_profile_id: i32, _dir: &Path) -> Result<(), ArchiverError> {
+    Err(ArchiverError::Daemon(
+        "watching folders needs archiverd".to_string(),
+    ))
+}
+
+#[cfg(unix)]
+fn list_watches_via_daemon() -> Result<(), ArchiverError> {
+    let mut client = connect_for_watching()?;
+    let watches = client.list_watches()?;
+    if watches.is_empty() {
+        println!("No folders are watched.");
+    }
+    for watch in watches {
+        println!("Profile {}: {}", watch.profile_id, watch.path);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn list_watches_via_daemon() -> Result<(), ArchiverError> {
+    Err(ArchiverError::Daemon(
+        "watching folders needs archiverd".to_string(),
+    ))
+}
+
+// Let a running archiverd pick up a changed setting
+#[cfg(unix)]
+fn reload_daemon_settings() {
+    if let Ok(mut client) = DaemonClient::connect() {
+        match client.reload_settings() {
+            Ok(()) => println!("archiverd uses the new settings from now on."),
+            Err(e) => println!("archiverd did not reload its settings: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn reload_daemon_settings() {}
+
+fn backup_catalog_command(dir: Option<&Path>) -> Result<(), ArchiverError> {
+    let path = match backup_catalog_via_daemon(dir) {
+        Some(path) => path?,
+        None => backup_catalog(establish_connection(), dir)?,
+    };
+    println!("Backed up the catalog to {}.", path.display());
+    Ok(())
+}
+
+fn check_catalog_command() -> Result<(), ArchiverError> {
+    let report = match check_catalog_via_daemon() {
+        Some(report) => report?,
+        None => check_catalog(establish_connection())?,
+    };
+    print_catalog_report(&report);
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn repair_catalog_command() -> Result<(), ArchiverError> {
+    let report = match repair_catalog_via_daemon() {
+        Some(report) => report?,
+        None => repair_catalog(establish_connection())?,
+    };
+    if let Some(backup) = &report.backup {
+        println!("Backed up the catalog to {} first.", backup.display());
+    }
+    println!("Repaired {} problems.", report.repaired.len());
+    print_catalog_report(&report.remaining);
+    Ok(())
+}
+
+fn print_catalog_report(report: &CatalogReport) {
+    match &report.integrity {
+        Some(_) if report.is_intact() => println!("Database file: ok"),
+        Some(messages) => {
+            println!("The database file is damaged:");
+            for message in messages {
+                println!("  {}", message);
+            }
+        }
+        None => println!("Database file: looked after by the PostgreSQL server"),
+    }
+    if report.problems.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+
+    let mut builder = Builder::new();
+    builder.set_header(["Problem", "Table", "Row", "Detail", "Repairable"]);
+    for problem in &report.problems {
+        builder.push_record([
+            problem.kind.as_str().to_string(),
+            problem.table.clone(),
+            problem.row.clone(),
+            problem.detail.clone(),
+            if problem.repairable { "yes" } else { "no" }.to_string(),
+        ]);
+    }
+    println!("{}", builder.build().with(Style::ascii_rounded()));
+    let repairable = report
+        .problems
+        .iter()
+        .filter(|problem| problem.repairable)
+        .count();
+    if repairable > 0 {
+        println!("db repair can fix {} of them.", repairable);
+    }
+}
+
+#[cfg(unix)]
+fn backup_catalog_via_daemon(dir: Option<&Path>) -> Option<Result<PathBuf, ArchiverError>> {
+    let mut client = DaemonClient::connect().ok()?;
+    // The daemon resolves relative paths against its own directory
+    let dir = match dir.map(std::path::absolute).transpose() {
+        Ok(dir) => dir,
+        Err(e) => return Some(Err(e.into())),
+    };
+    Some(client.backup_catalog(dir.as_deref()))
+}
+
+#[cfg(not(unix))]
+fn backup_catalog_via_daemon(_dir: Option<&Path>) -> Option<Result<PathBuf, ArchiverError>> {
+    None
+}
+
+#[cfg(unix)]
+fn check_catalog_via_daemon() -> Option<Result<CatalogReport, ArchiverError>> {
+    let mut client = DaemonClient::connect().ok()?;
+    Some(client.check_catalog())
+}
+
+#[cfg(not(unix))]
+fn check_catalog_via_daemon() -> Option<Result<CatalogReport, ArchiverError>> {
+    None
+}
+
+#[cfg(unix)]
+fn repair_catalog_via_daemon() -> Option<Result<RepairReport, ArchiverError>> {
+    let mut client = DaemonClient::connect().ok()?;
+    Some(client.repair_catalog())
+}
+
+#[cfg(not(unix))]
+fn repair_catalog_via_daemon() -> Option<Result<RepairReport, ArchiverError>> {
+    None
+}
+
+fn copy_catalog_to(from: &str, to: &str) -> Result<(), ArchiverError> {
+    println!("Copying {} to {}...", from, to);
+    let tables = copy_catalog(from, to)?;
+
+    let mut builder = Builder::new();
+    builder.set_header(["Table", "Rows"]);
+    for (table, rows) in tables {
+        builder.push_record([table.to_string(), rows.to_string()]);
+    }
+    println!("{}", builder.build().with(Style::ascii_rounded()));
+    println!("Set database_url to {} to use the copy.", to);
+    Ok(())
+}
+
+fn print_settings() -> Result<(), ArchiverError> {
+    let mut builder = Builder::new();
+    builder.set_header(["Setting", "Value", "From"]);
+    for key in KEYS {
+        let (value, source) = lookup(key)?;
+        builder.push_record([
+            key.to_string(),
+            value.unwrap_or_default(),
+            source.as_str().to_string(),
+        ]);
+    }
+    println!("{}", builder.build().with(Style::ascii_rounded()));
+    println!("Config file: {}", config_path().display());
+    Ok(())
+}
+
+// Change one setting in the config file, leaving the others as they are
+fn set_setting(key: &str, value: &str) -> Result<(), ArchiverError> {
+    let mut config = load_file(&config_path())?;
+    config.set(key, value)?;
+    save_file(&config)?;
+    println!("Saved {}.", config_path().display());
+    reload_daemon_settings();
+
+    let (_, source) = lookup(key)?;
+    if matches!(source, Source::Environment | Source::Flag) {
+        println!("{} is overridden by the {} for now.", key, source.as_str());
+    }
+    Ok(())
+}
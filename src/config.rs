@@ -0,0 +1,634 @@
+//! Persistent settings in a TOML file, overridden by environment variables
+//! and command line flags.
+//!
+//! The file lives at `$XDG_CONFIG_HOME/rs-timeskip-archiver/config.toml`
+//! (`~/.config/...` when that is not set), or wherever `ARCHIVE_CONFIG` or
+//! `--config` point. A value set in the environment wins over the file, and
+//! a flag wins over both. The settings are worked out once and kept until
+//! [`reload`] is called or the file is saved.
+
+use dotenvy::dotenv;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use crate::digest::HashAlgorithm;
+use crate::error::ArchiverError;
+
+/// Worker threads for jobs when no setting gives a number.
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// Every setting, by its name in the file.
+pub const KEYS: [&str; 6] = [
+    "database_url",
+    "workers",
+    "hash_algorithm",
+    "exclude",
+    "repository",
+    "theme",
+];
+
+/// One layer of settings: the file, the environment or the command line.
+/// Unset values fall through to the layer below.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<String>,
+    /// Glob patterns for files and folders left out of ingestion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+}
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Environment,
+    Flag,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "config file",
+            Source::Environment => "environment",
+            Source::Flag => "command line",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UiTheme {
+    Light,
+    Dark,
+}
+
+impl UiTheme {
+    pub const ALL: [UiTheme; 2] = [UiTheme::Light, UiTheme::Dark];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UiTheme::Light => "light",
+            UiTheme::Dark => "dark",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(UiTheme::Light),
+            "dark" => Some(UiTheme::Dark),
+            _ => None,
+        }
+    }
+}
+
+/// The settings in effect, with every layer applied.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub database_url: String,
+    pub workers: usize,
+    pub hash_algorithm: HashAlgorithm,
+    pub exclude: Vec<glob::Pattern>,
+    pub repository: PathBuf,
+    pub theme: UiTheme,
+}
+
+/// What the command line sets: another config file, and values that win over
+/// the file and the environment.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub config_path: Option<PathBuf>,
+    pub values: Config,
+}
+
+fn overrides() -> &'static RwLock<Overrides> {
+    static OVERRIDES: OnceLock<RwLock<Overrides>> = OnceLock::new();
+    OVERRIDES.get_or_init(Default::default)
+}
+
+// The file's contents, read once and replaced when saved
+fn cached_file() -> &'static RwLock<Option<Config>> {
+    static FILE: OnceLock<RwLock<Option<Config>>> = OnceLock::new();
+    FILE.get_or_init(Default::default)
+}
+
+// The settings in effect, worked out from every layer when first needed
+fn cached_settings() -> &'static RwLock<Option<Settings>> {
+    static SETTINGS: OnceLock<RwLock<Option<Settings>>> = OnceLock::new();
+    SETTINGS.get_or_init(Default::default)
+}
+
+impl Config {
+    /// Parse and check `value` as the setting `key`. An empty value unsets it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ArchiverError> {
+        let value = value.trim();
+        let text = (!value.is_empty()).then(|| value.to_string());
+        match key {
+            "database_url" => self.database_url = text,
+            "workers" => {
+                self.workers = text
+                    .map(|text| match text.parse::<usize>() {
+                        Ok(workers) if workers > 0 => Ok(workers),
+                        _ => Err(invalid(key, &text, "a number above zero")),
+                    })
+                    .transpose()?
+            }
+            "hash_algorithm" => {
+                if let Some(name) = &text {
+                    HashAlgorithm::parse(name)
+                        .ok_or_else(|| invalid(key, name, "sha3-256 or blake3"))?;
+                }
+                self.hash_algorithm = text
+            }
+            "exclude" => {
+                let patterns: Vec<String> = value
+                    .split(',')
+                    .map(|pattern| pattern.trim().to_string())
+                    .filter(|pattern| !pattern.is_empty())
+                    .collect();
+                for pattern in &patterns {
+                    glob::Pattern::new(pattern)
+                        .map_err(|e| invalid(key, pattern, &format!("a glob pattern ({})", e)))?;
+                }
+                self.exclude = (!patterns.is_empty()).then_some(patterns)
+            }
+            "repository" => self.repository = text,
+            "theme" => {
+                if let Some(name) = &text {
+                    UiTheme::parse(name).ok_or_else(|| invalid(key, name, "light or dark"))?;
+                }
+                self.theme = text
+            }
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// The setting `key` as text, if this layer sets it.
+    pub fn get(&self, key: &str) -> Result<Option<String>, ArchiverError> {
+        Ok(match key {
+            "database_url" => self.database_url.clone(),
+            "workers" => self.workers.map(|workers| workers.to_string()),
+            "hash_algorithm" => self.hash_algorithm.clone(),
+            "exclude" => self.exclude.as_ref().map(|patterns| patterns.join(",")),
+            "repository" => self.repository.clone(),
+            "theme" => self.theme.clone(),
+            _ => return Err(unknown_key(key)),
+        })
+    }
+
+    /// The values set in the environment.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        let mut config = Config::default();
+        for (key, var) in KEYS.iter().zip(env_vars()) {
+            if let Ok(value) = env::var(var) {
+                if let Err(e) = config.set(key, &value) {
+                    println!("Ignoring {}: {}", var, e);
+                }
+            }
+        }
+        config
+    }
+
+    /// Take flags of the form `--<key> <value>`, with dashes for underscores,
+    /// and `--config <path>` out of `args`, returning the remaining arguments.
+    pub fn parse_flags(args: Vec<String>) -> Result<(Overrides, Vec<String>), ArchiverError> {
+        let mut overrides = Overrides::default();
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                rest.push(arg);
+                continue;
+            };
+            let key = flag.replace('-', "_");
+            if key != "config" && !KEYS.contains(&key.as_str()) {
+                rest.push(arg);
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| ArchiverError::InvalidInput(format!("{} needs a value", arg)))?;
+            if key == "config" {
+                overrides.config_path = Some(PathBuf::from(value));
+            } else if key == "exclude" {
+                // Repeated --exclude flags add up
+                let mut patterns = overrides.values.exclude.take().unwrap_or_default();
+                let mut single = Config::default();
+                single.set("exclude", &value)?;
+                patterns.extend(single.exclude.unwrap_or_default());
+                overrides.values.exclude = Some(patterns);
+            } else {
+                overrides.values.set(&key, &value)?;
+            }
+        }
+        Ok((overrides, rest))
+    }
+}
+
+fn invalid(key: &str, value: &str, expected: &str) -> ArchiverError {
+    ArchiverError::InvalidInput(format!("{} must be {}, not {}", key, expected, value))
+}
+
+fn unknown_key(key: &str) -> ArchiverError {
+    ArchiverError::InvalidInput(format!(
+        "unknown setting {} (known: {})",
+        key,
+        KEYS.join(", ")
+    ))
+}
+
+/// The environment variable overriding each of [`KEYS`], in the same order.
+pub fn env_vars() -> [&'static str; 6] {
+    [
+        "DATABASE_URL",
+        "ARCHIVE_WORKERS",
+        "ARCHIVE_HASH_ALGORITHM",
+        "ARCHIVE_EXCLUDE",
+        "ARCHIVE_REPOSITORY",
+        "ARCHIVE_THEME",
+    ]
+}
+
+/// Use the config file and values given on the command line for the rest of
+/// the process.
+pub fn set_overrides(new: Overrides) {
+    *overrides().write().unwrap() = new;
+    reload();
+}
+
+/// Read the config file and the environment again when the settings are next
+/// needed, e.g. after another process changed the file.
+pub fn reload() {
+    *cached_file().write().unwrap() = None;
+    *cached_settings().write().unwrap() = None;
+}
+
+/// Where the config file is read from and saved to.
+pub fn config_path() -> PathBuf {
+    if let Some(path) = &overrides().read().unwrap().config_path {
+        return path.clone();
+    }
+    dotenv().ok();
+    if let Ok(path) = env::var("ARCHIVE_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env::var("HOME").unwrap_or_default()).join(".config"));
+    config_home.join("rs-timeskip-archiver").join("config.toml")
+}
+
+// `$XDG_DATA_HOME/rs-timeskip-archiver`, with `~/.local/share` when that is
+// not set
+fn data_dir() -> PathBuf {
+    dotenv().ok();
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env::var("HOME").unwrap_or_default())
+                .join(".local")
+                .join("share")
+        });
+    data_home.join("rs-timeskip-archiver")
+}
+
+/// The database used when none is configured:
+/// `$XDG_DATA_HOME/rs-timeskip-archiver/archive.db`, with `~/.local/share`
+/// when that is not set.
+pub fn default_database_path() -> PathBuf {
+    data_dir().join("archive.db")
+}
+
+/// The repository used when none is configured, next to the default
+/// database: `$XDG_DATA_HOME/rs-timeskip-archiver/archive`.
+pub fn default_repository_path() -> PathBuf {
+    data_dir().join("archive")
+}
+
+/// Read a config file. A missing file is an empty one.
+pub fn load_file(path: &Path) -> Result<Config, ArchiverError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let config: Config = toml::from_str(&text)
+        .map_err(|e| ArchiverError::InvalidInput(format!("{}: {}", path.display(), e)))?;
+    // Check the values the same way `config set` does
+    let mut checked = Config::default();
+    for key in KEYS {
+        if let Some(value) = config.get(key)? {
+            checked
+                .set(key, &value)
+                .map_err(|e| ArchiverError::InvalidInput(format!("{}: {}", path.display(), e)))?;
+        }
+    }
+    Ok(config)
+}
+
+/// Write `config` to the config file, creating its directory if needed. A
+/// relative repository path is saved as the absolute path it means here, so
+/// every process finds the same repository wherever it is started.
+pub fn save_file(config: &Config) -> Result<(), ArchiverError> {
+    let mut config = config.clone();
+    if let Some(repository) = &config.repository {
+        config.repository = Some(std::path::absolute(repository)?.display().to_string());
+    }
+    let path = config_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text = toml::to_string_pretty(&config)
+        .map_err(|e| ArchiverError::InvalidInput(format!("cannot write settings: {}", e)))?;
+    fs::write(&path, text)?;
+    *cached_file().write().unwrap() = Some(config);
+    *cached_settings().write().unwrap() = None;
+    Ok(())
+}
+
+/// The config file's values. A file that cannot be read is reported once and
+/// treated as empty.
+pub fn file_config() -> Config {
+    if let Some(config) = cached_file().read().unwrap().as_ref() {
+        return config.clone();
+    }
+    let path = config_path();
+    let config = load_file(&path).unwrap_or_else(|e| {
+        println!("Ignoring config file: {}", e);
+        Config::default()
+    });
+    *cached_file().write().unwrap() = Some(config.clone());
+    config
+}
+
+// Every layer, the one that wins first
+fn layers() -> [(Config, Source); 3] {
+    [
+        (overrides().read().unwrap().values.clone(), Source::Flag),
+        (Config::from_env(), Source::Environment),
+        (file_config(), Source::File),
+    ]
+}
+
+fn lookup_in(
+    layers: &[(Config, Source)],
+    key: &str,
+) -> Result<(Option<String>, Source), ArchiverError> {
+    for (config, source) in layers {
+        if let Some(value) = config.get(key)? {
+            return Ok((Some(value), *source));
+        }
+    }
+    let default = match key {
+        "database_url" => Some(default_database_path().display().to_string()),
+        "workers" => Some(DEFAULT_WORKERS.to_string()),
+        "hash_algorithm" => Some(HashAlgorithm::Blake3.as_str().to_string()),
+        "repository" => Some(default_repository_path().display().to_string()),
+        "theme" => Some(UiTheme::Light.as_str().to_string()),
+        _ => None,
+    };
+    Ok((default, Source::Default))
+}
+
+/// The value of `key` in effect and where it came from, or `None` for an
+/// exclude list that is not set.
+pub fn lookup(key: &str) -> Result<(Option<String>, Source), ArchiverError> {
+    lookup_in(&layers(), key)
+}
+
+/// The settings in effect, as they were when first needed or last reloaded.
+pub fn settings() -> Settings {
+    if let Some(settings) = cached_settings().read().unwrap().as_ref() {
+        return settings.clone();
+    }
+    let settings = settings_from(&layers());
+    *cached_settings().write().unwrap() = Some(settings.clone());
+    settings
+}
+
+fn settings_from(layers: &[(Config, Source)]) -> Settings {
+    let value = |key: &str| lookup_in(layers, key).ok().and_then(|(value, _)| value);
+    // Every layer was checked when it was read, so these parse
+    Settings {
+        database_url: value("database_url")
+            .unwrap_or_else(|| default_database_path().display().to_string()),
+        workers: value("workers")
+            .and_then(|workers| workers.parse().ok())
+            .unwrap_or(DEFAULT_WORKERS),
+        hash_algorithm: value("hash_algorithm")
+            .and_then(|name| HashAlgorithm::parse(&name))
+            .unwrap_or(HashAlgorithm::Blake3),
+        exclude: value("exclude")
+            .map(|patterns| {
+                patterns
+                    .split(',')
+                    .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        repository: value("repository")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_repository_path),
+        theme: value("theme")
+            .and_then(|name| UiTheme::parse(&name))
+            .unwrap_or(UiTheme::Light),
+    }
+}
+
+impl Settings {
+    /// Whether `path` matches an exclude pattern, either as a whole or by its
+    /// last component.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        self.exclude.iter().any(|pattern| {
+            pattern.matches_path(path) || name.as_ref().is_some_and(|name| pattern.matches(name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(pairs: &[(&str, &str)]) -> Config {
+        let mut config = Config::default();
+        for (key, value) in pairs {
+            config.set(key, value).unwrap();
+        }
+        config
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_win_over_the_environment_and_the_environment_over_the_file() {
+        let layers = [
+            (layer(&[("workers", "8")]), Source::Flag),
+            (
+                layer(&[("workers", "6"), ("theme", "dark")]),
+                Source::Environment,
+            ),
+            (
+                layer(&[("workers", "2"), ("theme", "light"), ("repository", "/srv")]),
+                Source::File,
+            ),
+        ];
+        let lookup = |key| lookup_in(&layers, key).unwrap();
+        assert_eq!(lookup("workers"), (Some("8".to_string()), Source::Flag));
+        assert_eq!(
+            lookup("theme"),
+            (Some("dark".to_string()), Source::Environment)
+        );
+        assert_eq!(
+            lookup("repository"),
+            (Some("/srv".to_string()), Source::File)
+        );
+        assert_eq!(
+            lookup("hash_algorithm"),
+            (Some("blake3".to_string()), Source::Default)
+        );
+        assert_eq!(lookup("exclude"), (None, Source::Default));
+        assert!(lookup_in(&layers, "colour").is_err());
+
+        let settings = settings_from(&layers);
+        assert_eq!(settings.workers, 8);
+        assert_eq!(settings.theme, UiTheme::Dark);
+        assert_eq!(settings.repository, PathBuf::from("/srv"));
+        assert_eq!(settings.hash_algorithm, HashAlgorithm::Blake3);
+        assert!(settings.exclude.is_empty());
+    }
+
+    #[test]
+    fn the_default_repository_is_next_to_the_default_database() {
+        let settings = settings_from(&[]);
+        assert!(settings.repository.is_absolute());
+        assert_eq!(
+            settings.repository.parent(),
+            default_database_path().parent()
+        );
+    }
+
+    #[test]
+    fn values_are_checked_and_empty_ones_unset() {
+        let mut config = layer(&[("workers", "3"), ("exclude", "*.tmp, ,.git")]);
+        assert_eq!(
+            config.exclude,
+            Some(vec!["*.tmp".to_string(), ".git".to_string()])
+        );
+
+        assert!(config.set("workers", "0").is_err());
+        assert!(config.set("workers", "many").is_err());
+        assert!(config.set("hash_algorithm", "md5").is_err());
+        assert!(config.set("exclude", "[").is_err());
+        assert!(config.set("theme", "blue").is_err());
+        assert!(config.set("colour", "blue").is_err());
+        assert_eq!(config.workers, Some(3));
+
+        config.set("workers", " ").unwrap();
+        assert_eq!(config.workers, None);
+    }
+
+    #[test]
+    fn flags_are_taken_out_of_the_arguments() {
+        let (overrides, rest) = Config::parse_flags(args(&[
+            "archiver",
+            "--workers",
+            "8",
+            "cli",
+            "--exclude",
+            "*.tmp",
+            "--exclude",
+            "*.bak,.git",
+            "--config",
+            "/etc/archiver.toml",
+            "--verbose",
+        ]))
+        .unwrap();
+        assert_eq!(rest, args(&["archiver", "cli", "--verbose"]));
+        assert_eq!(overrides.values.workers, Some(8));
+        assert_eq!(
+            overrides.values.exclude,
+            Some(args(&["*.tmp", "*.bak", ".git"]))
+        );
+        assert_eq!(
+            overrides.config_path,
+            Some(PathBuf::from("/etc/archiver.toml"))
+        );
+
+        assert!(Config::parse_flags(args(&["archiver", "--workers"])).is_err());
+        assert!(Config::parse_flags(args(&["archiver", "--workers", "0"])).is_err());
+    }
+
+    #[test]
+    fn config_files_are_checked_when_read() {
+        let dir = env::temp_dir().join(format!("tsk-config-{}-files", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            load_file(&dir.join("missing.toml")).unwrap(),
+            Config::default()
+        );
+
+        let path = dir.join("config.toml");
+        fs::write(&path, "workers = 2\nexclude = [\"*.tmp\"]\n").unwrap();
+        let config = load_file(&path).unwrap();
+        assert_eq!(config.workers, Some(2));
+        assert_eq!(config.exclude, Some(vec!["*.tmp".to_string()]));
+
+        fs::write(&path, "workers = 0\n").unwrap();
+        assert!(load_file(&path).is_err());
+        fs::write(&path, "colour = \"blue\"\n").unwrap();
+        assert!(load_file(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exclude_patterns_match_whole_paths_and_names() {
+        let settings = settings_from(&[(
+            layer(&[("exclude", "*.tmp,node_modules,/data/cache/*")]),
+            Source::File,
+        )]);
+        assert!(settings.is_excluded(Path::new("/home/me/notes.tmp")));
+        assert!(settings.is_excluded(Path::new("/home/me/app/node_modules")));
+        assert!(settings.is_excluded(Path::new("/data/cache/blob")));
+        assert!(!settings.is_excluded(Path::new("/home/me/notes.txt")));
+        assert!(!settings.is_excluded(Path::new("/home/me/node_modules_backup")));
+        assert!(!settings.is_excluded(Path::new("/data/other/blob")));
+    }
+
+    #[test]
+    fn settings_are_kept_until_reloaded() {
+        env::set_var("ARCHIVE_THEME", "dark");
+        reload();
+        assert_eq!(settings().theme, UiTheme::Dark);
+
+        env::set_var("ARCHIVE_THEME", "light");
+        assert_eq!(settings().theme, UiTheme::Dark);
+        reload();
+        assert_eq!(settings().theme, UiTheme::Light);
+        env::remove_var("ARCHIVE_THEME");
+    }
+}
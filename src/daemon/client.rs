@@ -0,0 +1,178 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use super::protocol::{Request, RequestEnvelope, Response, ResponseEnvelope, PROTOCOL_VERSION};
+use super::socket_path;
+use crate::error::ArchiverError;
+use crate::jobs::JobSummary;
+use crate::maintenance::{CatalogReport, RepairReport};
+use crate::models::JobItem;
+use crate::progress::Progress;
+use crate::watch::Watch;
+
+/// A connection to a running `archiverd`.
+pub struct DaemonClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl DaemonClient {
+    /// Connect to the daemon at the default socket path.
+    pub fn connect() -> Result<Self, ArchiverError> {
+        Self::connect_to(&socket_path())
+    }
+
+    pub fn connect_to(path: &Path) -> Result<Self, ArchiverError> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let mut client = DaemonClient {
+            reader,
+            writer,
+            next_id: 1,
+        };
+        client.request(Request::Ping)?;
+        Ok(client)
+    }
+
+    /// Send a request and wait for its response. `Response::Error` is turned
+    /// into `ArchiverError::Daemon`.
+    pub fn request(&mut self, request: Request) -> Result<Response, ArchiverError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut line = serde_json::to_string(&RequestEnvelope {
+            version: PROTOCOL_VERSION,
+            id,
+            request,
+        })
+        .map_err(|e| ArchiverError::Daemon(e.to_string()))?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ArchiverError::Daemon(
+                "the daemon closed the connection".to_string(),
+            ));
+        }
+        let envelope: ResponseEnvelope =
+            serde_json::from_str(&line).map_err(|e| ArchiverError::Daemon(e.to_string()))?;
+        if envelope.id != id {
+            return Err(ArchiverError::Daemon(format!(
+                "expected a response to request {}, got {}",
+                id, envelope.id
+            )));
+        }
+
+        match envelope.response {
+            Response::Error { message } => Err(ArchiverError::Daemon(message)),
+            response => Ok(response),
+        }
+    }
+
+    pub fn add_files(&mut self, profile_id: i32, paths: Vec<String>) -> Result<i32, ArchiverError> {
+        self.start_job(Request::AddFiles { profile_id, paths })
+    }
+
+    pub fn verify_profile(&mut self, profile_id: i32) -> Result<i32, ArchiverError> {
+        self.start_job(Request::VerifyProfile { profile_id })
+    }
+
+    pub fn run_scrub(&mut self, profile_id: i32) -> Result<i32, ArchiverError> {
+        self.start_job(Request::RunScrub { profile_id })
+    }
+
+    /// A job and, while it runs, its byte-level progress.
+    pub fn job_status(
+        &mut self,
+        job_id: i32,
+    ) -> Result<(JobSummary, Option<Progress>), ArchiverError> {
+        match self.request(Request::JobStatus { job_id })? {
+            Response::Job { job, progress } => Ok((job, progress)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn job_items(&mut self, job_id: i32) -> Result<Vec<JobItem>, ArchiverError> {
+        match self.request(Request::JobItems { job_id })? {
+            Response::JobItems { items } => Ok(items),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn cancel_job(&mut self, job_id: i32) -> Result<(), ArchiverError> {
+        self.request(Request::CancelJob { job_id }).map(|_| ())
+    }
+
+    pub fn list_jobs(&mut self) -> Result<Vec<JobSummary>, ArchiverError> {
+        match self.request(Request::ListJobs)? {
+            Response::Jobs { jobs } => Ok(jobs),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Archive new and changed files below `path`, which must be absolute.
+    pub fn watch(&mut self, profile_id: i32, path: &Path) -> Result<(), ArchiverError> {
+        let path = path.to_string_lossy().into_owned();
+        self.request(Request::Watch { profile_id, path })
+            .map(|_| ())
+    }
+
+    pub fn unwatch(&mut self, profile_id: i32, path: &Path) -> Result<(), ArchiverError> {
+        let path = path.to_string_lossy().into_owned();
+        self.request(Request::Unwatch { profile_id, path })
+            .map(|_| ())
+    }
+
+    pub fn list_watches(&mut self) -> Result<Vec<Watch>, ArchiverError> {
+        match self.request(Request::ListWatches)? {
+            Response::Watches { watches } => Ok(watches),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Have the daemon read the settings again.
+    pub fn reload_settings(&mut self) -> Result<(), ArchiverError> {
+        self.request(Request::ReloadSettings).map(|_| ())
+    }
+
+    /// Back the catalog up into `dir`, which must be absolute, or next to it.
+    pub fn backup_catalog(&mut self, dir: Option<&Path>) -> Result<PathBuf, ArchiverError> {
+        let dir = dir.map(|dir| dir.to_string_lossy().into_owned());
+        match self.request(Request::BackupCatalog { dir })? {
+            Response::Backup { path } => Ok(PathBuf::from(path)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn check_catalog(&mut self) -> Result<CatalogReport, ArchiverError> {
+        match self.request(Request::CheckCatalog)? {
+            Response::CatalogChecked { report } => Ok(report),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn repair_catalog(&mut self) -> Result<RepairReport, ArchiverError> {
+        match self.request(Request::RepairCatalog)? {
+            Response::CatalogRepaired { report } => Ok(report),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn shutdown(&mut self) -> Result<(), ArchiverError> {
+        self.request(Request::Shutdown).map(|_| ())
+    }
+
+    fn start_job(&mut self, request: Request) -> Result<i32, ArchiverError> {
+        match self.request(request)? {
+            Response::JobStarted { job_id } => Ok(job_id),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> ArchiverError {
+    ArchiverError::Daemon(format!("unexpected response {:?}", response))
+}
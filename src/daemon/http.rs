@@ -0,0 +1,373 @@
+//! JSON over HTTP for dashboards and scripts. Every endpoint wraps a daemon
+//! [`Request`], and every request must carry `Authorization: Bearer <token>`.
+//!
+//! | method | path                               | body                       |
+//! |--------|------------------------------------|----------------------------|
+//! | GET    | `/api/v1/profiles`                 |                            |
+//! | POST   | `/api/v1/profiles`                 | `{"profile_name": "..."}`  |
+//! | GET    | `/api/v1/profiles/<id>/files`      |                            |
+//! | POST   | `/api/v1/profiles/<id>/ingest`     | `{"paths": ["..."]}`       |
+//! | POST   | `/api/v1/profiles/<id>/verify`     |                            |
+//! | GET    | `/api/v1/files?q=...&profile_id=N` |                            |
+//! | GET    | `/api/v1/files/<id>/verifications` |                            |
+//! | GET    | `/api/v1/jobs`                     |                            |
+//! | GET    | `/api/v1/jobs/<id>`                |                            |
+//! | GET    | `/api/v1/jobs/<id>/items`          |                            |
+//! | POST   | `/api/v1/jobs/<id>/cancel`         |                            |
+//! | POST   | `/api/v1/catalog/backup`           | `{"dir": "..."}`, optional |
+//! | GET    | `/api/v1/watches`                  |                            |
+//! | POST   | `/api/v1/watches`                  | `{"profile_id": N, "path": "..."}` |
+//! | DELETE | `/api/v1/watches`                  | `{"profile_id": N, "path": "..."}` |
+//! | GET    | `/api/v1/catalog/check`            |                            |
+//! | POST   | `/api/v1/catalog/repair`           |                            |
+//!
+//! Starting a job answers `202 Accepted` with `{"job_id": N}`; errors answer
+//! with `{"error": "..."}`. A running job's `progress` has bytes done and in
+//! total, the current file, throughput and an ETA in seconds.
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Server};
+
+use super::protocol::{Request, Response};
+use super::Daemon;
+use crate::error::ArchiverError;
+use crate::watch::Watch;
+
+#[derive(Deserialize)]
+struct CreateProfileBody {
+    profile_name: String,
+}
+
+#[derive(Deserialize)]
+struct IngestBody {
+    paths: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct BackupBody {
+    dir: Option<String>,
+}
+
+/// A random token for when none is configured.
+pub fn generate_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+/// Serve the API on `addr`, which must be a loopback address, until the
+/// daemon shuts down.
+pub fn serve(daemon: &Arc<Daemon>, addr: &str, token: &str) -> Result<(), ArchiverError> {
+    let socket_addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| ArchiverError::InvalidInput(format!("Invalid address {}", addr)))?;
+    if !socket_addr.ip().is_loopback() {
+        return Err(ArchiverError::InvalidInput(
+            "The HTTP API only listens on loopback addresses".to_string(),
+        ));
+    }
+    if token.is_empty() {
+        return Err(ArchiverError::InvalidInput(
+            "The HTTP API needs a token".to_string(),
+        ));
+    }
+
+    let server = Server::http(socket_addr).map_err(|e| ArchiverError::Daemon(e.to_string()))?;
+    println!("archiverd HTTP API listening on http://{}", socket_addr);
+
+    let token: Arc<str> = Arc::from(token);
+    while !daemon.is_shutting_down() {
+        let request = match server.recv_timeout(Duration::from_secs(1)) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("Failed to accept HTTP request: {}", e);
+                continue;
+            }
+        };
+
+        // A slow request, such as a catalog check, must not hold up the others
+        let daemon = Arc::clone(daemon);
+        let token = Arc::clone(&token);
+        thread::spawn(move || answer(&daemon, request, &token));
+    }
+    Ok(())
+}
+
+fn answer(daemon: &Arc<Daemon>, mut request: tiny_http::Request, token: &str) {
+    let authorized = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|given| tokens_match(given, token));
+
+    let (status, body) = if authorized {
+        let mut body = String::new();
+        match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => route(daemon, request.method(), request.url(), &body),
+            Err(e) => error(400, format!("unreadable body: {}", e)),
+        }
+    } else {
+        error(401, "missing or wrong token".to_string())
+    };
+
+    let response = tiny_http::Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap());
+    if let Err(e) = request.respond(response) {
+        println!("Failed to answer HTTP request: {}", e);
+    }
+}
+
+/// Answer one request. A handler that panics answers 500 and leaves the
+/// daemon serving.
+fn route(daemon: &Arc<Daemon>, method: &Method, url: &str, body: &str) -> (u16, Value) {
+    panic::catch_unwind(AssertUnwindSafe(|| dispatch(daemon, method, url, body)))
+        .unwrap_or_else(|_| error(500, "internal error".to_string()))
+}
+
+fn dispatch(daemon: &Arc<Daemon>, method: &Method, url: &str, body: &str) -> (u16, Value) {
+    let Ok(url) = url::Url::parse(&format!("http://localhost{}", url)) else {
+        return error(400, "invalid URL".to_string());
+    };
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let segments: Vec<&str> = url.path().trim_matches('/').split('/').collect();
+
+    let request = match (method, segments.as_slice()) {
+        (Method::Get, ["api", "v1", "profiles"]) => Request::ListProfiles,
+        (Method::Post, ["api", "v1", "profiles"]) => {
+            match serde_json::from_str::<CreateProfileBody>(body) {
+                Ok(body) => Request::CreateProfile {
+                    profile_name: body.profile_name,
+                },
+                Err(e) => return error(400, e.to_string()),
+            }
+        }
+        (Method::Get, ["api", "v1", "profiles", id, "files"]) => match id.parse() {
+            Ok(profile_id) => Request::ListFiles { profile_id },
+            Err(_) => return error(404, format!("no profile {}", id)),
+        },
+        (Method::Post, ["api", "v1", "profiles", id, "ingest"]) => {
+            let Ok(profile_id) = id.parse() else {
+                return error(404, format!("no profile {}", id));
+            };
+            match serde_json::from_str::<IngestBody>(body) {
+                Ok(body) => Request::AddFiles {
+                    profile_id,
+                    paths: body.paths,
+                },
+                Err(e) => return error(400, e.to_string()),
+            }
+        }
+        (Method::Post, ["api", "v1", "profiles", id, "verify"]) => match id.parse() {
+            Ok(profile_id) => Request::VerifyProfile { profile_id },
+            Err(_) => return error(404, format!("no profile {}", id)),
+        },
+        (Method::Get, ["api", "v1", "files"]) => {
+            let profile_id = match query("profile_id").map(|id| id.parse()) {
+                Some(Ok(id)) => Some(id),
+                Some(Err(_)) => return error(400, "invalid profile_id".to_string()),
+                None => None,
+            };
+            Request::SearchFiles {
+                profile_id,
+                query: query("q").unwrap_or_default(),
+            }
+        }
+        (Method::Get, ["api", "v1", "files", id, "verifications"]) => match id.parse() {
+            Ok(file_id) => Request::VerificationHistory { file_id },
+            Err(_) => return error(404, format!("no file {}", id)),
+        },
+        (Method::Get, ["api", "v1", "jobs"]) => Request::ListJobs,
+        (Method::Get, ["api", "v1", "jobs", id]) => match id.parse() {
+            Ok(job_id) => Request::JobStatus { job_id },
+            Err(_) => return error(404, format!("no job {}", id)),
+        },
+        (Method::Get, ["api", "v1", "jobs", id, "items"]) => match id.parse() {
+            Ok(job_id) => Request::JobItems { job_id },
+            Err(_) => return error(404, format!("no job {}", id)),
+        },
+        (Method::Post, ["api", "v1", "jobs", id, "cancel"]) => match id.parse() {
+            Ok(job_id) => Request::CancelJob { job_id },
+            Err(_) => return error(404, format!("no job {}", id)),
+        },
+        (Method::Post, ["api", "v1", "catalog", "backup"]) => {
+            let body = match body.trim() {
+                "" => Ok(BackupBody::default()),
+                body => serde_json::from_str::<BackupBody>(body),
+            };
+            match body {
+                Ok(body) => Request::BackupCatalog { dir: body.dir },
+                Err(e) => return error(400, e.to_string()),
+            }
+        }
+        (Method::Get, ["api", "v1", "watches"]) => Request::ListWatches,
+        (Method::Post, ["api", "v1", "watches"]) => match serde_json::from_str::<Watch>(body) {
+            Ok(watch) => Request::Watch {
+                profile_id: watch.profile_id,
+                path: watch.path,
+            },
+            Err(e) => return error(400, e.to_string()),
+        },
+        (Method::Delete, ["api", "v1", "watches"]) => match serde_json::from_str::<Watch>(body) {
+            Ok(watch) => Request::Unwatch {
+                profile_id: watch.profile_id,
+                path: watch.path,
+            },
+            Err(e) => return error(400, e.to_string()),
+        },
+        (Method::Get, ["api", "v1", "catalog", "check"]) => Request::CheckCatalog,
+        (Method::Post, ["api", "v1", "catalog", "repair"]) => Request::RepairCatalog,
+        _ => return error(404, format!("no route for {} {}", method, url.path())),
+    };
+
+    // Requests that carry nothing but an id only fail when it is unknown, and
+    // requests that carry nothing at all only fail on the daemon's side
+    let error_status = match request {
+        Request::JobStatus { .. } | Request::JobItems { .. } => 404,
+        Request::ListProfiles
+        | Request::ListFiles { .. }
+        | Request::SearchFiles { .. }
+        | Request::VerificationHistory { .. }
+        | Request::ListJobs
+        | Request::ListWatches
+        | Request::CheckCatalog
+        | Request::RepairCatalog => 500,
+        _ => 400,
+    };
+    match daemon.handle(request) {
+        Response::Profile { profile } => (201, json!(profile)),
+        Response::Profiles { profiles } => (200, json!(profiles)),
+        Response::Files { files } => (200, json!(files)),
+        Response::Verifications { verifications } => (200, json!(verifications)),
+        Response::JobStarted { job_id } => (202, json!({ "job_id": job_id })),
+        Response::Job { job, progress } => {
+            let mut body = json!(job);
+            body["progress"] = json!(progress);
+            (200, body)
+        }
+        Response::Jobs { jobs } => (200, json!(jobs)),
+        Response::JobItems { items } => (200, json!(items)),
+        Response::Watches { watches } => (200, json!(watches)),
+        Response::Backup { path } => (201, json!({ "path": path })),
+        Response::CatalogChecked { report } => (200, json!(report)),
+        Response::CatalogRepaired { report } => (200, json!(report)),
+        Response::Error { message } => error(error_status, message),
+        Response::Pong { .. } | Response::Done => (200, json!({ "status": "ok" })),
+    }
+}
+
+fn error(status: u16, message: String) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+// Compare without returning early, so timing does not reveal the token
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::establish_connection_to;
+    use diesel::RunQueryDsl;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn daemon(name: &str) -> (Arc<Daemon>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("tsk-http-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        (Daemon::new(conn, 1), dir)
+    }
+
+    #[test]
+    fn creates_and_lists_profiles() {
+        let (daemon, dir) = daemon("profiles");
+        let body = r#"{"profile_name": "photos"}"#;
+        let (status, created) = route(&daemon, &Method::Post, "/api/v1/profiles", body);
+        assert_eq!(status, 201);
+        assert_eq!(created["profile_name"], "photos");
+
+        let (status, profiles) = route(&daemon, &Method::Get, "/api/v1/profiles", "");
+        assert_eq!(status, 200);
+        assert_eq!(profiles[0]["id"], created["id"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (daemon, dir) = daemon("bad");
+        let cases = [
+            (Method::Post, "/api/v1/profiles", "{", 400),
+            (
+                Method::Post,
+                "/api/v1/profiles",
+                r#"{"profile_name": " "}"#,
+                400,
+            ),
+            (Method::Get, "/api/v1/files?profile_id=x", "", 400),
+            (Method::Get, "/api/v1/jobs/x", "", 404),
+            (Method::Get, "/api/v1/jobs/7", "", 404),
+            (Method::Get, "/api/v1/jobs/7/items", "", 404),
+            (Method::Delete, "/api/v1/profiles", "", 404),
+        ];
+        for (method, url, body, expected) in cases {
+            let (status, answer) = route(&daemon, &method, url, body);
+            assert_eq!(status, expected, "{} {}", method, url);
+            assert!(answer["error"].is_string());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn database_failures_answer_500() {
+        let (daemon, dir) = daemon("failures");
+        let mut conn = daemon.conn.get().unwrap();
+        diesel::sql_query("DROP TABLE verifications")
+            .execute(&mut *conn)
+            .unwrap();
+        drop(conn);
+
+        let (status, answer) = route(&daemon, &Method::Get, "/api/v1/files/1/verifications", "");
+        assert_eq!(status, 500);
+        assert!(answer["error"].is_string());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn panics_answer_500_and_later_requests_are_served() {
+        let (daemon, dir) = daemon("panics");
+        let poisoner = Arc::clone(&daemon);
+        let _ = thread::spawn(move || {
+            let _watches = poisoner.watches.lock().unwrap();
+            panic!("poison the watches");
+        })
+        .join();
+
+        let (status, _) = route(&daemon, &Method::Get, "/api/v1/watches", "");
+        assert_eq!(status, 500);
+        let (status, _) = route(&daemon, &Method::Get, "/api/v1/profiles", "");
+        assert_eq!(status, 200);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
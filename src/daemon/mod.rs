@@ -0,0 +1,364 @@
+//! `archiverd`: a background process that owns the database connections and a
+//! worker pool, so long operations keep running after the GUI or CLI exits.
+//!
+//! Clients reach it over a Unix domain socket ([`socket`]) or, when enabled,
+//! a token-protected HTTP API on localhost ([`http`]).
+
+#[cfg(unix)]
+pub mod client;
+pub mod http;
+pub mod protocol;
+#[cfg(unix)]
+pub mod socket;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use self::protocol::{Request, Response};
+use crate::config;
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::jobs::{
+    cancel_job, create_ingest_job, create_scrub_job, create_verify_job, get_job, get_job_items,
+    interrupted_jobs, job_progress, list_jobs, run_job,
+};
+use crate::maintenance::{backup_catalog, check_catalog, repair_catalog};
+use crate::models::Job;
+use crate::scrub::run_scrub_daemon;
+use crate::thread_pool::{JobHandle, ThreadPool};
+use crate::watch::{changed_files, watch_interval, Watch};
+use crate::{
+    try_create_profile, try_get_files, try_get_profiles, try_get_verification_history,
+    try_search_files,
+};
+
+/// Where the daemon listens: `ARCHIVERD_SOCKET`, else `archiverd.sock` in
+/// `XDG_RUNTIME_DIR`, else a per-user name in the temporary directory.
+pub fn socket_path() -> PathBuf {
+    if let Ok(path) = env::var("ARCHIVERD_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => PathBuf::from(dir).join("archiverd.sock"),
+        Err(_) => env::temp_dir().join(format!(
+            "archiverd-{}.sock",
+            env::var("USER").unwrap_or_default()
+        )),
+    }
+}
+
+/// State shared by every transport: the database connections, the worker pool
+/// and the jobs handed to it.
+pub struct Daemon {
+    conn: DbPool,
+    pool: ThreadPool,
+    running: Mutex<HashMap<i32, JobHandle<()>>>,
+    /// Watched folders, with the job archiving their latest changes. They
+    /// last as long as the daemon runs.
+    watches: Mutex<Vec<(Watch, Option<i32>)>>,
+    shutting_down: AtomicBool,
+}
+
+impl Daemon {
+    /// Take over the database connections, with `workers` threads for jobs. Jobs
+    /// left unfinished by an earlier run are resumed, and due scrub schedules
+    /// and watched folders are taken care of in the background from now on.
+    pub fn new(conn: DbPool, workers: usize) -> Arc<Self> {
+        let daemon = Arc::new(Daemon {
+            conn,
+            pool: ThreadPool::new(workers),
+            running: Mutex::new(HashMap::new()),
+            watches: Mutex::new(Vec::new()),
+            shutting_down: AtomicBool::new(false),
+        });
+
+        match interrupted_jobs(daemon.conn.clone()) {
+            Ok(jobs) => {
+                for job in jobs {
+                    println!("Resuming {} job {}", job.kind, job.id);
+                    spawn_job(&daemon, job.id);
+                }
+            }
+            Err(e) => println!("Failed to load interrupted jobs: {}", e),
+        }
+
+        let conn = daemon.conn.clone();
+        thread::spawn(move || run_scrub_daemon(conn));
+        let watcher = Arc::clone(&daemon);
+        thread::spawn(move || run_watches(&watcher));
+        daemon
+    }
+
+    pub fn handle(self: &Arc<Self>, request: Request) -> Response {
+        handle_request(self, request)
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Block until every job handed to the pool has stopped.
+    pub fn wait_for_jobs(&self) {
+        loop {
+            let handles: Vec<(i32, JobHandle<()>)> = self.running.lock().unwrap().drain().collect();
+            if handles.is_empty() {
+                return;
+            }
+            for (job_id, handle) in handles {
+                if let Err(e) = handle.join() {
+                    println!("Job {} {}", job_id, e);
+                }
+            }
+        }
+    }
+}
+
+fn handle_request(daemon: &Arc<Daemon>, request: Request) -> Response {
+    let conn = daemon.conn.clone();
+
+    match request {
+        Request::Ping => Response::Pong {
+            daemon_version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        Request::ListProfiles => match try_get_profiles(conn) {
+            Ok(profiles) => Response::Profiles { profiles },
+            Err(e) => failed(e),
+        },
+        Request::CreateProfile { profile_name } => {
+            let profile_name = profile_name.trim();
+            if profile_name.is_empty() {
+                return Response::Error {
+                    message: "A profile needs a name".to_string(),
+                };
+            }
+            match try_create_profile(conn, profile_name) {
+                Ok(profile) => Response::Profile { profile },
+                Err(e) => failed(e),
+            }
+        }
+        Request::ListFiles { profile_id } => match try_get_files(conn, &profile_id) {
+            Ok(files) => Response::Files { files },
+            Err(e) => failed(e),
+        },
+        Request::SearchFiles { profile_id, query } => {
+            match try_search_files(conn, profile_id.as_ref(), &query) {
+                Ok(files) => Response::Files { files },
+                Err(e) => failed(e),
+            }
+        }
+        Request::VerificationHistory { file_id } => {
+            match try_get_verification_history(conn, &file_id) {
+                Ok(verifications) => Response::Verifications { verifications },
+                Err(e) => failed(e),
+            }
+        }
+        Request::AddFiles { profile_id, paths } => {
+            start_job(daemon, create_ingest_job(conn, &profile_id, &paths))
+        }
+        Request::VerifyProfile { profile_id } => {
+            start_job(daemon, create_verify_job(conn, &profile_id))
+        }
+        Request::RunScrub { profile_id } => start_job(daemon, create_scrub_job(conn, &profile_id)),
+        Request::JobStatus { job_id } => match get_job(conn, &job_id) {
+            Ok(job) => Response::Job {
+                job,
+                progress: job_progress(job_id),
+            },
+            Err(_) => Response::Error {
+                message: format!("no job {}", job_id),
+            },
+        },
+        Request::JobItems { job_id } => {
+            match get_job(conn.clone(), &job_id).and_then(|_| get_job_items(conn, &job_id)) {
+                Ok(items) => Response::JobItems { items },
+                _ => Response::Error {
+                    message: format!("no job {}", job_id),
+                },
+            }
+        }
+        Request::CancelJob { job_id } => match cancel_job(conn, &job_id) {
+            Ok(_) => {
+                // Skip it altogether if it is still queued
+                if let Some(handle) = daemon.running.lock().unwrap().get(&job_id) {
+                    handle.cancel();
+                }
+                Response::Done
+            }
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        },
+        Request::ListJobs => match list_jobs(conn, None) {
+            Ok(jobs) => Response::Jobs { jobs },
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        },
+        Request::BackupCatalog { dir } => {
+            match backup_catalog(conn, dir.as_deref().map(Path::new)) {
+                Ok(path) => Response::Backup {
+                    path: path.to_string_lossy().into_owned(),
+                },
+                Err(e) => Response::Error {
+                    message: e.to_string(),
+                },
+            }
+        }
+        Request::CheckCatalog => match check_catalog(conn) {
+            Ok(report) => Response::CatalogChecked { report },
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        },
+        Request::RepairCatalog => match repair_catalog(conn) {
+            Ok(report) => Response::CatalogRepaired { report },
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        },
+        Request::Watch { profile_id, path } => {
+            let path = Path::new(&path);
+            if !path.is_absolute() || !path.is_dir() {
+                return Response::Error {
+                    message: format!("{} is not an absolute path to a folder", path.display()),
+                };
+            }
+            match try_get_profiles(conn) {
+                Ok(profiles) if profiles.iter().any(|profile| profile.id == profile_id) => {}
+                Ok(_) => {
+                    return Response::Error {
+                        message: format!("no profile {}", profile_id),
+                    }
+                }
+                Err(e) => return failed(e),
+            }
+            let watch = Watch {
+                profile_id,
+                path: path.to_string_lossy().into_owned(),
+            };
+            let mut watches = daemon.watches.lock().unwrap();
+            if !watches.iter().any(|(watched, _)| *watched == watch) {
+                watches.push((watch, None));
+            }
+            Response::Done
+        }
+        Request::Unwatch { profile_id, path } => {
+            let mut watches = daemon.watches.lock().unwrap();
+            let before = watches.len();
+            watches.retain(|(watch, _)| watch.profile_id != profile_id || watch.path != path);
+            if watches.len() == before {
+                return Response::Error {
+                    message: format!("{} is not watched", path),
+                };
+            }
+            Response::Done
+        }
+        Request::ListWatches => Response::Watches {
+            watches: daemon
+                .watches
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(watch, _)| watch.clone())
+                .collect(),
+        },
+        Request::ReloadSettings => {
+            config::reload();
+            Response::Done
+        }
+        Request::Shutdown => {
+            daemon.shutting_down.store(true, Ordering::SeqCst);
+            Response::Done
+        }
+    }
+}
+
+fn failed(e: ArchiverError) -> Response {
+    Response::Error {
+        message: e.to_string(),
+    }
+}
+
+fn start_job(daemon: &Arc<Daemon>, job: Result<Job, ArchiverError>) -> Response {
+    match job {
+        Ok(job) => {
+            spawn_job(daemon, job.id);
+            Response::JobStarted { job_id: job.id }
+        }
+        Err(e) => Response::Error {
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Run a recorded job on the pool. Its progress lives in the jobs table.
+fn spawn_job(daemon: &Arc<Daemon>, job_id: i32) {
+    let mut running = daemon.running.lock().unwrap();
+
+    // Forget jobs that have stopped since the last call
+    running.retain(|job_id, handle| match handle.try_join() {
+        None => true,
+        Some(Ok(())) => false,
+        Some(Err(e)) => {
+            println!("Job {} {}", job_id, e);
+            false
+        }
+    });
+    if running.contains_key(&job_id) {
+        return;
+    }
+
+    let conn = daemon.conn.clone();
+    let handle = daemon.pool.spawn(move || {
+        if let Err(e) = run_job(conn, &job_id, |_| {}) {
+            println!("Job {} failed: {}", job_id, e);
+        }
+    });
+    running.insert(job_id, handle);
+}
+
+/// Archive what changed in the watched folders, round after round. A folder
+/// is left alone while the job archiving its previous changes still runs.
+fn run_watches(daemon: &Arc<Daemon>) {
+    loop {
+        thread::sleep(watch_interval());
+        if daemon.is_shutting_down() {
+            return;
+        }
+
+        let watches = daemon.watches.lock().unwrap().clone();
+        for (watch, job_id) in watches {
+            let busy = job_id.is_some_and(|job_id| {
+                get_job(daemon.conn.clone(), &job_id).is_ok_and(|job| !job.is_finished())
+            });
+            if busy {
+                continue;
+            }
+
+            let changed = changed_files(
+                daemon.conn.clone(),
+                &watch.profile_id,
+                Path::new(&watch.path),
+            );
+            let job = match changed {
+                Ok(files) if files.is_empty() => continue,
+                Ok(files) => create_ingest_job(daemon.conn.clone(), &watch.profile_id, &files),
+                Err(e) => Err(e),
+            };
+            match job {
+                Ok(job) => {
+                    println!("Archiving changes in {} as job {}", watch.path, job.id);
+                    spawn_job(daemon, job.id);
+                    let mut watches = daemon.watches.lock().unwrap();
+                    if let Some(entry) = watches.iter_mut().find(|(watched, _)| *watched == watch) {
+                        entry.1 = Some(job.id);
+                    }
+                }
+                Err(e) => println!("Failed to archive changes in {}: {}", watch.path, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Messages exchanged with `archiverd` over its control socket.
+//!
+//! Each message is one line of JSON. A client sends a [`RequestEnvelope`] and
+//! reads back a [`ResponseEnvelope`] carrying the same `id`. Both carry the
+//! protocol version; the daemon answers requests of any other version with
+//! [`Response::Error`] and does nothing else.
+
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::JobSummary;
+use crate::maintenance::{CatalogReport, RepairReport};
+use crate::models::{File, JobItem, Profile, Verification};
+use crate::progress::Progress;
+use crate::watch::Watch;
+
+pub const PROTOCOL_VERSION: u32 = 6;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequestEnvelope {
+    pub version: u32,
+    pub id: u64,
+    pub request: Request,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResponseEnvelope {
+    pub version: u32,
+    pub id: u64,
+    pub response: Response,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Ping,
+    ListProfiles,
+    CreateProfile {
+        profile_name: String,
+    },
+    ListFiles {
+        profile_id: i32,
+    },
+    /// Files whose path contains `query` or whose digest starts with it.
+    SearchFiles {
+        profile_id: Option<i32>,
+        query: String,
+    },
+    /// Start archiving files in the background.
+    AddFiles {
+        profile_id: i32,
+        paths: Vec<String>,
+    },
+    /// Start verifying every file of a profile in the background.
+    VerifyProfile {
+        profile_id: i32,
+    },
+    /// Start the next scrub batch of a profile in the background.
+    RunScrub {
+        profile_id: i32,
+    },
+    VerificationHistory {
+        file_id: i32,
+    },
+    JobStatus {
+        job_id: i32,
+    },
+    /// Every item of a job with its state and, for failures, the reason.
+    JobItems {
+        job_id: i32,
+    },
+    /// Stop a job after the item it is working on.
+    CancelJob {
+        job_id: i32,
+    },
+    ListJobs,
+    /// Archive new and changed files below the absolute folder `path` into a
+    /// profile for as long as the daemon runs.
+    Watch {
+        profile_id: i32,
+        path: String,
+    },
+    Unwatch {
+        profile_id: i32,
+        path: String,
+    },
+    ListWatches,
+    /// Read the settings again, after the config file changed.
+    ReloadSettings,
+    /// Copy the catalog to a file named after the current time, in `dir` or
+    /// next to the catalog.
+    BackupCatalog {
+        dir: Option<String>,
+    },
+    CheckCatalog,
+    /// Back the catalog up, then fix what `CheckCatalog` finds if it can.
+    RepairCatalog,
+    /// Stop accepting requests and exit once running jobs have finished.
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Pong {
+        daemon_version: String,
+    },
+    Done,
+    Profile {
+        profile: Profile,
+    },
+    Profiles {
+        profiles: Vec<Profile>,
+    },
+    Files {
+        files: Vec<File>,
+    },
+    Verifications {
+        verifications: Vec<Verification>,
+    },
+    JobStarted {
+        job_id: i32,
+    },
+    /// A job, with its byte-level progress while it runs.
+    Job {
+        job: JobSummary,
+        progress: Option<Progress>,
+    },
+    Jobs {
+        jobs: Vec<JobSummary>,
+    },
+    JobItems {
+        items: Vec<JobItem>,
+    },
+    Watches {
+        watches: Vec<Watch>,
+    },
+    Backup {
+        path: String,
+    },
+    CatalogChecked {
+        report: CatalogReport,
+    },
+    CatalogRepaired {
+        report: RepairReport,
+    },
+    Error {
+        message: String,
+    },
+}
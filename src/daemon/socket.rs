@@ -0,0 +1,129 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use super::protocol::{Request, RequestEnvelope, Response, ResponseEnvelope, PROTOCOL_VERSION};
+use super::Daemon;
+use crate::error::ArchiverError;
+
+/// Serve requests on the Unix socket at `path` until a client asks the daemon
+/// to shut down. The socket is only accessible to the current user.
+pub fn serve(daemon: &Arc<Daemon>, path: &Path) -> Result<(), ArchiverError> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(ArchiverError::Daemon(format!(
+                "a daemon is already listening on {}",
+                path.display()
+            )));
+        }
+        // Left behind by a daemon that did not shut down cleanly
+        fs::remove_file(path)?;
+    }
+
+    let listener = bind_private(path)?;
+
+    println!("archiverd listening on {}", path.display());
+
+    for stream in listener.incoming() {
+        if daemon.is_shutting_down() {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                let daemon = Arc::clone(daemon);
+                let path = path.to_path_buf();
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(&daemon, stream, &path) {
+                        println!("Client connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => println!("Failed to accept connection: {}", e),
+        }
+    }
+
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Bind the socket inside a fresh directory only the current user can enter,
+/// tighten its permissions and only then move it to `path`, so no one else
+/// can connect in between.
+fn bind_private(path: &Path) -> Result<UnixListener, ArchiverError> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let private = parent.join(format!(".archiverd-{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let staged = private.join("sock");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    fs::remove_dir(&private)?;
+    Ok(bound?)
+}
+
+fn handle_client(
+    daemon: &Arc<Daemon>,
+    stream: UnixStream,
+    path: &Path,
+) -> Result<(), ArchiverError> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+        let envelope = match serde_json::from_str::<RequestEnvelope>(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                write_response(
+                    &mut writer,
+                    0,
+                    Response::Error {
+                        message: format!("malformed request: {}", e),
+                    },
+                )?;
+                continue;
+            }
+        };
+
+        if envelope.version != PROTOCOL_VERSION {
+            let message = format!(
+                "unsupported protocol version {}, this daemon speaks {}",
+                envelope.version, PROTOCOL_VERSION
+            );
+            write_response(&mut writer, envelope.id, Response::Error { message })?;
+            continue;
+        }
+
+        let shutdown = matches!(envelope.request, Request::Shutdown);
+        write_response(&mut writer, envelope.id, daemon.handle(envelope.request))?;
+        if shutdown {
+            // Wake the accept loop so it notices
+            let _ = UnixStream::connect(path);
+        }
+    }
+    Ok(())
+}
+
+fn write_response(
+    writer: &mut UnixStream,
+    id: u64,
+    response: Response,
+) -> Result<(), ArchiverError> {
+    let mut line = serde_json::to_string(&ResponseEnvelope {
+        version: PROTOCOL_VERSION,
+        id,
+        response,
+    })
+    .map_err(|e| ArchiverError::Daemon(e.to_string()))?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,570 @@
+//! Opening the catalog database: the schema migrations are built into the
+//! program and applied on connect, so a new database needs no setup.
+//!
+//! A catalog is an SQLite file by default. Built with the `postgres` feature,
+//! a `postgres://` or `postgresql://` URL selects a PostgreSQL database
+//! instead, which a team can share. Both have the same schema: the migrations
+//! in `migrations_postgres` mirror those in `migrations` version for version.
+//!
+//! The library works on a pool of connections rather than one shared
+//! connection, so threads that read files do not queue behind each other's
+//! inserts. SQLite still allows one writer at a time; a write that finds the
+//! database busy waits for it, and is retried by [`retry_on_busy`] if that
+//! is not enough.
+
+use diesel::backend::Backend;
+use diesel::connection::{AnsiTransactionManager, SimpleConnection, TransactionManager};
+use diesel::migration::MigrationSource;
+use diesel::prelude::*;
+use diesel::r2d2::{CustomizeConnection, ManageConnection, Pool, PooledConnection, R2D2Connection};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::ArchiverError;
+use crate::models::{
+    Chunk, File, FileChunk, Job, JobItem, LegalHold, NewChunk, Profile, ScrubSchedule, Verification,
+};
+use crate::schema;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+#[cfg(feature = "postgres")]
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
+
+/// A connection to a catalog of either kind. Queries written with the diesel
+/// DSL run on both; the few that cannot match on the variant.
+#[derive(diesel::MultiConnection)]
+pub enum AnyConnection {
+    Sqlite(SqliteConnection),
+    #[cfg(feature = "postgres")]
+    Postgresql(PgConnection),
+}
+
+pub type DbPool = Pool<CatalogManager>;
+pub type DbConnection = PooledConnection<CatalogManager>;
+
+// How long a connection waits for another process's write to finish before
+// giving up with "database is locked"
+const BUSY_TIMEOUT_MS: u32 = 5000;
+// How often a write that still found the database locked is tried again,
+// waiting twice as long each time
+const BUSY_RETRIES: u32 = 5;
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(100);
+// Rows inserted per statement when copying a catalog, well inside the bind
+// parameter limits of both databases
+const COPY_BATCH: usize = 500;
+
+/// Whether `database_url` names a PostgreSQL database rather than an SQLite
+/// file.
+pub fn is_postgres_url(database_url: &str) -> bool {
+    database_url.starts_with("postgres://") || database_url.starts_with("postgresql://")
+}
+
+// Connect to the kind of database the URL names. Trying each kind in turn,
+// as `AnyConnection::establish` does, would create an SQLite file named after
+// a PostgreSQL server that could not be reached.
+fn establish(database_url: &str) -> ConnectionResult<AnyConnection> {
+    if is_postgres_url(database_url) {
+        #[cfg(feature = "postgres")]
+        return PgConnection::establish(database_url).map(AnyConnection::Postgresql);
+        #[cfg(not(feature = "postgres"))]
+        return Err(diesel::ConnectionError::BadConnection(String::from(
+            "PostgreSQL support is not built in; rebuild with --features postgres",
+        )));
+    }
+    SqliteConnection::establish(database_url).map(AnyConnection::Sqlite)
+}
+
+/// Connect to the catalog at `database_url`, creating it if needed, and bring
+/// its schema up to date. An SQLite connection creates the database's
+/// directory, enforces foreign keys, uses write-ahead logging so readers and a
+/// writer do not block each other, and waits for locks held by other
+/// processes. A PostgreSQL connection works in UTC, as SQLite does.
+pub fn open_database(database_url: &str) -> Result<AnyConnection, ArchiverError> {
+    if let Some(dir) = database_file(database_url).and_then(Path::parent) {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut conn = establish(database_url)
+        .map_err(|e| ArchiverError::Connection(format!("{}: {}", database_url, e)))?;
+    match &mut conn {
+        AnyConnection::Sqlite(conn) => conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {}; PRAGMA journal_mode = WAL;",
+            BUSY_TIMEOUT_MS
+        ))?,
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(conn) => conn.batch_execute("SET TIME ZONE 'UTC';")?,
+    }
+    run_migrations(&mut conn)?;
+    // Only after migrating, which may rebuild tables that others refer to
+    match &mut conn {
+        AnyConnection::Sqlite(conn) => conn.batch_execute("PRAGMA foreign_keys = ON;")?,
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(_) => {}
+    }
+    Ok(conn)
+}
+
+/// Open the catalog at `database_url` as with [`open_database`], and pool up
+/// to `size` connections to it.
+pub fn open_pool(database_url: &str, size: u32) -> Result<DbPool, ArchiverError> {
+    // Every connection to an in-memory database is an empty database of its own
+    if !is_postgres_url(database_url) && database_file(database_url).is_none() {
+        return Err(ArchiverError::InvalidInput(format!(
+            "{} is not a database file",
+            database_url
+        )));
+    }
+    // Creates and migrates the database once, before any pooled connection
+    drop(open_database(database_url)?);
+
+    Pool::builder()
+        .max_size(size.max(1))
+        .connection_customizer(Box::new(ConnectionOptions))
+        .build(CatalogManager {
+            database_url: database_url.to_string(),
+        })
+        .map_err(|e| ArchiverError::Connection(format!("{}: {}", database_url, e)))
+}
+
+/// Opens the pooled connections to a catalog, of the kind its URL names.
+#[derive(Debug)]
+pub struct CatalogManager {
+    database_url: String,
+}
+
+impl ManageConnection for CatalogManager {
+    type Connection = AnyConnection;
+    type Error = diesel::r2d2::Error;
+
+    fn connect(&self) -> Result<AnyConnection, Self::Error> {
+        establish(&self.database_url).map_err(diesel::r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut AnyConnection) -> Result<(), Self::Error> {
+        conn.ping().map_err(diesel::r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut AnyConnection) -> bool {
+        thread::panicking() || conn.is_broken()
+    }
+}
+
+// Settings that last only as long as a connection, applied to every pooled one
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<AnyConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut AnyConnection) -> Result<(), diesel::r2d2::Error> {
+        match conn {
+            AnyConnection::Sqlite(conn) => conn.batch_execute(&format!(
+                "PRAGMA busy_timeout = {}; PRAGMA foreign_keys = ON;",
+                BUSY_TIMEOUT_MS
+            )),
+            #[cfg(feature = "postgres")]
+            AnyConnection::Postgresql(conn) => conn.batch_execute("SET TIME ZONE 'UTC';"),
+        }
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Run `write` again, after a pause, as long as it fails because another
+/// connection holds the database. Give it a whole transaction, so a retry
+/// starts from scratch.
+pub fn retry_on_busy<T>(
+    mut write: impl FnMut() -> Result<T, ArchiverError>,
+) -> Result<T, ArchiverError> {
+    let mut delay = BUSY_RETRY_DELAY;
+    for _ in 0..BUSY_RETRIES {
+        match write() {
+            Err(e) if is_busy(&e) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    write()
+}
+
+// SQLite reports SQLITE_BUSY and SQLITE_LOCKED without a kind of their own.
+// PostgreSQL waits for row locks instead, so only a conflict between two
+// serializable transactions would be worth retrying.
+fn is_busy(e: &ArchiverError) -> bool {
+    match e {
+        ArchiverError::Database(DieselError::DatabaseError(DatabaseErrorKind::Unknown, info)) => {
+            let message = info.message();
+            message.contains("database is locked") || message.contains("database table is locked")
+        }
+        ArchiverError::Database(DieselError::DatabaseError(
+            DatabaseErrorKind::SerializationFailure,
+            _,
+        )) => true,
+        _ => false,
+    }
+}
+
+/// Start a transaction that writes. On SQLite it takes the write lock up
+/// front, so two writers cannot both read and then fail to upgrade their
+/// locks; PostgreSQL locks the rows as they are written.
+pub fn write_transaction<T>(
+    conn: &mut AnyConnection,
+    write: impl FnOnce(&mut AnyConnection) -> Result<T, ArchiverError>,
+) -> Result<T, ArchiverError> {
+    match &mut *conn {
+        // What `SqliteConnection::immediate_transaction` does, but handing
+        // `write` the connection it was given
+        AnyConnection::Sqlite(sqlite) => {
+            AnsiTransactionManager::begin_transaction_sql(sqlite, "BEGIN IMMEDIATE")?;
+            match write(conn) {
+                Ok(value) => {
+                    <AnyConnection as Connection>::TransactionManager::commit_transaction(conn)?;
+                    Ok(value)
+                }
+                Err(e) => {
+                    <AnyConnection as Connection>::TransactionManager::rollback_transaction(conn)?;
+                    Err(e)
+                }
+            }
+        }
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(_) => conn.transaction(write),
+    }
+}
+
+/// Add a chunk to the `chunks` table unless it is there already.
+pub(crate) fn insert_chunk(conn: &mut AnyConnection, chunk: &NewChunk) -> QueryResult<usize> {
+    use schema::chunks;
+
+    match conn {
+        AnyConnection::Sqlite(conn) => diesel::insert_or_ignore_into(chunks::table)
+            .values(chunk)
+            .execute(conn),
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(conn) => diesel::insert_into(chunks::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn),
+    }
+}
+
+#[derive(QueryableByName)]
+struct DatabaseName {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    name: String,
+}
+
+/// Where a connection's database lives: the file of an SQLite catalog, the
+/// server and database name of a PostgreSQL one. Two connections to the same
+/// catalog give the same answer.
+pub fn database_path(conn: &mut AnyConnection) -> Result<String, ArchiverError> {
+    let query = match conn {
+        AnyConnection::Sqlite(_) => {
+            "SELECT file AS name FROM pragma_database_list WHERE name = 'main'"
+        }
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(_) => {
+            "SELECT COALESCE(host(inet_server_addr()), 'localhost') || ':' \
+             || current_setting('port') || '/' || current_database() AS name"
+        }
+    };
+    let database = diesel::sql_query(query).get_result::<DatabaseName>(conn)?;
+    Ok(database.name)
+}
+
+// The file behind a database URL, if it is a plain path
+fn database_file(database_url: &str) -> Option<&Path> {
+    if is_postgres_url(database_url) {
+        return None;
+    }
+    let path = database_url
+        .strip_prefix("sqlite://")
+        .unwrap_or(database_url);
+    (path != ":memory:" && !path.starts_with("file:")).then(|| Path::new(path))
+}
+
+/// Apply the migrations the database does not have yet, returning their
+/// versions. A database with migrations this program does not know was
+/// written by a newer version, and is refused rather than used with a schema
+/// the program does not understand.
+pub fn run_migrations(conn: &mut AnyConnection) -> Result<Vec<String>, ArchiverError> {
+    match conn {
+        AnyConnection::Sqlite(conn) => migrate(conn, MIGRATIONS),
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(conn) => migrate(conn, POSTGRES_MIGRATIONS),
+    }
+}
+
+fn migrate<DB: Backend>(
+    conn: &mut impl MigrationHarness<DB>,
+    migrations: EmbeddedMigrations,
+) -> Result<Vec<String>, ArchiverError> {
+    let known: Vec<String> = MigrationSource::<DB>::migrations(&migrations)
+        .map_err(|e| ArchiverError::Migration(e.to_string()))?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    let applied: Vec<String> = conn
+        .applied_migrations()
+        .map_err(|e| ArchiverError::Migration(e.to_string()))?
+        .iter()
+        .map(|version| version.to_string())
+        .collect();
+
+    let unknown: Vec<String> = applied
+        .into_iter()
+        .filter(|version| !known.contains(version))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(ArchiverError::SchemaTooNew(unknown));
+    }
+
+    let ran = conn
+        .run_pending_migrations(migrations)
+        .map_err(|e| ArchiverError::Migration(e.to_string()))?;
+    Ok(ran.iter().map(|version| version.to_string()).collect())
+}
+
+// Insert `rows` into `table` a batch at a time. Inserting several rows in
+// one statement is only possible on a connection of a known kind.
+macro_rules! copy_rows {
+    ($to:expr, $table:expr, $rows:expr) => {
+        for batch in $rows.chunks(COPY_BATCH) {
+            match &mut *$to {
+                AnyConnection::Sqlite(to) => {
+                    diesel::insert_into($table).values(batch).execute(to)?
+                }
+                #[cfg(feature = "postgres")]
+                AnyConnection::Postgresql(to) => {
+                    diesel::insert_into($table).values(batch).execute(to)?
+                }
+            };
+        }
+    };
+}
+
+/// Copy every row of the catalog `from` into the empty catalog `to`, keeping
+/// ids, e.g. to move an SQLite catalog to a shared PostgreSQL database. Both
+/// are at the latest schema version once opened with [`open_database`]. The
+/// copy is one transaction, so `to` ends up with all of it or none. Returns
+/// how many rows each table had.
+pub fn copy_catalog(
+    from: &mut AnyConnection,
+    to: &mut AnyConnection,
+) -> Result<Vec<(&'static str, usize)>, ArchiverError> {
+    use schema::{
+        chunks, file_chunks, files, job_items, jobs, legal_holds, profiles, scrub_schedules,
+        verifications,
+    };
+
+    let existing: i64 = profiles::table.count().get_result(to)?;
+    if existing > 0 {
+        return Err(ArchiverError::InvalidInput(String::from(
+            "the destination catalog is not empty",
+        )));
+    }
+
+    // Each table is read whole, then written before the tables that refer to it
+    let profile_rows = profiles::table.order(profiles::id).load::<Profile>(from)?;
+    let hold_rows = legal_holds::table
+        .order(legal_holds::id)
+        .load::<LegalHold>(from)?;
+    let file_rows = files::table.order(files::id).load::<File>(from)?;
+    let chunk_rows = chunks::table.order(chunks::digest).load::<Chunk>(from)?;
+    let file_chunk_rows = file_chunks::table
+        .order(file_chunks::id)
+        .load::<FileChunk>(from)?;
+    let verification_rows = verifications::table
+        .order(verifications::id)
+        .load::<Verification>(from)?;
+    let schedule_rows = scrub_schedules::table
+        .order(scrub_schedules::id)
+        .load::<ScrubSchedule>(from)?;
+    let job_rows = jobs::table.order(jobs::id).load::<Job>(from)?;
+    let job_item_rows = job_items::table
+        .order(job_items::id)
+        .load::<JobItem>(from)?;
+
+    to.transaction(|to| {
+        copy_rows!(to, profiles::table, profile_rows);
+        copy_rows!(to, legal_holds::table, hold_rows);
+        copy_rows!(to, files::table, file_rows);
+        copy_rows!(to, chunks::table, chunk_rows);
+        copy_rows!(to, file_chunks::table, file_chunk_rows);
+        copy_rows!(to, verifications::table, verification_rows);
+        copy_rows!(to, scrub_schedules::table, schedule_rows);
+        copy_rows!(to, jobs::table, job_rows);
+        copy_rows!(to, job_items::table, job_item_rows);
+
+        // PostgreSQL hands out ids from sequences, which the copied ids bypassed
+        #[cfg(feature = "postgres")]
+        if let AnyConnection::Postgresql(to) = to {
+            for table in [
+                "profiles",
+                "legal_holds",
+                "files",
+                "file_chunks",
+                "verifications",
+                "scrub_schedules",
+                "jobs",
+                "job_items",
+            ] {
+                to.batch_execute(&format!(
+                    "SELECT setval(pg_get_serial_sequence('{0}', 'id'), \
+                     COALESCE(MAX(id), 1), MAX(id) IS NOT NULL) FROM {0};",
+                    table
+                ))?;
+            }
+        }
+
+        Ok::<_, ArchiverError>(vec![
+            ("profiles", profile_rows.len()),
+            ("legal_holds", hold_rows.len()),
+            ("files", file_rows.len()),
+            ("chunks", chunk_rows.len()),
+            ("file_chunks", file_chunk_rows.len()),
+            ("verifications", verification_rows.len()),
+            ("scrub_schedules", schedule_rows.len()),
+            ("jobs", job_rows.len()),
+            ("job_items", job_item_rows.len()),
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewLegalHold, NewProfile, NewVerification};
+    use chrono::NaiveDateTime;
+
+    // The migration that made paths unique
+    const UNIQUE_PATHS_MIGRATION: &str = "20261018170000";
+
+    fn at(hour: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn file(id: i32, profile_id: i32, path: &str, digest: &str, added: u32) -> File {
+        File {
+            id,
+            file_name: path.to_string(),
+            sha256: digest.repeat(64),
+            created_at: at(added),
+            updated_at: at(added),
+            profile_id,
+            removed_at: None,
+            last_verified_at: None,
+            hash_algorithm: "blake3".to_string(),
+            modified_at: None,
+        }
+    }
+
+    // A catalog from before paths were unique, with profile 1 tracking /a
+    // three times and /c twice after it was removed once, and profile 2,
+    // under legal hold, tracking /b twice
+    fn old_catalog(database: &str) {
+        use schema::{files, legal_holds, profiles, verifications};
+
+        drop(open_database(database).unwrap());
+        let mut conn = SqliteConnection::establish(database).unwrap();
+        while conn
+            .applied_migrations()
+            .unwrap()
+            .iter()
+            .any(|version| version.to_string() == UNIQUE_PATHS_MIGRATION)
+        {
+            conn.revert_last_migration(MIGRATIONS).unwrap();
+        }
+
+        for profile_name in ["free", "held"] {
+            diesel::insert_into(profiles::table)
+                .values(&NewProfile { profile_name })
+                .execute(&mut conn)
+                .unwrap();
+        }
+        let mut removed = file(6, 1, "/c", "1", 0);
+        removed.removed_at = Some(at(4));
+        for row in [
+            file(1, 1, "/a", "1", 0),
+            file(2, 1, "/a", "1", 1),
+            file(3, 1, "/a", "2", 2),
+            file(4, 2, "/b", "3", 0),
+            file(5, 2, "/b", "4", 1),
+            removed,
+            file(7, 1, "/c", "1", 1),
+            file(8, 1, "/c", "2", 2),
+        ] {
+            diesel::insert_into(files::table)
+                .values(&row)
+                .execute(&mut conn)
+                .unwrap();
+        }
+        diesel::insert_into(verifications::table)
+            .values(&NewVerification {
+                file_id: 2,
+                ok: true,
+                detail: None,
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(legal_holds::table)
+            .values(&NewLegalHold {
+                profile_id: 2,
+                held_by: "counsel",
+                hold_reason: "litigation",
+            })
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    #[test]
+    fn upgrading_keeps_the_newest_row_of_a_path() {
+        use schema::{files, verifications};
+
+        let dir = std::env::temp_dir().join(format!("tsk-db-{}-unique", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let database = dir.join("catalog.db").display().to_string();
+        old_catalog(&database);
+
+        let mut conn = open_database(&database).unwrap();
+        let rows: Vec<File> = files::table.order(files::id).load(&mut conn).unwrap();
+        // Every row is kept, those superseded as history from when the next
+        // one was added, under legal hold too
+        let removed: Vec<(i32, Option<NaiveDateTime>)> =
+            rows.iter().map(|row| (row.id, row.removed_at)).collect();
+        assert_eq!(
+            removed,
+            [
+                (1, Some(at(1))),
+                (2, Some(at(2))),
+                (3, None),
+                (4, Some(at(1))),
+                (5, None),
+                (6, Some(at(4))),
+                (7, Some(at(2))),
+                (8, None),
+            ]
+        );
+        let verified: Vec<i32> = verifications::table
+            .select(verifications::file_id)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(verified, [2]);
+
+        // Now a second current row of a path is refused
+        assert!(diesel::insert_into(files::table)
+            .values(&file(9, 1, "/a", "3", 3))
+            .execute(&mut conn)
+            .is_err());
+        drop(conn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Whole-file digests, as stored in `files.sha256`.
+//!
+//! Files archived before `files.hash_algorithm` existed use SHA3-256. New
+//! files default to BLAKE3, whose tree structure lets one large file be hashed
+//! on every core; large files on disk are read in pieces big enough for that.
+
+use sha3::{Digest, Sha3_256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::ArchiverError;
+use crate::models::File;
+use crate::scrub::Throttle;
+
+// Below this many bytes, splitting BLAKE3 work across threads costs more than
+// it saves.
+const PARALLEL_THRESHOLD: usize = 4 * 1024 * 1024;
+
+const READ_BUFFER: usize = 1024 * 1024;
+
+// How much of a file of at least `PARALLEL_THRESHOLD` bytes is read, and
+// hashed across threads, at a time.
+const LARGE_READ_BUFFER: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha3_256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sha3-256" => Some(HashAlgorithm::Sha3_256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// The algorithm for newly added files: the `hash_algorithm` setting,
+    /// else BLAKE3.
+    pub fn for_new_files() -> Self {
+        crate::config::settings().hash_algorithm
+    }
+}
+
+impl File {
+    /// The algorithm this file's digest was computed with.
+    pub fn digest_algorithm(&self) -> Result<HashAlgorithm, ArchiverError> {
+        HashAlgorithm::parse(&self.hash_algorithm).ok_or_else(|| {
+            ArchiverError::InvalidInput(format!("Unknown hash algorithm {}", self.hash_algorithm))
+        })
+    }
+}
+
+/// An incremental whole-file digest. BLAKE3 input is gathered into large
+/// pieces so each is hashed across threads.
+pub enum FileHasher {
+    Sha3(Box<Sha3_256>),
+    Blake3 {
+        hasher: Box<blake3::Hasher>,
+        pending: Vec<u8>,
+    },
+}
+
+impl FileHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha3_256 => FileHasher::Sha3(Box::new(Sha3_256::new())),
+            HashAlgorithm::Blake3 => FileHasher::Blake3 {
+                hasher: Box::new(blake3::Hasher::new()),
+                pending: Vec::new(),
+            },
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            FileHasher::Sha3(hasher) => hasher.update(data),
+            FileHasher::Blake3 { hasher, pending } => {
+                if pending.is_empty() && data.len() >= PARALLEL_THRESHOLD {
+                    hasher.update_rayon(data);
+                    return;
+                }
+                pending.extend_from_slice(data);
+                if pending.len() >= PARALLEL_THRESHOLD {
+                    hasher.update_rayon(pending);
+                    pending.clear();
+                }
+            }
+        }
+    }
+
+    /// The digest as lowercase hex.
+    pub fn finalize(self) -> String {
+        match self {
+            FileHasher::Sha3(hasher) => format!("{:x}", hasher.finalize()),
+            FileHasher::Blake3 {
+                mut hasher,
+                pending,
+            } => {
+                hasher.update_rayon(&pending);
+                hasher.finalize().to_hex().to_string()
+            }
+        }
+    }
+}
+
+/// Hash the file at `path`, calling `on_progress` with the number of bytes
+/// hashed so far as it goes.
+///
+/// Files are read rather than memory-mapped: a file truncated while it is
+/// hashed, as can happen during a scrub, then only ends early, where reading
+/// a mapping past its new end would kill the whole process.
+pub fn hash_path(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    mut throttle: Option<&mut Throttle>,
+    mut on_progress: impl FnMut(u64),
+) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = FileHasher::new(algorithm);
+    let mut hashed: u64 = 0;
+
+    let mut buffer = match file.metadata()?.len() {
+        len if len >= PARALLEL_THRESHOLD as u64 => vec![0u8; LARGE_READ_BUFFER],
+        _ => vec![0u8; READ_BUFFER],
+    };
+    loop {
+        match read_full(&mut file, &mut buffer)? {
+            0 => break,
+            n => {
+                if let Some(throttle) = throttle.as_mut() {
+                    throttle.consume(n);
+                }
+                hasher.update(&buffer[..n]);
+                hashed += n as u64;
+                on_progress(hashed);
+            }
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+// Fill `buffer` as far as the file goes, so large files reach the hasher in
+// pieces it can split across threads
+fn read_full(file: &mut fs::File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Not a repeating pattern, so misplaced pieces change the digest
+    fn contents(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x2545_f491;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blake3_digests_match_the_reference_around_the_threshold() {
+        let dir = std::env::temp_dir().join(format!("tsk-digest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for len in [
+            0,
+            PARALLEL_THRESHOLD - 1,
+            PARALLEL_THRESHOLD,
+            PARALLEL_THRESHOLD + 1,
+            LARGE_READ_BUFFER + PARALLEL_THRESHOLD + 7,
+        ] {
+            let data = contents(len);
+            let expected = blake3::hash(&data).to_hex().to_string();
+
+            let path = dir.join(format!("{}.bin", len));
+            fs::write(&path, &data).unwrap();
+            let mut reported = 0;
+            let digest =
+                hash_path(&path, HashAlgorithm::Blake3, None, |bytes| reported = bytes).unwrap();
+            assert_eq!(digest, expected, "{} bytes", len);
+            assert_eq!(reported, len as u64);
+
+            // Fed in pieces of every size the hasher treats differently
+            for piece in [1000, PARALLEL_THRESHOLD - 1, PARALLEL_THRESHOLD] {
+                let mut hasher = FileHasher::new(HashAlgorithm::Blake3);
+                for part in data.chunks(piece) {
+                    hasher.update(part);
+                }
+                assert_eq!(hasher.finalize(), expected, "{} bytes by {}", len, piece);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sha3_digests_match_the_reference() {
+        let data = contents(PARALLEL_THRESHOLD + 3);
+        let mut hasher = FileHasher::new(HashAlgorithm::Sha3_256);
+        for part in data.chunks(PARALLEL_THRESHOLD / 3) {
+            hasher.update(part);
+        }
+        assert_eq!(hasher.finalize(), format!("{:x}", Sha3_256::digest(&data)));
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+use crate::models::LegalHold;
+
+#[derive(Debug)]
+pub enum ArchiverError {
+    Database(diesel::result::Error),
+    Connection(String),
+    Io(std::io::Error),
+    /// The profile is under an active legal hold and its records are write-once.
+    LegalHold(Box<LegalHold>),
+    /// The profile has no active legal hold to release.
+    NoLegalHold,
+    InvalidInput(String),
+    /// A stored chunk no longer matches its digest.
+    CorruptChunk(String),
+    /// A stored object other than a chunk failed to decrypt.
+    CorruptObject(String),
+    /// Reassembled or re-read content does not match the digest in `files`.
+    DigestMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// No passphrase was given for the archive repository.
+    MissingPassphrase,
+    /// The passphrase does not unlock the archive repository.
+    WrongPassphrase,
+    Crypto(String),
+    /// A storage backend failed outside of plain I/O (HTTP, SSH, ...).
+    Storage(String),
+    /// The daemon refused a request or could not be talked to.
+    Daemon(String),
+    /// The database schema could not be brought up to date.
+    Migration(String),
+    /// The database has schema versions this program does not know, so it
+    /// was written by a newer version.
+    SchemaTooNew(Vec<String>),
+}
+
+impl fmt::Display for ArchiverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiverError::Database(e) => write!(f, "database error: {}", e),
+            ArchiverError::Connection(msg) => write!(f, "cannot connect to database {}", msg),
+            ArchiverError::Io(e) => write!(f, "I/O error: {}", e),
+            ArchiverError::LegalHold(hold) => write!(
+                f,
+                "profile is under legal hold (set by {} at {}: {})",
+                hold.held_by,
+                // PostgreSQL keeps fractions of a second, SQLite does not
+                hold.created_at.format("%Y-%m-%d %H:%M:%S"),
+                hold.hold_reason
+            ),
+            ArchiverError::NoLegalHold => write!(f, "profile is not under legal hold"),
+            ArchiverError::InvalidInput(msg) => write!(f, "{}", msg),
+            ArchiverError::CorruptChunk(digest) => write!(f, "chunk {} is corrupt", digest),
+            ArchiverError::CorruptObject(key) => write!(f, "object {} is corrupt", key),
+            ArchiverError::DigestMismatch { expected, actual } => {
+                write!(f, "digest mismatch: expected {}, got {}", expected, actual)
+            }
+            ArchiverError::MissingPassphrase => {
+                write!(f, "repository passphrase not set (ARCHIVE_PASSPHRASE)")
+            }
+            ArchiverError::WrongPassphrase => write!(f, "wrong repository passphrase"),
+            ArchiverError::Crypto(msg) => write!(f, "encryption error: {}", msg),
+            ArchiverError::Storage(msg) => write!(f, "storage error: {}", msg),
+            ArchiverError::Daemon(msg) => write!(f, "daemon error: {}", msg),
+            ArchiverError::Migration(msg) => write!(f, "database migration failed: {}", msg),
+            ArchiverError::SchemaTooNew(versions) => write!(
+                f,
+                "the database was written by a newer version of this program \
+                 (unknown schema versions {}); upgrade to use it",
+                versions.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArchiverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiverError::Database(e) => Some(e),
+            ArchiverError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ArchiverError {
+    fn from(e: diesel::result::Error) -> Self {
+        ArchiverError::Database(e)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for ArchiverError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        ArchiverError::Connection(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for ArchiverError {
+    fn from(e: std::io::Error) -> Self {
+        ArchiverError::Io(e)
+    }
+}
@@ -0,0 +1,355 @@
+//! A directory tree over a profile's tracked paths, with file counts, sizes
+//! and verification status rolled up per folder.
+//!
+//! Folders that hold nothing but a single subfolder are merged into one node,
+//! so `/home/me/photos` is one level rather than three.
+
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::is_separator;
+
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::get_files;
+use crate::models::File;
+use crate::schema::{chunks, file_chunks, files, verifications};
+
+/// How a file fared when last verified. For a folder, the worst of its files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VerificationStatus {
+    Ok,
+    Unverified,
+    Failed,
+}
+
+impl VerificationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationStatus::Ok => "ok",
+            VerificationStatus::Unverified => "unverified",
+            VerificationStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub file: File,
+    pub size: u64,
+    pub status: VerificationStatus,
+}
+
+impl FileEntry {
+    /// The last component of the file's path.
+    pub fn name(&self) -> &str {
+        self.file
+            .file_name
+            .rsplit(is_separator)
+            .next()
+            .unwrap_or(&self.file.file_name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Folder {
+    /// The folder's name, or several joined with `/` where folders were merged.
+    pub name: String,
+    /// The folder's path from the top of the tree, which identifies it.
+    pub path: String,
+    pub folders: Vec<Folder>,
+    /// Indices into [`FileTree::entries`], sorted by name.
+    pub files: Vec<usize>,
+    pub file_count: usize,
+    pub total_size: u64,
+    pub status: VerificationStatus,
+}
+
+/// Identifies a row of the tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TreeKey {
+    Folder(String),
+    File(i32),
+}
+
+pub enum TreeNode<'a> {
+    Folder(&'a Folder),
+    File(&'a FileEntry),
+    /// Stands for the files of a folder beyond the number shown.
+    More {
+        folder: &'a Folder,
+        hidden: usize,
+    },
+}
+
+/// One visible line of the tree.
+pub struct TreeRow<'a> {
+    pub depth: usize,
+    pub node: TreeNode<'a>,
+}
+
+impl TreeRow<'_> {
+    /// The row's key. A "more" row shares its folder's key.
+    pub fn key(&self) -> TreeKey {
+        match &self.node {
+            TreeNode::Folder(folder) | TreeNode::More { folder, .. } => {
+                TreeKey::Folder(folder.path.clone())
+            }
+            TreeNode::File(entry) => TreeKey::File(entry.file.id),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FileTree {
+    pub root: Folder,
+    pub entries: Vec<FileEntry>,
+}
+
+impl fmt::Debug for FileTree {
+    // The whole tree is far too long to print
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FileTree({} files)", self.entries.len())
+    }
+}
+
+impl Default for FileTree {
+    fn default() -> Self {
+        FileTree::new(Vec::new(), &HashMap::new(), &HashMap::new())
+    }
+}
+
+// A folder while the tree is being built
+#[derive(Default)]
+struct Builder {
+    folders: BTreeMap<String, Builder>,
+    files: Vec<usize>,
+}
+
+impl FileTree {
+    /// Build a tree from `files`, with each file's size in bytes and whether
+    /// its latest verification passed, where known.
+    pub fn new(files: Vec<File>, sizes: &HashMap<i32, u64>, statuses: &HashMap<i32, bool>) -> Self {
+        let entries: Vec<FileEntry> = files
+            .into_iter()
+            .map(|file| FileEntry {
+                size: sizes.get(&file.id).copied().unwrap_or(0),
+                status: match statuses.get(&file.id) {
+                    Some(true) => VerificationStatus::Ok,
+                    Some(false) => VerificationStatus::Failed,
+                    None => VerificationStatus::Unverified,
+                },
+                file,
+            })
+            .collect();
+
+        let mut root = Builder::default();
+        for (index, entry) in entries.iter().enumerate() {
+            let mut parts: Vec<&str> = entry
+                .file
+                .file_name
+                .split(is_separator)
+                .filter(|part| !part.is_empty())
+                .collect();
+            parts.pop();
+            let folder = parts.into_iter().fold(&mut root, |folder, part| {
+                folder.folders.entry(part.to_string()).or_default()
+            });
+            folder.files.push(index);
+        }
+
+        let mut tree = FileTree {
+            root: build_folder(String::new(), String::new(), root, &entries),
+            entries,
+        };
+        tree.root.folders = tree
+            .root
+            .folders
+            .drain(..)
+            .map(merge_single_folders)
+            .collect();
+        update_totals(&mut tree.root, &tree.entries);
+        tree
+    }
+
+    /// The rows to show with the folders in `expanded` open, listing at most
+    /// `limit(folder)` files of each folder.
+    pub fn rows(
+        &self,
+        expanded: &HashSet<String>,
+        limit: impl Fn(&Folder) -> usize,
+    ) -> Vec<TreeRow<'_>> {
+        let mut rows = Vec::new();
+        self.push_rows(&self.root, 0, expanded, &limit, &mut rows);
+        rows
+    }
+
+    fn push_rows<'a>(
+        &'a self,
+        folder: &'a Folder,
+        depth: usize,
+        expanded: &HashSet<String>,
+        limit: &impl Fn(&Folder) -> usize,
+        rows: &mut Vec<TreeRow<'a>>,
+    ) {
+        for child in &folder.folders {
+            rows.push(TreeRow {
+                depth,
+                node: TreeNode::Folder(child),
+            });
+            if expanded.contains(&child.path) {
+                self.push_rows(child, depth + 1, expanded, limit, rows);
+            }
+        }
+        let shown = limit(folder).min(folder.files.len());
+        for &index in &folder.files[..shown] {
+            rows.push(TreeRow {
+                depth,
+                node: TreeNode::File(&self.entries[index]),
+            });
+        }
+        if shown < folder.files.len() {
+            rows.push(TreeRow {
+                depth,
+                node: TreeNode::More {
+                    folder,
+                    hidden: folder.files.len() - shown,
+                },
+            });
+        }
+    }
+
+    pub fn folder(&self, path: &str) -> Option<&Folder> {
+        fn find<'a>(folder: &'a Folder, path: &str) -> Option<&'a Folder> {
+            if folder.path == path {
+                return Some(folder);
+            }
+            folder
+                .folders
+                .iter()
+                .filter(|child| path.starts_with(&child.path))
+                .find_map(|child| find(child, path))
+        }
+        find(&self.root, path)
+    }
+
+    pub fn entry(&self, file_id: i32) -> Option<&FileEntry> {
+        self.entries.iter().find(|entry| entry.file.id == file_id)
+    }
+
+    /// The ids of every file in `folder` and its subfolders.
+    pub fn file_ids_under(&self, folder: &Folder) -> Vec<i32> {
+        let mut ids: Vec<i32> = folder
+            .files
+            .iter()
+            .map(|&index| self.entries[index].file.id)
+            .collect();
+        for child in &folder.folders {
+            ids.extend(self.file_ids_under(child));
+        }
+        ids
+    }
+
+    /// Record a new verification result for a file.
+    pub fn set_status(&mut self, file_id: i32, ok: bool) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.file.id == file_id)
+        {
+            entry.status = if ok {
+                VerificationStatus::Ok
+            } else {
+                VerificationStatus::Failed
+            };
+            entry.file.last_verified_at = Some(chrono::Utc::now().naive_utc());
+            update_totals(&mut self.root, &self.entries);
+        }
+    }
+}
+
+fn build_folder(name: String, path: String, builder: Builder, entries: &[FileEntry]) -> Folder {
+    let mut files = builder.files;
+    files.sort_by(|a, b| entries[*a].name().cmp(entries[*b].name()));
+    Folder {
+        folders: builder
+            .folders
+            .into_iter()
+            .map(|(child, child_builder)| {
+                let child_path = if path.is_empty() {
+                    child.clone()
+                } else {
+                    format!("{}/{}", path, child)
+                };
+                build_folder(child, child_path, child_builder, entries)
+            })
+            .collect(),
+        name,
+        path,
+        files,
+        file_count: 0,
+        total_size: 0,
+        status: VerificationStatus::Ok,
+    }
+}
+
+fn merge_single_folders(mut folder: Folder) -> Folder {
+    while folder.files.is_empty() && folder.folders.len() == 1 {
+        let child = folder.folders.pop().unwrap();
+        folder = Folder {
+            name: format!("{}/{}", folder.name, child.name),
+            ..child
+        };
+    }
+    folder.folders = folder.folders.drain(..).map(merge_single_folders).collect();
+    folder
+}
+
+fn update_totals(folder: &mut Folder, entries: &[FileEntry]) {
+    folder.file_count = folder.files.len();
+    folder.total_size = folder.files.iter().map(|&index| entries[index].size).sum();
+    folder.status = folder
+        .files
+        .iter()
+        .map(|&index| entries[index].status)
+        .max()
+        .unwrap_or(VerificationStatus::Ok);
+    for child in &mut folder.folders {
+        update_totals(child, entries);
+        folder.file_count += child.file_count;
+        folder.total_size += child.total_size;
+        folder.status = folder.status.max(child.status);
+    }
+}
+
+/// The tracked files of a profile as a tree, with their sizes and latest
+/// verification results.
+pub fn load_file_tree(conn: DbPool, pid: &i32) -> Result<FileTree, ArchiverError> {
+    let tracked = get_files(conn.clone(), pid);
+    let mut conn = conn.get()?;
+
+    let sizes: Vec<(i32, Option<i64>)> = file_chunks::table
+        .inner_join(chunks::table)
+        .inner_join(files::table)
+        .filter(files::profile_id.eq(pid))
+        .filter(files::removed_at.is_null())
+        .group_by(file_chunks::file_id)
+        .select((file_chunks::file_id, diesel::dsl::sum(chunks::size)))
+        .load(&mut *conn)?;
+    let sizes: HashMap<i32, u64> = sizes
+        .into_iter()
+        .map(|(file_id, size)| (file_id, size.unwrap_or(0) as u64))
+        .collect();
+
+    let results: Vec<(i32, bool)> = verifications::table
+        .inner_join(files::table)
+        .filter(files::profile_id.eq(pid))
+        .filter(files::removed_at.is_null())
+        .order(verifications::id.asc())
+        .select((verifications::file_id, verifications::ok))
+        .load(&mut *conn)?;
+    // Later results overwrite earlier ones, leaving each file's latest
+    let statuses: HashMap<i32, bool> = results.into_iter().collect();
+
+    Ok(FileTree::new(tracked, &sizes, &statuses))
+}
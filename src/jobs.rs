@@ -0,0 +1,1033 @@
+//! Long-running work recorded in the `jobs` and `job_items` tables.
+//!
+//! A job is a list of items (file paths to ingest, or file ids to verify)
+//! that are processed in order, each ending up `done` or `failed` with a
+//! reason. Ingest jobs read several files at once, one per worker, and write
+//! the states of their items in batches. Because every item's state is
+//! written soon after it is known, a job interrupted by a crash picks up at
+//! its pending items when it is run again, and cancelling it leaves the
+//! remaining items pending.
+//!
+//! A run claims its job by recording itself as the job's owner and keeps a
+//! heartbeat while it works, so a job is only ever run by one process at a
+//! time. A job whose heartbeat stopped counts as interrupted and can be
+//! claimed again.
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::db::{self, AnyConnection, DbPool};
+use crate::error::ArchiverError;
+use crate::models::{Job, JobItem, NewJob, NewJobItem};
+use crate::progress::{Progress, ProgressTracker};
+use crate::schema;
+use crate::scrub::{next_scrub_batch, try_get_scrub_schedule, Throttle};
+use crate::thread_pool::{JobHandle, ThreadPool};
+use crate::{
+    archive_file, get_files, open_profile_store, record_files, verify_file_throttled, ArchivedFile,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Ingest,
+    Verify,
+    Scrub,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Ingest => "ingest",
+            JobKind::Verify => "verify",
+            JobKind::Scrub => "scrub",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "ingest" => Some(JobKind::Ingest),
+            "verify" => Some(JobKind::Verify),
+            "scrub" => Some(JobKind::Scrub),
+            _ => None,
+        }
+    }
+}
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_COMPLETED: &str = "completed";
+pub const STATUS_CANCELLED: &str = "cancelled";
+
+pub const ITEM_PENDING: &str = "pending";
+pub const ITEM_DONE: &str = "done";
+pub const ITEM_FAILED: &str = "failed";
+
+// Ingested files are recorded once this many are archived, or once this long
+// has passed since the last batch
+const INGEST_BATCH: usize = 500;
+const INGEST_BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often a running job's heartbeat is refreshed, and how long after the
+// last one the job counts as interrupted
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const LEASE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A job together with how far it has got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub job: Job,
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+impl JobSummary {
+    /// Items processed so far, successfully or not.
+    pub fn processed(&self) -> usize {
+        self.done + self.failed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.job.status == STATUS_COMPLETED || self.job.status == STATUS_CANCELLED
+    }
+}
+
+fn create_job(
+    conn: &DbPool,
+    kind: JobKind,
+    pid: i32,
+    targets: &[String],
+) -> Result<Job, ArchiverError> {
+    use schema::{job_items, jobs};
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        let job = diesel::insert_into(jobs::table)
+            .values(&NewJob {
+                kind: kind.as_str(),
+                profile_id: pid,
+            })
+            .returning(jobs::all_columns)
+            .get_result::<Job>(conn)?;
+
+        for (seq, target) in targets.iter().enumerate() {
+            diesel::insert_into(job_items::table)
+                .values(&NewJobItem {
+                    job_id: job.id,
+                    seq: seq as i32,
+                    target,
+                })
+                .execute(conn)?;
+        }
+        Ok(job)
+    })
+}
+
+/// Record a job that archives `paths` into a profile. Run it with [`run_job`].
+pub fn create_ingest_job(conn: DbPool, pid: &i32, paths: &[String]) -> Result<Job, ArchiverError> {
+    create_job(&conn, JobKind::Ingest, *pid, paths)
+}
+
+/// Record a job that verifies every tracked file of a profile.
+pub fn create_verify_job(conn: DbPool, pid: &i32) -> Result<Job, ArchiverError> {
+    let file_ids: Vec<i32> = get_files(conn.clone(), pid)
+        .iter()
+        .map(|file| file.id)
+        .collect();
+    create_files_verify_job(conn, pid, &file_ids)
+}
+
+/// Record a job that verifies some of a profile's tracked files.
+pub fn create_files_verify_job(
+    conn: DbPool,
+    pid: &i32,
+    file_ids: &[i32],
+) -> Result<Job, ArchiverError> {
+    let targets: Vec<String> = file_ids.iter().map(|file_id| file_id.to_string()).collect();
+    create_job(&conn, JobKind::Verify, *pid, &targets)
+}
+
+/// Record a job that verifies the next scrub batch of a profile, and count it
+/// as the schedule's latest run.
+pub fn create_scrub_job(conn: DbPool, pid: &i32) -> Result<Job, ArchiverError> {
+    let batch = next_scrub_batch(conn.clone(), pid)?;
+    let file_ids: Vec<String> = batch.iter().map(|file| file.id.to_string()).collect();
+    create_job(&conn, JobKind::Scrub, *pid, &file_ids)
+}
+
+/// Process a job's pending items in order, calling `on_progress` as bytes are
+/// read. The latest progress of a running job is also available from
+/// [`job_progress`].
+///
+/// The job is claimed first; one that is finished, or being run by another
+/// thread or process, is left alone. Cancellation is checked before every
+/// item, or after every batch of an ingest job, so a job cancelled from
+/// another thread or process stops after the items it is working on.
+pub fn run_job(
+    conn: DbPool,
+    job_id: &i32,
+    mut on_progress: impl FnMut(&Progress),
+) -> Result<JobSummary, ArchiverError> {
+    use schema::{files, job_items, jobs};
+
+    let Some(lease) = Lease::claim(conn.clone(), *job_id)? else {
+        // Finished, or another run has it
+        return get_job(conn, job_id);
+    };
+    let job = {
+        let mut conn = conn.get()?;
+        jobs::table.find(job_id).first::<Job>(&mut *conn)?
+    };
+    let kind = JobKind::parse(&job.kind)
+        .ok_or_else(|| ArchiverError::InvalidInput(format!("Unknown job kind {}", job.kind)))?;
+
+    let mut throttle = match kind {
+        JobKind::Scrub => try_get_scrub_schedule(conn.clone(), &job.profile_id)?
+            .and_then(|schedule| Throttle::new(schedule.read_limit_mb as u32)),
+        _ => None,
+    };
+
+    let pending = {
+        let mut conn = conn.get()?;
+        job_items::table
+            .filter(job_items::job_id.eq(job_id))
+            .filter(job_items::state.eq(ITEM_PENDING))
+            .order(job_items::seq.asc())
+            .load::<JobItem>(&mut *conn)?
+    };
+
+    // The path each item reads, to show and to size up the work
+    let paths: Vec<String> = match kind {
+        JobKind::Ingest => pending.iter().map(|item| item.target.clone()).collect(),
+        JobKind::Verify | JobKind::Scrub => {
+            let ids: Vec<i32> = pending
+                .iter()
+                .filter_map(|item| item.target.parse().ok())
+                .collect();
+            let names: HashMap<i32, String> = {
+                let mut conn = conn.get()?;
+                files::table
+                    .filter(files::id.eq_any(&ids))
+                    .select((files::id, files::file_name))
+                    .load::<(i32, String)>(&mut *conn)?
+                    .into_iter()
+                    .collect()
+            };
+            pending
+                .iter()
+                .map(|item| {
+                    item.target
+                        .parse()
+                        .ok()
+                        .and_then(|id| names.get(&id).cloned())
+                        .unwrap_or_else(|| item.target.clone())
+                })
+                .collect()
+        }
+    };
+    let sizes: Vec<u64> = paths
+        .iter()
+        .map(|path| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0))
+        .collect();
+
+    let summary = get_job(conn.clone(), job_id)?;
+    let _live = LiveProgress(*job_id);
+    let mut tracker = ProgressTracker::new(
+        summary.total,
+        summary.processed(),
+        sizes.iter().sum(),
+        |progress: &Progress| {
+            live_progress()
+                .lock()
+                .unwrap()
+                .insert(*job_id, progress.clone());
+            // A single large file can take longer than the lease
+            lease.renew_if_due();
+            on_progress(progress);
+        },
+    );
+
+    let finished = match kind {
+        JobKind::Ingest => ingest_items(&conn, &lease, &job, &pending, &sizes, &mut tracker)?,
+        JobKind::Verify | JobKind::Scrub => verify_items(
+            &conn,
+            &lease,
+            &pending,
+            &paths,
+            &sizes,
+            throttle.as_mut(),
+            &mut tracker,
+        )?,
+    };
+    if !finished {
+        drop(lease);
+        return get_job(conn, job_id);
+    }
+    tracker.finish();
+
+    {
+        let mut conn = conn.get()?;
+        // A cancellation that raced the last item still wins
+        diesel::update(
+            jobs::table
+                .find(job_id)
+                .filter(jobs::status.eq(STATUS_RUNNING))
+                .filter(jobs::owner.eq(&lease.owner)),
+        )
+        .set((
+            jobs::status.eq(STATUS_COMPLETED),
+            jobs::finished_at.eq(diesel::dsl::now),
+            jobs::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut *conn)?;
+    }
+    drop(lease);
+    get_job(conn, job_id)
+}
+
+// Verify a job's pending items one after the other. Returns false if the job
+// was cancelled.
+fn verify_items<F: FnMut(&Progress)>(
+    conn: &DbPool,
+    lease: &Lease,
+    pending: &[JobItem],
+    paths: &[String],
+    sizes: &[u64],
+    mut throttle: Option<&mut Throttle>,
+    tracker: &mut ProgressTracker<F>,
+) -> Result<bool, ArchiverError> {
+    for ((item, path), size) in pending.iter().zip(paths).zip(sizes) {
+        if !lease.renew()? {
+            return Ok(false);
+        }
+
+        tracker.start_file(path);
+        let on_bytes = &mut |bytes: u64| tracker.file_bytes(bytes);
+        let result = match item.target.parse::<i32>() {
+            Ok(file_id) => {
+                match verify_file_throttled(
+                    conn.clone(),
+                    &file_id,
+                    throttle.as_deref_mut(),
+                    on_bytes,
+                ) {
+                    Ok(verification) => match verification.detail {
+                        None => Ok(()),
+                        Some(detail) => Err(detail),
+                    },
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            Err(_) => Err(format!("{} is not a file id", item.target)),
+        };
+
+        finish_item(
+            &mut *conn.get()?,
+            &lease.job_id,
+            item.id,
+            result.err().as_deref(),
+        )?;
+        tracker.finish_file(*size);
+    }
+    Ok(true)
+}
+
+// What an ingest worker tells the thread recording its results
+enum IngestEvent {
+    // Bytes read so far from the file of a pending item
+    Read(usize, u64),
+    Archived(usize, Result<ArchivedFile, String>),
+}
+
+// Archive an ingest job's pending items on the ingest pool, while this thread
+// records them in the catalog a batch at a time, each batch in a single
+// transaction. Returns false if the job was cancelled.
+fn ingest_items<F: FnMut(&Progress)>(
+    conn: &DbPool,
+    lease: &Lease,
+    job: &Job,
+    pending: &[JobItem],
+    sizes: &[u64],
+    tracker: &mut ProgressTracker<F>,
+) -> Result<bool, ArchiverError> {
+    if pending.is_empty() {
+        return Ok(true);
+    }
+    let store = open_profile_store(conn.clone(), &job.profile_id)?;
+    let (sender, receiver) = mpsc::channel();
+
+    let pool = ingest_pool();
+    let handles: Vec<JobHandle<()>> = pending
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let (store, target) = (Arc::clone(&store), item.target.clone());
+            let sender = sender.clone();
+            pool.spawn_cancellable(move |_| {
+                // A panic fails the item instead of leaving it unaccounted for
+                let mut outcome = ArchiveOutcome {
+                    sender,
+                    index,
+                    result: None,
+                };
+                let result = if Path::new(&target).exists() {
+                    let sender = outcome.sender.clone();
+                    let on_bytes = &mut |bytes: u64| {
+                        let _ = sender.send(IngestEvent::Read(index, bytes));
+                    };
+                    archive_file(&store, &target, on_bytes).map_err(|e| e.to_string())
+                } else {
+                    Err("no such file".to_string())
+                };
+                outcome.result = Some(result);
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let recorded = record_ingest_events(conn, lease, job, pending, sizes, receiver, tracker);
+    // Items not started yet are skipped, whether or not recording failed, and
+    // the ones being archived are waited for. One that panicked has already
+    // failed its item.
+    for handle in &handles {
+        handle.cancel();
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    recorded
+}
+
+// Sends an ingest item's result when dropped, also when archiving panicked
+struct ArchiveOutcome {
+    sender: mpsc::Sender<IngestEvent>,
+    index: usize,
+    result: Option<Result<ArchivedFile, String>>,
+}
+
+impl Drop for ArchiveOutcome {
+    fn drop(&mut self) {
+        let result = self
+            .result
+            .take()
+            .unwrap_or_else(|| Err("archiving panicked".to_string()));
+        let _ = self.sender.send(IngestEvent::Archived(self.index, result));
+    }
+}
+
+// The pool ingest jobs archive files on, shared by every job of this process
+// so they take `workers` threads between them. It follows the setting.
+fn ingest_pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+    let workers = config::settings().workers.max(1);
+    let pool = POOL.get_or_init(|| ThreadPool::new(workers));
+    if pool.size() != workers {
+        pool.resize(workers);
+    }
+    pool
+}
+
+fn record_ingest_events<F: FnMut(&Progress)>(
+    conn: &DbPool,
+    lease: &Lease,
+    job: &Job,
+    pending: &[JobItem],
+    sizes: &[u64],
+    receiver: mpsc::Receiver<IngestEvent>,
+    tracker: &mut ProgressTracker<F>,
+) -> Result<bool, ArchiverError> {
+    let mut read = vec![0; pending.len()];
+    let mut batch = Vec::new();
+    let mut last_write = Instant::now();
+
+    for event in receiver {
+        match event {
+            IngestEvent::Read(index, bytes) => {
+                tracker.add_bytes(&pending[index].target, bytes.saturating_sub(read[index]));
+                read[index] = bytes;
+            }
+            IngestEvent::Archived(index, result) => {
+                tracker.finish_concurrent_file(
+                    &pending[index].target,
+                    sizes[index].saturating_sub(read[index]),
+                );
+                batch.push((index, result));
+            }
+        }
+
+        if batch.len() >= INGEST_BATCH
+            || (!batch.is_empty() && last_write.elapsed() >= INGEST_BATCH_INTERVAL)
+        {
+            record_ingested(conn, job, pending, &batch)?;
+            batch.clear();
+            last_write = Instant::now();
+            if !lease.renew()? {
+                return Ok(false);
+            }
+        }
+    }
+    record_ingested(conn, job, pending, &batch)?;
+    Ok(true)
+}
+
+// Record a batch of archived items and their state in one transaction. If
+// the batch cannot be recorded, e.g. because one of its files is under legal
+// hold, its items are recorded one by one so only the culprits fail.
+fn record_ingested(
+    conn: &DbPool,
+    job: &Job,
+    pending: &[JobItem],
+    batch: &[(usize, Result<ArchivedFile, String>)],
+) -> Result<(), ArchiverError> {
+    let mut conn = conn.get()?;
+    let mut write = |entries: &[(usize, Result<&ArchivedFile, &str>)]| {
+        db::retry_on_busy(|| {
+            db::write_transaction(&mut conn, |conn| {
+                let archived: Vec<&ArchivedFile> = entries
+                    .iter()
+                    .filter_map(|(_, result)| result.ok())
+                    .collect();
+                record_files(conn, &job.profile_id, &archived)?;
+                for (index, result) in entries {
+                    finish_item(conn, &job.id, pending[*index].id, result.err())?;
+                }
+                Ok(())
+            })
+        })
+    };
+
+    let entries: Vec<(usize, Result<&ArchivedFile, &str>)> = batch
+        .iter()
+        .map(|(index, result)| (*index, result.as_ref().map_err(String::as_str)))
+        .collect();
+    if entries.is_empty() || write(&entries).is_ok() {
+        return Ok(());
+    }
+    for entry in entries {
+        if let Err(e) = write(&[entry]) {
+            write(&[(entry.0, Err(&e.to_string()))])?;
+        }
+    }
+    Ok(())
+}
+
+// A job claimed by one run of `run_job`. Other runs, in this process or
+// another, leave the job alone while its heartbeat is fresh. Dropping the
+// lease gives the job up, so it can be resumed straight away.
+struct Lease {
+    conn: DbPool,
+    job_id: i32,
+    owner: String,
+    last_beat: Cell<Instant>,
+}
+
+impl Lease {
+    // Claim a pending or running job that has no owner or whose owner's
+    // heartbeat stopped, in one statement so only one run can get it
+    fn claim(conn: DbPool, job_id: i32) -> Result<Option<Self>, ArchiverError> {
+        use schema::jobs;
+
+        let owner = new_owner();
+        let now = Utc::now().naive_utc();
+        let claimed = diesel::update(
+            jobs::table
+                .find(job_id)
+                .filter(jobs::status.eq_any([STATUS_PENDING, STATUS_RUNNING]))
+                .filter(
+                    jobs::owner
+                        .is_null()
+                        .or(jobs::heartbeat_at.lt(stale_before(now))),
+                ),
+        )
+        .set((
+            jobs::status.eq(STATUS_RUNNING),
+            jobs::owner.eq(&owner),
+            jobs::heartbeat_at.eq(now),
+            jobs::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut *conn.get()?)?;
+
+        Ok((claimed > 0).then(|| Lease {
+            conn,
+            job_id,
+            owner,
+            last_beat: Cell::new(Instant::now()),
+        }))
+    }
+
+    // Refresh the heartbeat. False once the job was cancelled, or claimed by
+    // another run after this one missed its heartbeats.
+    fn renew(&self) -> Result<bool, ArchiverError> {
+        use schema::jobs;
+
+        let renewed = diesel::update(
+            jobs::table
+                .find(self.job_id)
+                .filter(jobs::status.eq(STATUS_RUNNING))
+                .filter(jobs::owner.eq(&self.owner)),
+        )
+        .set(jobs::heartbeat_at.eq(Utc::now().naive_utc()))
+        .execute(&mut *self.conn.get()?)?;
+        self.last_beat.set(Instant::now());
+        Ok(renewed > 0)
+    }
+
+    // Refresh the heartbeat if it is due. Losing the job is noticed at the
+    // next item or batch.
+    fn renew_if_due(&self) {
+        if self.last_beat.get().elapsed() >= HEARTBEAT_INTERVAL {
+            let _ = self.renew();
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        use schema::jobs;
+
+        if let Ok(mut conn) = self.conn.get() {
+            let _ = diesel::update(
+                jobs::table
+                    .find(self.job_id)
+                    .filter(jobs::owner.eq(&self.owner)),
+            )
+            .set((
+                jobs::owner.eq(None::<String>),
+                jobs::heartbeat_at.eq(None::<NaiveDateTime>),
+            ))
+            .execute(&mut *conn);
+        }
+    }
+}
+
+// A name for one run of `run_job`, different for every run in every process
+fn new_owner() -> String {
+    static PROCESS: OnceLock<String> = OnceLock::new();
+    static RUNS: AtomicU64 = AtomicU64::new(0);
+
+    let process = PROCESS.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        format!("{}-{:x}", std::process::id(), started)
+    });
+    format!("{}-{}", process, RUNS.fetch_add(1, Ordering::Relaxed))
+}
+
+// Heartbeats older than this, as of `now`, belong to runs that stopped
+fn stale_before(now: NaiveDateTime) -> NaiveDateTime {
+    now - chrono::Duration::from_std(LEASE_TIMEOUT).unwrap()
+}
+
+// Record how an item ended, `reason` being why it failed
+fn finish_item(
+    conn: &mut AnyConnection,
+    job_id: &i32,
+    item_id: i32,
+    reason: Option<&str>,
+) -> Result<(), ArchiverError> {
+    use schema::{job_items, jobs};
+
+    let state = match reason {
+        None => ITEM_DONE,
+        Some(_) => ITEM_FAILED,
+    };
+    diesel::update(job_items::table.find(item_id))
+        .set((
+            job_items::state.eq(state),
+            job_items::reason.eq(reason),
+            job_items::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+    diesel::update(jobs::table.find(job_id))
+        .set(jobs::updated_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The latest progress of a job running in this process.
+pub fn job_progress(job_id: i32) -> Option<Progress> {
+    live_progress().lock().unwrap().get(&job_id).cloned()
+}
+
+fn live_progress() -> &'static Mutex<HashMap<i32, Progress>> {
+    static LIVE_PROGRESS: OnceLock<Mutex<HashMap<i32, Progress>>> = OnceLock::new();
+    LIVE_PROGRESS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Forgets a job's progress however `run_job` returns
+struct LiveProgress(i32);
+
+impl Drop for LiveProgress {
+    fn drop(&mut self) {
+        live_progress().lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Stop a pending or running job. Items it has not reached stay pending.
+pub fn cancel_job(conn: DbPool, job_id: &i32) -> Result<Job, ArchiverError> {
+    use schema::jobs::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    let cancelled = diesel::update(
+        jobs.find(job_id)
+            .filter(status.eq_any([STATUS_PENDING, STATUS_RUNNING])),
+    )
+    .set((
+        status.eq(STATUS_CANCELLED),
+        finished_at.eq(diesel::dsl::now),
+        updated_at.eq(diesel::dsl::now),
+    ))
+    .execute(&mut *conn)?;
+
+    let job = jobs.find(job_id).first::<Job>(&mut *conn)?;
+    if cancelled == 0 {
+        return Err(ArchiverError::InvalidInput(format!(
+            "Job {} is already {}",
+            job.id, job.status
+        )));
+    }
+    Ok(job)
+}
+
+pub fn get_job(conn: DbPool, job_id: &i32) -> Result<JobSummary, ArchiverError> {
+    use schema::jobs::dsl::*;
+
+    let job = {
+        let mut conn = conn.get()?;
+        jobs.find(job_id).first::<Job>(&mut *conn)?
+    };
+    Ok(summarize(conn, vec![job])?.remove(0))
+}
+
+pub fn get_job_items(conn: DbPool, jid: &i32) -> Result<Vec<JobItem>, ArchiverError> {
+    use schema::job_items::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    Ok(job_items
+        .filter(job_id.eq(jid))
+        .order(seq.asc())
+        .load::<JobItem>(&mut *conn)?)
+}
+
+/// Every job, or a profile's jobs, newest first.
+pub fn list_jobs(conn: DbPool, pid: Option<&i32>) -> Result<Vec<JobSummary>, ArchiverError> {
+    use schema::jobs::dsl::*;
+
+    let found = {
+        let mut conn = conn.get()?;
+        let mut query = jobs.order(id.desc()).into_boxed();
+        if let Some(pid) = pid {
+            query = query.filter(profile_id.eq(*pid));
+        }
+        query.load::<Job>(&mut *conn)?
+    };
+    summarize(conn, found)
+}
+
+/// Jobs that were pending or running when their process stopped, oldest
+/// first: those no run owns, or whose owner's heartbeat stopped. Pass each
+/// to [`run_job`] to carry on where it left off.
+pub fn interrupted_jobs(conn: DbPool) -> Result<Vec<Job>, ArchiverError> {
+    use schema::jobs::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    Ok(jobs
+        .filter(status.eq_any([STATUS_PENDING, STATUS_RUNNING]))
+        .filter(
+            owner
+                .is_null()
+                .or(heartbeat_at.lt(stale_before(Utc::now().naive_utc()))),
+        )
+        .order(id.asc())
+        .load::<Job>(&mut *conn)?)
+}
+
+fn summarize(conn: DbPool, found: Vec<Job>) -> Result<Vec<JobSummary>, ArchiverError> {
+    use schema::job_items::dsl::*;
+
+    let ids: Vec<i32> = found.iter().map(|job| job.id).collect();
+    let counts: Vec<(i32, String, i64)> = {
+        let mut conn = conn.get()?;
+        job_items
+            .filter(job_id.eq_any(&ids))
+            .group_by((job_id, state))
+            .select((job_id, state, count_star()))
+            .load(&mut *conn)?
+    };
+
+    let mut by_job: HashMap<i32, (usize, usize, usize)> = HashMap::new();
+    for (jid, item_state, count) in counts {
+        let entry = by_job.entry(jid).or_default();
+        entry.0 += count as usize;
+        match item_state.as_str() {
+            ITEM_DONE => entry.1 += count as usize,
+            ITEM_FAILED => entry.2 += count as usize,
+            _ => {}
+        }
+    }
+
+    Ok(found
+        .into_iter()
+        .map(|job| {
+            let (total, done, failed) = by_job.get(&job.id).copied().unwrap_or_default();
+            JobSummary {
+                job,
+                total,
+                done,
+                failed,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_profile, establish_connection_to, get_profiles, set_profile_storage};
+
+    #[test]
+    fn ingest_archives_on_the_pool_and_fails_missing_files() {
+        let dir = std::env::temp_dir().join(format!("tsk-jobs-{}-ingest", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "jobs test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        create_profile(conn.clone(), "ingested");
+        let pid = get_profiles(conn.clone())[0].id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+
+        let mut paths: Vec<String> = (0..12)
+            .map(|i| {
+                let path = dir.join(format!("file-{}.txt", i));
+                fs::write(&path, format!("contents {}", i)).unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        paths.push(dir.join("missing.txt").display().to_string());
+
+        let job = create_ingest_job(conn.clone(), &pid, &paths).unwrap();
+        run_job(conn.clone(), &job.id, |_| {}).unwrap();
+
+        let summary = get_job(conn.clone(), &job.id).unwrap();
+        assert_eq!(summary.job.status, STATUS_COMPLETED);
+        assert_eq!((summary.done, summary.failed), (12, 1));
+        let failed: Vec<JobItem> = get_job_items(conn.clone(), &job.id)
+            .unwrap()
+            .into_iter()
+            .filter(|item| item.state == ITEM_FAILED)
+            .collect();
+        assert_eq!(failed[0].target, paths[12]);
+        assert_eq!(get_files(conn, &pid).len(), 12);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A catalog with a profile archiving into its own repository, and `count`
+    // files to work on
+    fn setup(name: &str, count: usize) -> (std::path::PathBuf, DbPool, i32, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("tsk-jobs-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "jobs test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        create_profile(conn.clone(), name);
+        let pid = get_profiles(conn.clone())[0].id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+        let paths = (0..count)
+            .map(|i| {
+                let path = dir.join(format!("file-{}.txt", i));
+                fs::write(&path, format!("contents {}", i)).unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        (dir, conn, pid, paths)
+    }
+
+    fn states(conn: &DbPool, job_id: &i32) -> Vec<String> {
+        get_job_items(conn.clone(), job_id)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.state)
+            .collect()
+    }
+
+    #[test]
+    fn a_cancelled_job_keeps_what_it_finished() {
+        let (dir, conn, pid, paths) = setup("cancel", 5);
+        let ingest = create_ingest_job(conn.clone(), &pid, &paths).unwrap();
+        run_job(conn.clone(), &ingest.id, |_| {}).unwrap();
+
+        let job = create_verify_job(conn.clone(), &pid).unwrap();
+        let mut cancelled = false;
+        let summary = run_job(conn.clone(), &job.id, |progress| {
+            // As if cancelled from elsewhere once two files are verified
+            if progress.files_done == 2 && !cancelled {
+                cancel_job(conn.clone(), &job.id).unwrap();
+                cancelled = true;
+            }
+        })
+        .unwrap();
+        assert_eq!(summary.job.status, STATUS_CANCELLED);
+        assert_eq!((summary.done, summary.failed, summary.total), (2, 0, 5));
+        assert_eq!(
+            states(&conn, &job.id),
+            [
+                ITEM_DONE,
+                ITEM_DONE,
+                ITEM_PENDING,
+                ITEM_PENDING,
+                ITEM_PENDING
+            ]
+        );
+
+        // Running it again does nothing, and it cannot be cancelled twice
+        let summary = run_job(conn.clone(), &job.id, |_| {}).unwrap();
+        assert_eq!(
+            (summary.job.status.as_str(), summary.done),
+            (STATUS_CANCELLED, 2)
+        );
+        assert!(cancel_job(conn.clone(), &job.id).is_err());
+        assert!(interrupted_jobs(conn).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn racing_runs_process_each_item_once() {
+        let (dir, conn, pid, paths) = setup("race", 8);
+        let ingest = create_ingest_job(conn.clone(), &pid, &paths).unwrap();
+        run_job(conn.clone(), &ingest.id, |_| {}).unwrap();
+
+        let job = create_verify_job(conn.clone(), &pid).unwrap();
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let runs: Vec<_> = (0..2)
+            .map(|_| {
+                let (conn, barrier) = (conn.clone(), Arc::clone(&barrier));
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    run_job(conn, &job.id, |_| {}).unwrap()
+                })
+            })
+            .collect();
+        for run in runs {
+            run.join().unwrap();
+        }
+
+        let summary = get_job(conn.clone(), &job.id).unwrap();
+        assert_eq!(summary.job.status, STATUS_COMPLETED);
+        assert_eq!((summary.done, summary.failed), (8, 0));
+        assert_eq!(summary.job.owner, None);
+        for file in get_files(conn.clone(), &pid) {
+            assert_eq!(
+                crate::get_verification_history(conn.clone(), &file.id).len(),
+                1
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_job_with_a_live_owner_is_not_resumed() {
+        use schema::jobs;
+
+        let (dir, conn, pid, paths) = setup("owned", 2);
+        let job = create_ingest_job(conn.clone(), &pid, &paths).unwrap();
+        let claim = |heartbeat_at: NaiveDateTime| {
+            let mut conn = conn.get().unwrap();
+            diesel::update(jobs::table.find(job.id))
+                .set((
+                    jobs::status.eq(STATUS_RUNNING),
+                    jobs::owner.eq("another process"),
+                    jobs::heartbeat_at.eq(heartbeat_at),
+                ))
+                .execute(&mut *conn)
+                .unwrap();
+        };
+
+        claim(Utc::now().naive_utc());
+        assert!(interrupted_jobs(conn.clone()).unwrap().is_empty());
+        let summary = run_job(conn.clone(), &job.id, |_| {}).unwrap();
+        assert_eq!(
+            (summary.job.status.as_str(), summary.done),
+            (STATUS_RUNNING, 0)
+        );
+
+        // Once its heartbeat stops, the job is taken over
+        claim(stale_before(Utc::now().naive_utc()) - chrono::Duration::seconds(1));
+        assert_eq!(interrupted_jobs(conn.clone()).unwrap().len(), 1);
+        let summary = run_job(conn.clone(), &job.id, |_| {}).unwrap();
+        assert_eq!(
+            (summary.job.status.as_str(), summary.done),
+            (STATUS_COMPLETED, 2)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_interrupted_job_resumes_at_its_pending_items() {
+        use schema::{job_items, jobs};
+
+        let (dir, conn, pid, paths) = setup("resume", 6);
+        let job = create_ingest_job(conn.clone(), &pid, &paths).unwrap();
+        // As left by a process that stopped after recording the first three
+        // items: the job still running, the rest pending
+        {
+            let mut conn = conn.get().unwrap();
+            diesel::update(jobs::table.find(job.id))
+                .set(jobs::status.eq(STATUS_RUNNING))
+                .execute(&mut *conn)
+                .unwrap();
+            diesel::update(
+                job_items::table
+                    .filter(job_items::job_id.eq(job.id))
+                    .filter(job_items::seq.lt(3)),
+            )
+            .set(job_items::state.eq(ITEM_DONE))
+            .execute(&mut *conn)
+            .unwrap();
+        }
+
+        let interrupted = interrupted_jobs(conn.clone()).unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert_eq!(interrupted[0].id, job.id);
+
+        let mut first = None;
+        let summary = run_job(conn.clone(), &job.id, |progress| {
+            first.get_or_insert(progress.clone());
+        })
+        .unwrap();
+        let first = first.unwrap();
+        assert_eq!((first.files_done, first.files_total), (3, 6));
+        assert_eq!(summary.job.status, STATUS_COMPLETED);
+        assert_eq!((summary.done, summary.failed), (6, 0));
+        assert!(states(&conn, &job.id)
+            .iter()
+            .all(|state| state == ITEM_DONE));
+
+        // Only the last three were archived
+        let mut archived: Vec<String> = get_files(conn.clone(), &pid)
+            .into_iter()
+            .map(|file| file.file_name)
+            .collect();
+        archived.sort();
+        assert_eq!(archived, paths[3..]);
+        assert!(interrupted_jobs(conn).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,98 +1,1000 @@
+pub mod backend;
+pub mod chunking;
+pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod digest;
+pub mod error;
+pub mod file_tree;
+pub mod jobs;
+pub mod maintenance;
 pub mod models;
+pub mod parity;
+pub mod progress;
+pub mod replication;
+pub mod scan;
 pub mod schema;
+pub mod scrub;
+pub mod store;
 pub mod thread_pool;
-
+pub mod timeline;
+pub mod watch;
 use diesel::prelude::*;
 use dotenvy::dotenv;
-use futures::channel::mpsc::Sender as FuturesSender;
-use sha3::{Digest, Sha3_256};
 use std::{
+    collections::HashMap,
     env,
-    sync::{Arc, Mutex},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::backend::{open_backend, LocalBackend, StorageBackend};
+use crate::chunking::Chunker;
+use crate::db::{AnyConnection, DbPool};
+use crate::digest::{hash_path, FileHasher, HashAlgorithm};
+use crate::error::ArchiverError;
+use crate::models::{
+    File, FileChunk, LegalHold, NewChunk, NewFile, NewFileChunk, NewLegalHold, NewProfile,
+    NewVerification, Profile, Verification,
 };
+use crate::parity::RepairOutcome;
+use crate::scrub::Throttle;
+use crate::store::{chunk_digest, ContentStore, DEFAULT_COMPRESSION_LEVEL};
+
+/// Open the configured catalog, creating and migrating it as needed. Exits
+/// the process if that fails, e.g. because the database is newer than this
+/// program.
+pub fn establish_connection() -> DbPool {
+    let database_url = config::settings().database_url;
 
-use crate::models::{File, NewFile, NewProfile, Profile};
+    establish_connection_to(&database_url).unwrap_or_else(|e| {
+        println!("Cannot open database {}: {}", database_url, e);
+        std::process::exit(1);
+    })
+}
+
+/// Connect to a catalog other than the configured one, e.g. a replica,
+/// creating and migrating it as needed. There is a connection for each
+/// worker thread, and two for everything else.
+pub fn establish_connection_to(database_url: &str) -> Result<DbPool, ArchiverError> {
+    db::open_pool(database_url, config::settings().workers as u32 + 2)
+}
 
-pub fn establish_connection() -> SqliteConnection {
+/// Copy the catalog at `from_url` into the new, empty catalog at `to_url`,
+/// e.g. to move an SQLite catalog to a PostgreSQL database a team shares.
+/// Both are brought up to date first. Returns how many rows each table had.
+pub fn copy_catalog(
+    from_url: &str,
+    to_url: &str,
+) -> Result<Vec<(&'static str, usize)>, ArchiverError> {
+    let mut from = db::open_database(from_url)?;
+    let mut to = db::open_database(to_url)?;
+    if db::database_path(&mut from)? == db::database_path(&mut to)? {
+        return Err(ArchiverError::InvalidInput(
+            "The copy must go to another catalog".to_string(),
+        ));
+    }
+    db::copy_catalog(&mut from, &mut to)
+}
+
+/// Directory holding archived file contents: the `repository` setting from
+/// the config file, overridden by `ARCHIVE_REPOSITORY`, overridden in turn by
+/// `--repository`. Defaults to `$XDG_DATA_HOME/rs-timeskip-archiver/archive`.
+pub fn repository_path() -> PathBuf {
+    config::settings().repository
+}
+
+fn repository_passphrase() -> Result<String, ArchiverError> {
     dotenv().ok();
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    env::var("ARCHIVE_PASSPHRASE").map_err(|_| ArchiverError::MissingPassphrase)
+}
+
+fn open_storage_backend(
+    storage_url: Option<&str>,
+) -> Result<Box<dyn StorageBackend>, ArchiverError> {
+    match storage_url {
+        Some(storage_url) => open_backend(storage_url),
+        None => Ok(Box::new(LocalBackend::new(repository_path()))),
+    }
+}
+
+/// Open an archive repository, creating it on first use. `None` is the default
+/// local repository, anything else a storage URL as accepted by
+/// [`backend::open_backend`]. Unlocking the data key is deliberately slow, so
+/// opened repositories are kept for the lifetime of the process.
+pub fn open_store(storage_url: Option<&str>) -> Result<Arc<ContentStore>, ArchiverError> {
+    type Slot = Arc<Mutex<Option<Arc<ContentStore>>>>;
+    static STORES: OnceLock<Mutex<HashMap<String, Slot>>> = OnceLock::new();
+
+    let cache_key = match storage_url {
+        Some(storage_url) => storage_url.to_string(),
+        None => repository_path().display().to_string(),
+    };
+    // The map is only locked to find the repository's slot. Opening holds the
+    // slot alone, so other repositories open at the same time while two
+    // threads opening this one wait for a single key derivation.
+    let slot = Arc::clone(
+        STORES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(cache_key)
+            .or_default(),
+    );
+    let mut slot = slot.lock().unwrap();
+    if let Some(store) = slot.as_ref() {
+        return Ok(Arc::clone(store));
+    }
+
+    let passphrase = repository_passphrase()?;
+    let backend = open_storage_backend(storage_url)?;
+    let store = if ContentStore::exists(backend.as_ref())? {
+        ContentStore::open(backend, &passphrase)?
+    } else {
+        let level = env::var("ARCHIVE_COMPRESSION_LEVEL")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        ContentStore::init(backend, &passphrase, level)?
+    };
+
+    let store = Arc::new(store);
+    *slot = Some(Arc::clone(&store));
+    Ok(store)
+}
+
+/// Open the repository a profile archives into.
+pub fn open_profile_store(conn: DbPool, pid: &i32) -> Result<Arc<ContentStore>, ArchiverError> {
+    use schema::profiles;
+
+    let profile = {
+        let mut conn = conn.get()?;
+        profiles::table.find(pid).first::<Profile>(&mut *conn)?
+    };
+    open_store(profile.storage_url.as_deref())
+}
+
+/// Change the passphrase of a repository. Only the wrapped data key is rewritten.
+pub fn rotate_repository_key(
+    storage_url: Option<&str>,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<(), ArchiverError> {
+    let store = ContentStore::open(open_storage_backend(storage_url)?, old_passphrase)?;
+    store.rotate_key(old_passphrase, new_passphrase)
+}
 
-    SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+/// Change the zstd level a repository uses for chunks stored from now on.
+pub fn set_repository_compression_level(
+    storage_url: Option<&str>,
+    passphrase: &str,
+    level: i32,
+) -> Result<(), ArchiverError> {
+    let mut store = ContentStore::open(open_storage_backend(storage_url)?, passphrase)?;
+    store.set_compression_level(level)
 }
 
-pub fn create_profile(conn: Arc<Mutex<SqliteConnection>>, profile_name: &str) {
+pub fn create_profile(conn: DbPool, profile_name: &str) {
+    try_create_profile(conn, profile_name).expect("Error saving new profile");
+}
+
+/// Like [`create_profile`], returning the new profile instead of panicking.
+pub fn try_create_profile(conn: DbPool, profile_name: &str) -> Result<Profile, ArchiverError> {
     use schema::profiles;
 
     let new_profile = NewProfile { profile_name };
-    let mut conn = conn.lock().unwrap();
+    let mut conn = conn.get()?;
 
-    diesel::insert_into(profiles::table)
+    Ok(diesel::insert_into(profiles::table)
         .values(&new_profile)
-        .execute(&mut *conn)
-        .expect("Error saving new profile");
+        .returning(profiles::all_columns)
+        .get_result::<Profile>(&mut *conn)?)
 }
 
-pub fn get_profiles(conn: Arc<Mutex<SqliteConnection>>) -> Vec<Profile> {
+pub fn get_profiles(conn: DbPool) -> Vec<Profile> {
+    try_get_profiles(conn).expect("Error loading profiles")
+}
+
+pub fn try_get_profiles(conn: DbPool) -> Result<Vec<Profile>, ArchiverError> {
     use schema::profiles::dsl::*;
 
-    let mut conn = conn.lock().unwrap();
+    let mut conn = conn.get()?;
 
-    profiles
-        .load::<Profile>(&mut *conn)
-        .expect("Error loading profiles")
+    Ok(profiles.load::<Profile>(&mut *conn)?)
 }
 
-pub fn add_file(
-    conn: Arc<Mutex<SqliteConnection>>,
-    file_path: String,
+/// Choose where a profile archives its file contents. `None` selects the
+/// default local repository. Only allowed before anything has been archived,
+/// existing contents have to be replicated instead.
+pub fn set_profile_storage(
+    conn: DbPool,
     pid: &i32,
-    tx: &mut FuturesSender<(usize, usize)>,
-    current_file_index: usize,
-    total_files: usize,
-) -> Result<usize, diesel::result::Error> {
+    new_storage_url: Option<&str>,
+) -> Result<(), ArchiverError> {
     use schema::files;
+    use schema::profiles::dsl::*;
 
-    let file_path_buf = std::path::PathBuf::from(file_path.as_str());
+    // Fail early on malformed or unreachable locations
+    open_storage_backend(new_storage_url)?;
 
-    if file_path_buf.exists() {
-        // Hash file
-        let mut file_blob = std::fs::File::open(file_path.as_str()).unwrap();
-        let mut hasher = Sha3_256::new();
-        std::io::copy(&mut file_blob, &mut hasher).unwrap();
+    let mut conn = conn.get()?;
 
-        let file_out_hash = format!("{:x}", hasher.finalize());
-        let new_file = NewFile {
-            file_name: file_path.as_str(),
-            sha256: &file_out_hash,
-            profile_id: *pid,
-        };
+    conn.transaction(|conn| {
+        ensure_not_on_hold(conn, pid)?;
 
-        let mut conn = conn.lock().unwrap();
+        let archived_files: i64 = files::table
+            .filter(files::profile_id.eq(pid))
+            .count()
+            .get_result(conn)?;
+        if archived_files > 0 {
+            return Err(ArchiverError::InvalidInput(
+                "The profile already has archived files; replicate them to the new storage instead"
+                    .to_string(),
+            ));
+        }
+
+        diesel::update(profiles.find(pid))
+            .set((
+                storage_url.eq(new_storage_url),
+                updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?;
+        Ok(())
+    })
+}
 
-        // Insert file into database
-        diesel::insert_into(files::table)
-            .values(&new_file)
-            .execute(&mut *conn)?;
+/// Archive the file at `file_path` into a profile, calling `on_bytes` with the
+/// number of bytes read so far as it goes.
+pub fn add_file(
+    conn: DbPool,
+    file_path: String,
+    pid: &i32,
+    on_bytes: &mut dyn FnMut(u64),
+) -> Result<usize, ArchiverError> {
+    if Path::new(&file_path).exists() {
+        let store = open_profile_store(conn.clone(), pid)?;
+        let archived = archive_file(&store, &file_path, on_bytes)?;
 
-        // Send progress update
-        if tx.try_send((current_file_index, total_files)).is_err() {
-            println!("Receiver has been dropped.");
+        let mut conn = conn.get()?;
+        let added = db::retry_on_busy(|| {
+            db::write_transaction(&mut conn, |conn| record_files(conn, pid, &[&archived]))
+        })?;
+        if !added[0] {
+            println!("{} is already tracked and unchanged", file_path);
         }
+
         Ok(2)
     } else {
-        Err(diesel::result::Error::NotFound)
+        Err(diesel::result::Error::NotFound.into())
     }
 }
 
-pub fn get_files(conn: Arc<Mutex<SqliteConnection>>, pid: &i32) -> Vec<File> {
+/// A file whose contents are in the repository, to be recorded in the
+/// catalog with [`record_files`].
+#[derive(Debug)]
+pub struct ArchivedFile {
+    pub file_name: String,
+    pub digest: String,
+    pub algorithm: HashAlgorithm,
+    pub modified_at: Option<chrono::NaiveDateTime>,
+    // Chunk digests and sizes, in file order
+    chunks: Vec<(String, i32)>,
+}
+
+/// Store the file at `file_path` in a repository, hashing it and storing its
+/// chunks in a single pass, and create its recovery data if that is enabled
+/// and missing. Nothing is written to the catalog, so any number of files can
+/// be archived at once.
+pub fn archive_file(
+    store: &ContentStore,
+    file_path: &str,
+    on_bytes: &mut dyn FnMut(u64),
+) -> Result<ArchivedFile, ArchiverError> {
+    let file_blob = std::fs::File::open(file_path)?;
+    let modified_at = file_blob
+        .metadata()?
+        .modified()
+        .ok()
+        .map(|modified| chrono::DateTime::<chrono::Utc>::from(modified).naive_utc());
+    let algorithm = HashAlgorithm::for_new_files();
+    let mut hasher = FileHasher::new(algorithm);
+    let mut chunk_list: Vec<(String, i32)> = Vec::new();
+    let mut bytes_read: u64 = 0;
+
+    for chunk in Chunker::new(file_blob) {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        bytes_read += chunk.len() as u64;
+        on_bytes(bytes_read);
+
+        let digest = chunk_digest(&chunk);
+        store.put_chunk(&digest, &chunk)?;
+        chunk_list.push((digest, chunk.len() as i32));
+    }
+    let digest = hasher.finalize();
+
+    // Recovery data is stored by digest, so an unchanged file already has it
+    if let Some(percent) = parity_percent() {
+        let created = parity::has_parity(store, &digest).and_then(|exists| {
+            if !exists {
+                parity::create_parity(store, &digest, algorithm, &chunk_list, percent)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = created {
+            println!("Could not create recovery data for {}: {}", file_path, e);
+        }
+    }
+
+    Ok(ArchivedFile {
+        file_name: file_path.to_string(),
+        digest,
+        algorithm,
+        modified_at,
+        chunks: chunk_list,
+    })
+}
+
+/// Record archived files and their chunk lists in a profile, returning for
+/// each whether it was added. A path is tracked once per profile: adding it
+/// again unchanged leaves it as it is, and new content replaces it, keeping
+/// the old row as history. Content tracked under another hash algorithm is
+/// compared by its chunks. Call it inside a transaction, so a batch of files
+/// is recorded completely or not at all.
+pub fn record_files(
+    conn: &mut AnyConnection,
+    pid: &i32,
+    archived: &[&ArchivedFile],
+) -> Result<Vec<bool>, ArchiverError> {
+    use schema::{file_chunks, files};
+
+    let mut added = Vec::with_capacity(archived.len());
+    for file in archived {
+        let tracked = files::table
+            .filter(files::profile_id.eq(pid))
+            .filter(files::file_name.eq(&file.file_name))
+            .filter(files::removed_at.is_null())
+            .first::<File>(conn)
+            .optional()?;
+        if let Some(tracked) = tracked {
+            let unchanged = if tracked.hash_algorithm == file.algorithm.as_str() {
+                tracked.sha256 == file.digest
+            } else {
+                // Chunk digests do not depend on the file's hash algorithm
+                let tracked_chunks = file_chunks::table
+                    .filter(file_chunks::file_id.eq(tracked.id))
+                    .order(file_chunks::seq.asc())
+                    .select(file_chunks::chunk_digest)
+                    .load::<String>(conn)?;
+                tracked_chunks.len() == file.chunks.len()
+                    && tracked_chunks
+                        .iter()
+                        .zip(&file.chunks)
+                        .all(|(tracked, (digest, _))| *tracked == *digest)
+            };
+            if unchanged {
+                added.push(false);
+                continue;
+            }
+            ensure_not_on_hold(conn, pid)?;
+            diesel::update(files::table.find(tracked.id))
+                .set(files::removed_at.eq(diesel::dsl::now))
+                .execute(conn)?;
+        }
+
+        let file_id = diesel::insert_into(files::table)
+            .values(&NewFile {
+                file_name: &file.file_name,
+                sha256: &file.digest,
+                profile_id: *pid,
+                hash_algorithm: file.algorithm.as_str(),
+                modified_at: file.modified_at,
+            })
+            .returning(files::id)
+            .get_result::<i32>(conn)?;
+
+        for (seq, (digest, size)) in file.chunks.iter().enumerate() {
+            db::insert_chunk(
+                conn,
+                &NewChunk {
+                    digest,
+                    size: *size,
+                },
+            )?;
+            diesel::insert_into(file_chunks::table)
+                .values(&NewFileChunk {
+                    file_id,
+                    seq: seq as i32,
+                    chunk_digest: digest,
+                })
+                .execute(conn)?;
+        }
+        added.push(true);
+    }
+    Ok(added)
+}
+
+/// Reassemble an archived file from its chunks into `dest`, verifying the
+/// result against the whole-file digest stored in `files`.
+pub fn restore_file(conn: DbPool, fid: &i32, dest: &std::path::Path) -> Result<(), ArchiverError> {
+    use schema::file_chunks::dsl::*;
+    use schema::files;
+
+    let (file, chunk_list) = {
+        let mut conn = conn.get()?;
+        let file = files::table.find(fid).first::<File>(&mut *conn)?;
+        let chunk_list = file_chunks
+            .filter(file_id.eq(fid))
+            .order(seq.asc())
+            .load::<FileChunk>(&mut *conn)?;
+        (file, chunk_list)
+    };
+
+    let store = open_profile_store(conn, &file.profile_id)?;
+    let mut hasher = FileHasher::new(file.digest_algorithm()?);
+    let mut out = std::fs::File::create(dest)?;
+
+    for file_chunk in &chunk_list {
+        let data = match store.get_chunk(&file_chunk.chunk_digest) {
+            Ok(data) => data,
+            Err(e) => {
+                drop(out);
+                let _ = std::fs::remove_file(dest);
+                return Err(e);
+            }
+        };
+        hasher.update(&data);
+        out.write_all(&data)?;
+    }
+    out.sync_all()?;
+
+    let restored_hash = hasher.finalize();
+    if restored_hash != file.sha256 {
+        drop(out);
+        let _ = std::fs::remove_file(dest);
+        return Err(ArchiverError::DigestMismatch {
+            expected: file.sha256,
+            actual: restored_hash,
+        });
+    }
+    Ok(())
+}
+
+pub fn get_files(conn: DbPool, pid: &i32) -> Vec<File> {
+    try_get_files(conn, pid).expect("Error loading files")
+}
+
+pub fn try_get_files(conn: DbPool, pid: &i32) -> Result<Vec<File>, ArchiverError> {
     use schema::files::dsl::*;
 
-    let mut conn = conn.lock().unwrap();
+    let mut conn = conn.get()?;
 
-    files
+    Ok(files
         .filter(profile_id.eq(pid))
-        .load::<File>(&mut *conn)
-        .expect("Error loading files")
+        .filter(removed_at.is_null())
+        .load::<File>(&mut *conn)?)
+}
+
+/// Find tracked files whose path contains `query` or whose digest starts with
+/// it, optionally limited to one profile.
+pub fn search_files(conn: DbPool, pid: Option<&i32>, query: &str) -> Vec<File> {
+    try_search_files(conn, pid, query).expect("Error searching files")
+}
+
+pub fn try_search_files(
+    conn: DbPool,
+    pid: Option<&i32>,
+    query: &str,
+) -> Result<Vec<File>, ArchiverError> {
+    use schema::files::dsl::*;
+
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    let mut conn = conn.get()?;
+
+    let mut search = files
+        .filter(removed_at.is_null())
+        .filter(
+            file_name
+                .like(format!("%{}%", escaped))
+                .escape('\\')
+                .or(sha256.like(format!("{}%", escaped)).escape('\\')),
+        )
+        .order(file_name.asc())
+        .into_boxed();
+    if let Some(pid) = pid {
+        search = search.filter(profile_id.eq(*pid));
+    }
+
+    Ok(search.load::<File>(&mut *conn)?)
+}
+
+/// Stop tracking a file. The row is kept with a `removed_at` timestamp so the
+/// profile's history stays intact. Refused while the profile is under legal hold.
+pub fn remove_file(conn: DbPool, fid: &i32) -> Result<(), ArchiverError> {
+    use schema::files::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        let file = files.find(fid).first::<File>(conn)?;
+        ensure_not_on_hold(conn, &file.profile_id)?;
+
+        diesel::update(files.find(fid))
+            .set(removed_at.eq(diesel::dsl::now))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Re-hash a tracked file where it lives on disk and record the result in its
+/// verification history. A missing or unreadable file fails verification.
+pub fn verify_file(conn: DbPool, fid: &i32) -> Result<Verification, ArchiverError> {
+    verify_file_throttled(conn, fid, None, &mut |_| {})
+}
+
+pub(crate) fn verify_file_throttled(
+    conn: DbPool,
+    fid: &i32,
+    throttle: Option<&mut Throttle>,
+    on_bytes: &mut dyn FnMut(u64),
+) -> Result<Verification, ArchiverError> {
+    use schema::files;
+
+    let file = {
+        let mut conn = conn.get()?;
+        files::table.find(fid).first::<File>(&mut *conn)?
+    };
+
+    let detail = match file.digest_algorithm() {
+        Ok(algorithm) => match hash_path(Path::new(&file.file_name), algorithm, throttle, on_bytes)
+        {
+            Ok(actual) if actual == file.sha256 => None,
+            Ok(actual) => Some(format!("Digest mismatch, found {}", actual)),
+            Err(e) => Some(e.to_string()),
+        },
+        Err(e) => Some(e.to_string()),
+    };
+    record_verification(&conn, fid, detail.as_deref())
+}
+
+/// Verify every tracked file of a profile.
+pub fn verify_profile(conn: DbPool, pid: &i32) -> Result<Vec<(File, Verification)>, ArchiverError> {
+    let mut results = Vec::new();
+    for file in get_files(conn.clone(), pid) {
+        let verification = verify_file(conn.clone(), &file.id)?;
+        results.push((file, verification));
+    }
+    Ok(results)
+}
+
+pub fn get_verification_history(conn: DbPool, fid: &i32) -> Vec<Verification> {
+    try_get_verification_history(conn, fid).expect("Error loading verifications")
+}
+
+pub fn try_get_verification_history(
+    conn: DbPool,
+    fid: &i32,
+) -> Result<Vec<Verification>, ArchiverError> {
+    use schema::verifications::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    Ok(verifications
+        .filter(file_id.eq(fid))
+        .order(id.desc())
+        .load::<Verification>(&mut *conn)?)
+}
+
+/// Generate Reed-Solomon recovery data for the archived copy of a tracked
+/// file, able to rebuild roughly `redundancy_percent` percent of it. Its
+/// chunks must still be intact.
+pub fn create_recovery_data(
+    conn: DbPool,
+    fid: &i32,
+    redundancy_percent: u32,
+) -> Result<(), ArchiverError> {
+    let (file, chunk_list) = archived_chunks(&conn, fid)?;
+    let store = open_profile_store(conn, &file.profile_id)?;
+    parity::create_parity(
+        &store,
+        &file.sha256,
+        file.digest_algorithm()?,
+        &chunk_list,
+        redundancy_percent,
+    )?;
+    Ok(())
+}
+
+// A file with the digests and sizes of its chunks, in file order
+fn archived_chunks(conn: &DbPool, fid: &i32) -> Result<(File, Vec<(String, i32)>), ArchiverError> {
+    use schema::{chunks, file_chunks, files};
+
+    let mut conn = conn.get()?;
+    let file = files::table.find(fid).first::<File>(&mut *conn)?;
+    let chunk_list = file_chunks::table
+        .inner_join(chunks::table)
+        .filter(file_chunks::file_id.eq(fid))
+        .order(file_chunks::seq.asc())
+        .select((file_chunks::chunk_digest, chunks::size))
+        .load::<(String, i32)>(&mut *conn)?;
+    Ok((file, chunk_list))
+}
+
+pub fn has_recovery_data(conn: DbPool, fid: &i32) -> Result<bool, ArchiverError> {
+    use schema::files;
+
+    let file = {
+        let mut conn = conn.get()?;
+        files::table.find(fid).first::<File>(&mut *conn)?
+    };
+    let store = open_profile_store(conn, &file.profile_id)?;
+    parity::has_parity(&store, &file.sha256)
+}
+
+/// Rebuild the damaged chunks of a file's archived copy from its recovery
+/// data, restore the repaired copy over the file on disk and re-check its
+/// digest there. The verification history records that final check, so a
+/// file only shows as verified once the copy on disk is whole again.
+/// Refused while the profile is under legal hold.
+pub fn repair_file(
+    conn: DbPool,
+    fid: &i32,
+) -> Result<(RepairOutcome, Verification), ArchiverError> {
+    let (file, chunk_list) = archived_chunks(&conn, fid)?;
+    ensure_not_on_hold(&mut *conn.get()?, &file.profile_id)?;
+    let store = open_profile_store(conn.clone(), &file.profile_id)?;
+
+    let outcome =
+        match parity::repair_chunks(&store, &file.sha256, file.digest_algorithm()?, &chunk_list) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                record_verification(&conn, fid, Some(&format!("Repair failed: {}", e)))?;
+                return Err(e);
+            }
+        };
+
+    // Restored beside the file first, so a failed restore leaves it as it was
+    let path = Path::new(&file.file_name);
+    let tmp_path = path.with_extension(format!(
+        "{}-{}.tmp",
+        path.extension().and_then(|e| e.to_str()).unwrap_or(""),
+        std::process::id()
+    ));
+    let restored = restore_file(conn.clone(), fid, &tmp_path)
+        .and_then(|()| std::fs::rename(&tmp_path, path).map_err(ArchiverError::from));
+    if let Err(e) = restored {
+        let _ = std::fs::remove_file(&tmp_path);
+        let detail = format!(
+            "Repository copy repaired, but restoring it to the file failed: {}",
+            e
+        );
+        record_verification(&conn, fid, Some(&detail))?;
+        return Err(e);
+    }
+
+    let verification = verify_file(conn, fid)?;
+    Ok((outcome, verification))
+}
+
+fn record_verification(
+    conn: &DbPool,
+    fid: &i32,
+    detail: Option<&str>,
+) -> Result<Verification, ArchiverError> {
+    use schema::{files, verifications};
+
+    let mut conn = conn.get()?;
+
+    // Verifications only add to the history, so they are recorded under
+    // legal hold too; the file row itself is left as it is then
+    conn.transaction(|conn| {
+        let verification = diesel::insert_into(verifications::table)
+            .values(&NewVerification {
+                file_id: *fid,
+                ok: detail.is_none(),
+                detail,
+            })
+            .returning(verifications::all_columns)
+            .get_result::<Verification>(conn)?;
+        let pid: i32 = files::table
+            .find(fid)
+            .select(files::profile_id)
+            .first(conn)?;
+        if active_legal_hold(conn, &pid)?.is_none() {
+            diesel::update(files::table.find(fid))
+                .set(files::last_verified_at.eq(verification.verified_at))
+                .execute(conn)?;
+        }
+        Ok(verification)
+    })
+}
+
+// Redundancy to generate recovery data with when files are added, if any.
+fn parity_percent() -> Option<u32> {
+    env::var("ARCHIVE_PARITY_PERCENT")
+        .ok()
+        .and_then(|percent| percent.parse().ok())
+        .filter(|percent| *percent > 0)
+}
+
+/// Place a profile under legal hold. While the hold is active no API in this
+/// crate will delete or modify the profile's files, history or archived blobs.
+pub fn place_legal_hold(
+    conn: DbPool,
+    pid: &i32,
+    held_by: &str,
+    hold_reason: &str,
+) -> Result<LegalHold, ArchiverError> {
+    use schema::legal_holds;
+
+    if held_by.trim().is_empty() || hold_reason.trim().is_empty() {
+        return Err(ArchiverError::InvalidInput(
+            "A legal hold needs both a holder and a reason".to_string(),
+        ));
+    }
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        ensure_not_on_hold(conn, pid)?;
+
+        let new_hold = NewLegalHold {
+            profile_id: *pid,
+            held_by,
+            hold_reason,
+        };
+
+        diesel::insert_into(legal_holds::table)
+            .values(&new_hold)
+            .execute(conn)?;
+
+        active_legal_hold(conn, pid)?.ok_or(ArchiverError::NoLegalHold)
+    })
+}
+
+/// Release the active legal hold on a profile, recording who released it and why.
+pub fn release_legal_hold(
+    conn: DbPool,
+    pid: &i32,
+    released_by_name: &str,
+    reason: &str,
+) -> Result<LegalHold, ArchiverError> {
+    use schema::legal_holds::dsl::*;
+
+    if released_by_name.trim().is_empty() || reason.trim().is_empty() {
+        return Err(ArchiverError::InvalidInput(
+            "Releasing a legal hold needs both a name and a reason".to_string(),
+        ));
+    }
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        let hold = active_legal_hold(conn, pid)?.ok_or(ArchiverError::NoLegalHold)?;
+
+        diesel::update(legal_holds.find(hold.id))
+            .set((
+                released_by.eq(released_by_name),
+                release_reason.eq(reason),
+                released_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?;
+
+        Ok(legal_holds.find(hold.id).first::<LegalHold>(conn)?)
+    })
+}
+
+pub fn get_legal_hold(conn: DbPool, pid: &i32) -> Option<LegalHold> {
+    try_get_legal_hold(conn, pid).expect("Error loading legal hold")
+}
+
+pub fn try_get_legal_hold(conn: DbPool, pid: &i32) -> Result<Option<LegalHold>, ArchiverError> {
+    let mut conn = conn.get()?;
+
+    Ok(active_legal_hold(&mut conn, pid)?)
+}
+
+pub fn get_legal_hold_history(conn: DbPool, pid: &i32) -> Vec<LegalHold> {
+    try_get_legal_hold_history(conn, pid).expect("Error loading legal holds")
+}
+
+pub fn try_get_legal_hold_history(
+    conn: DbPool,
+    pid: &i32,
+) -> Result<Vec<LegalHold>, ArchiverError> {
+    use schema::legal_holds::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    Ok(legal_holds
+        .filter(profile_id.eq(pid))
+        .order(id.desc())
+        .load::<LegalHold>(&mut *conn)?)
+}
+
+fn active_legal_hold(
+    conn: &mut AnyConnection,
+    pid: &i32,
+) -> Result<Option<LegalHold>, diesel::result::Error> {
+    use schema::legal_holds::dsl::*;
+
+    legal_holds
+        .filter(profile_id.eq(pid))
+        .filter(released_at.is_null())
+        .first::<LegalHold>(conn)
+        .optional()
+}
+
+/// Fail with `ArchiverError::LegalHold` if the profile is under an active hold.
+/// Every API that deletes or modifies existing records must call this first.
+pub(crate) fn ensure_not_on_hold(conn: &mut AnyConnection, pid: &i32) -> Result<(), ArchiverError> {
+    match active_legal_hold(conn, pid)? {
+        Some(hold) => Err(ArchiverError::LegalHold(Box::new(hold))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Track a file as an earlier version did, under SHA3-256
+    fn make_legacy(conn: &DbPool, path: &Path) {
+        use schema::files;
+
+        let digest = hash_path(path, HashAlgorithm::Sha3_256, None, &mut |_| {}).unwrap();
+        let mut conn = conn.get().unwrap();
+        diesel::update(files::table.filter(files::file_name.eq(path.to_str().unwrap())))
+            .set((
+                files::hash_algorithm.eq(HashAlgorithm::Sha3_256.as_str()),
+                files::sha256.eq(digest),
+            ))
+            .execute(&mut *conn)
+            .unwrap();
+    }
+
+    fn versions(conn: &DbPool, path: &Path) -> Vec<File> {
+        use schema::files;
+
+        let mut conn = conn.get().unwrap();
+        files::table
+            .filter(files::file_name.eq(path.to_str().unwrap()))
+            .load::<File>(&mut *conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn legacy_digests_are_compared_by_chunks() {
+        let dir = std::env::temp_dir().join(format!("tsk-lib-{}-legacy", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "lib test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        create_profile(conn.clone(), "legacy");
+        let pid = get_profiles(conn.clone())[0].id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+
+        let path = dir.join("legacy.txt");
+        fs::write(&path, "unchanged contents").unwrap();
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        make_legacy(&conn, &path);
+
+        // Unchanged under the new default algorithm: no new version
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        let tracked = versions(&conn, &path);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].hash_algorithm, HashAlgorithm::Sha3_256.as_str());
+
+        // Changed: a new version replaces it
+        fs::write(&path, "changed contents").unwrap();
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        let tracked = versions(&conn, &path);
+        assert_eq!(tracked.len(), 2);
+        assert!(tracked.iter().any(|file| file.removed_at.is_none()
+            && file.hash_algorithm == HashAlgorithm::for_new_files().as_str()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repair_verifies_the_file_on_disk() {
+        let dir = std::env::temp_dir().join(format!("tsk-lib-{}-repair", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "lib test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        let pid = try_create_profile(conn.clone(), "repaired").unwrap().id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+        let path = dir.join("report.txt");
+        fs::write(&path, "the original contents").unwrap();
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        let fid = versions(&conn, &path)[0].id;
+        create_recovery_data(conn.clone(), &fid, 50).unwrap();
+
+        // Rotted on disk: repairing restores it and the recheck passes
+        fs::write(&path, "the original c0ntents").unwrap();
+        assert!(!verify_file(conn.clone(), &fid).unwrap().ok);
+        let (_, verification) = repair_file(conn.clone(), &fid).unwrap();
+        assert!(verification.ok);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "the original contents");
+        assert!(get_verification_history(conn.clone(), &fid)[0].ok);
+
+        // Nothing can be restored over a directory: the file is not verified
+        fs::remove_file(&path).unwrap();
+        fs::create_dir_all(path.join("in the way")).unwrap();
+        assert!(repair_file(conn.clone(), &fid).is_err());
+        let history = get_verification_history(conn.clone(), &fid);
+        assert!(!history[0].ok);
+        assert!(history[0]
+            .detail
+            .as_deref()
+            .unwrap()
+            .starts_with("Repository copy repaired"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn legal_hold_blocks_changes_until_released() {
+        let dir = std::env::temp_dir().join(format!("tsk-lib-{}-hold", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "lib test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        let pid = try_create_profile(conn.clone(), "held").unwrap().id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+        let path = dir.join("evidence.txt");
+        fs::write(&path, "as it was").unwrap();
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        let fid = versions(&conn, &path)[0].id;
+
+        place_legal_hold(conn.clone(), &pid, "counsel", "litigation").unwrap();
+        let held =
+            |result: Result<(), ArchiverError>| matches!(result, Err(ArchiverError::LegalHold(_)));
+        assert!(held(
+            place_legal_hold(conn.clone(), &pid, "counsel", "again").map(|_| ())
+        ));
+        assert!(held(remove_file(conn.clone(), &fid)));
+        assert!(held(set_profile_storage(conn.clone(), &pid, None)));
+        assert!(held(repair_file(conn.clone(), &fid).map(|_| ())));
+        fs::write(&path, "tampered with").unwrap();
+        assert!(held(
+            add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).map(|_| ())
+        ));
+
+        // Verifying still adds to the history, but leaves the row alone
+        let verification = verify_file(conn.clone(), &fid).unwrap();
+        assert!(!verification.ok);
+        assert_eq!(get_verification_history(conn.clone(), &fid).len(), 1);
+        let tracked = versions(&conn, &path);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].last_verified_at, None);
+        assert_eq!(tracked[0].removed_at, None);
+
+        let released = release_legal_hold(conn.clone(), &pid, "counsel", "settled").unwrap();
+        assert_eq!(released.released_by.as_deref(), Some("counsel"));
+        assert!(get_legal_hold(conn.clone(), &pid).is_none());
+        assert!(matches!(
+            release_legal_hold(conn.clone(), &pid, "counsel", "again"),
+            Err(ArchiverError::NoLegalHold)
+        ));
+        add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        assert_eq!(versions(&conn, &path).len(), 2);
+        remove_file(conn.clone(), &fid).unwrap();
+
+        // Released holds stay on record
+        place_legal_hold(conn.clone(), &pid, "auditor", "audit").unwrap();
+        let history = get_legal_hold_history(conn.clone(), &pid);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].held_by, "auditor");
+        assert!(history[0].released_at.is_none());
+        assert_eq!(history[1].release_reason.as_deref(), Some("settled"));
+        assert!(history[1].released_at.is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,14 +1,46 @@
 mod cli;
 mod ui;
+use rs_timeskip_archiver::config::{self, Config};
 use rs_timeskip_archiver::establish_connection;
+use rs_timeskip_archiver::scrub::run_scrub_daemon;
 use std::env;
 
 fn main() {
-    let connection = establish_connection();
-    let args: Vec<String> = env::args().collect();
+    // Settings given as flags win over the environment and the config file
+    let args = match Config::parse_flags(env::args().collect()) {
+        Ok((overrides, args)) => {
+            config::set_overrides(overrides);
+            args
+        }
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    // Settings can be changed without a database
+    if args.len() > 1 && args[1] == "config" {
+        cli::run_config_command(&args[2..]);
+        return;
+    }
+
+    // Catalog maintenance opens the catalogs itself, or asks archiverd
+    if args.len() > 1 && args[1] == "db" {
+        cli::run_db_command(&args[2..]);
+        return;
+    }
+
+    // Watched folders live in archiverd
+    if args.len() > 1 && args[1] == "watch" {
+        cli::run_watch_command(&args[2..]);
+        return;
+    }
 
+    let connection = establish_connection();
     if args.len() > 1 && args[1] == "cli" {
         cli::run_cli(connection);
+    } else if args.len() > 1 && args[1] == "scrub" {
+        run_scrub_daemon(connection);
     } else {
         ui::run_ui(connection).unwrap();
     }
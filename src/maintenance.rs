@@ -0,0 +1,630 @@
+//! Looking after the catalog itself: backups, integrity checks and repairs.
+//!
+//! The catalog holds the only record of which digests make up which files, so
+//! losing it loses the archive. [`backup_catalog`] copies an SQLite catalog
+//! while it is in use. [`check_catalog`] runs SQLite's own `integrity_check`
+//! and looks for rows that refer to nothing or hold malformed digests, which
+//! foreign keys do not catch in catalogs written before they were enforced.
+//! [`repair_catalog`] fixes what can be fixed without guessing.
+
+use chrono::{Local, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::{self, AnyConnection, DbPool};
+use crate::digest::HashAlgorithm;
+use crate::ensure_not_on_hold;
+use crate::error::ArchiverError;
+use crate::models::Profile;
+use crate::schema;
+
+/// Where backups go unless told otherwise: `backups` next to the catalog.
+pub const BACKUP_DIR: &str = "backups";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// Files, legal holds, scrub schedules or jobs of a profile that is gone.
+    MissingProfile,
+    /// A chunk list entry of a file that is gone.
+    OrphanedFileChunk,
+    /// A chunk list entry naming a chunk the catalog does not know.
+    MissingChunk,
+    /// A verification of a file that is gone.
+    OrphanedVerification,
+    /// An item of a job that is gone.
+    OrphanedJobItem,
+    /// A digest that is not 64 lowercase hexadecimal digits.
+    MalformedDigest,
+    /// A file hashed with an algorithm this program does not know.
+    UnknownHashAlgorithm,
+}
+
+impl ProblemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProblemKind::MissingProfile => "missing profile",
+            ProblemKind::OrphanedFileChunk => "orphaned chunk list entry",
+            ProblemKind::MissingChunk => "missing chunk",
+            ProblemKind::OrphanedVerification => "orphaned verification",
+            ProblemKind::OrphanedJobItem => "orphaned job item",
+            ProblemKind::MalformedDigest => "malformed digest",
+            ProblemKind::UnknownHashAlgorithm => "unknown hash algorithm",
+        }
+    }
+}
+
+/// Something wrong with the rows of the catalog.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CatalogProblem {
+    pub kind: ProblemKind,
+    /// The table the problem was found in.
+    pub table: String,
+    /// The id of the row, or the missing id for a missing profile.
+    pub row: String,
+    pub detail: String,
+    /// Whether [`repair_catalog`] can fix it.
+    pub repairable: bool,
+}
+
+/// What [`check_catalog`] found.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CatalogReport {
+    /// What SQLite's `integrity_check` reported, `ok` when the file is sound.
+    /// PostgreSQL looks after its own storage, so there is none for it.
+    pub integrity: Option<Vec<String>>,
+    pub problems: Vec<CatalogProblem>,
+}
+
+impl CatalogReport {
+    /// Whether the database file is sound, as far as SQLite can tell.
+    pub fn is_intact(&self) -> bool {
+        self.integrity
+            .as_ref()
+            .is_none_or(|messages| messages.iter().all(|message| message == "ok"))
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.is_intact() && self.problems.is_empty()
+    }
+}
+
+/// What [`repair_catalog`] did.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepairReport {
+    /// The backup taken before changing anything, for an SQLite catalog.
+    pub backup: Option<PathBuf>,
+    pub repaired: Vec<CatalogProblem>,
+    /// The check run again afterwards, with what could not be repaired.
+    pub remaining: CatalogReport,
+}
+
+/// Copy the SQLite catalog to a new file named after it and the current time,
+/// in `dir` or else in [`BACKUP_DIR`] next to the catalog. The copy is
+/// consistent even while other connections write, and is itself a catalog
+/// that `database_url` can point at. PostgreSQL catalogs are backed up with
+/// `pg_dump` instead.
+pub fn backup_catalog(conn: DbPool, dir: Option<&Path>) -> Result<PathBuf, ArchiverError> {
+    backup_database(&mut *conn.get()?, dir)
+}
+
+fn backup_database(conn: &mut AnyConnection, dir: Option<&Path>) -> Result<PathBuf, ArchiverError> {
+    if !matches!(*conn, AnyConnection::Sqlite(_)) {
+        return Err(ArchiverError::InvalidInput(
+            "Back up a PostgreSQL catalog with pg_dump".to_string(),
+        ));
+    }
+    let database = PathBuf::from(db::database_path(conn)?);
+
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => database.parent().unwrap_or(Path::new(".")).join(BACKUP_DIR),
+    };
+    fs::create_dir_all(&dir)?;
+    let stem = database
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "archive".to_string());
+    let backup = dir.join(format!(
+        "{}-{}.db",
+        stem,
+        Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    if backup.exists() {
+        return Err(ArchiverError::InvalidInput(format!(
+            "{} already exists",
+            backup.display()
+        )));
+    }
+
+    // Writes a compacted copy from a single read transaction
+    let target = backup.to_string_lossy().replace('\'', "''");
+    diesel::sql_query(format!("VACUUM INTO '{}'", target)).execute(conn)?;
+    Ok(backup)
+}
+
+// Whether the profile is under a legal hold, so its rows must not change
+fn is_on_hold(conn: &mut AnyConnection, profile_id: &i32) -> Result<bool, ArchiverError> {
+    match ensure_not_on_hold(conn, profile_id) {
+        Ok(()) => Ok(false),
+        Err(ArchiverError::LegalHold(_)) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    integrity_check: String,
+}
+
+/// Check the catalog: the database file with SQLite's `integrity_check`, and
+/// its rows for references to nothing and malformed digests.
+pub fn check_catalog(conn: DbPool) -> Result<CatalogReport, ArchiverError> {
+    let mut conn = conn.get()?;
+    let integrity = match *conn {
+        AnyConnection::Sqlite(_) => Some(
+            diesel::sql_query("PRAGMA integrity_check")
+                .load::<IntegrityCheck>(&mut *conn)?
+                .into_iter()
+                .map(|row| row.integrity_check)
+                .collect(),
+        ),
+        #[cfg(feature = "postgres")]
+        AnyConnection::Postgresql(_) => None,
+    };
+    Ok(CatalogReport {
+        integrity,
+        problems: find_problems(&mut conn)?,
+    })
+}
+
+fn find_problems(conn: &mut AnyConnection) -> Result<Vec<CatalogProblem>, ArchiverError> {
+    use schema::{
+        chunks, file_chunks, files, job_items, jobs, legal_holds, profiles, scrub_schedules,
+        verifications,
+    };
+
+    let mut problems = Vec::new();
+    let mut problem = |kind: ProblemKind, table: &str, row: String, detail: String| {
+        let repairable = match kind {
+            ProblemKind::MissingChunk | ProblemKind::UnknownHashAlgorithm => false,
+            // Chunk digests are keys that chunk files are stored under
+            ProblemKind::MalformedDigest => {
+                table == "files" && is_digest(&normalize_digest(&detail))
+            }
+            _ => true,
+        };
+        problems.push(CatalogProblem {
+            kind,
+            table: table.to_string(),
+            row,
+            detail,
+            repairable,
+        });
+    };
+
+    // Rows of each profile that is gone, by what refers to it
+    let mut missing: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+    let mut count_missing = |rows: Vec<i32>, what: &str| {
+        let mut counts: BTreeMap<i32, usize> = BTreeMap::new();
+        for profile_id in rows {
+            *counts.entry(profile_id).or_default() += 1;
+        }
+        for (profile_id, count) in counts {
+            missing
+                .entry(profile_id)
+                .or_default()
+                .push(format!("{} {}", count, what));
+        }
+    };
+    count_missing(
+        files::table
+            .left_join(profiles::table)
+            .filter(profiles::id.nullable().is_null())
+            .select(files::profile_id)
+            .load(conn)?,
+        "files",
+    );
+    count_missing(
+        legal_holds::table
+            .left_join(profiles::table)
+            .filter(profiles::id.nullable().is_null())
+            .select(legal_holds::profile_id)
+            .load(conn)?,
+        "legal holds",
+    );
+    count_missing(
+        scrub_schedules::table
+            .left_join(profiles::table)
+            .filter(profiles::id.nullable().is_null())
+            .select(scrub_schedules::profile_id)
+            .load(conn)?,
+        "scrub schedules",
+    );
+    count_missing(
+        jobs::table
+            .left_join(profiles::table)
+            .filter(profiles::id.nullable().is_null())
+            .select(jobs::profile_id)
+            .load(conn)?,
+        "jobs",
+    );
+    for (profile_id, rows) in missing {
+        problem(
+            ProblemKind::MissingProfile,
+            "profiles",
+            profile_id.to_string(),
+            format!("referred to by {}", rows.join(", ")),
+        );
+    }
+
+    let orphaned: Vec<(i32, i32)> = file_chunks::table
+        .left_join(files::table)
+        .filter(files::id.nullable().is_null())
+        .select((file_chunks::id, file_chunks::file_id))
+        .load(conn)?;
+    for (id, file_id) in orphaned {
+        problem(
+            ProblemKind::OrphanedFileChunk,
+            "file_chunks",
+            id.to_string(),
+            format!("file {} does not exist", file_id),
+        );
+    }
+
+    let unknown: Vec<(i32, String)> = file_chunks::table
+        .left_join(chunks::table)
+        .filter(chunks::digest.nullable().is_null())
+        .select((file_chunks::id, file_chunks::chunk_digest))
+        .load(conn)?;
+    for (id, digest) in unknown {
+        problem(
+            ProblemKind::MissingChunk,
+            "file_chunks",
+            id.to_string(),
+            format!("chunk {} is not in the chunks table", digest),
+        );
+    }
+
+    let orphaned: Vec<(i32, i32)> = verifications::table
+        .left_join(files::table)
+        .filter(files::id.nullable().is_null())
+        .select((verifications::id, verifications::file_id))
+        .load(conn)?;
+    for (id, file_id) in orphaned {
+        problem(
+            ProblemKind::OrphanedVerification,
+            "verifications",
+            id.to_string(),
+            format!("file {} does not exist", file_id),
+        );
+    }
+
+    let orphaned: Vec<(i32, i32)> = job_items::table
+        .left_join(jobs::table)
+        .filter(jobs::id.nullable().is_null())
+        .select((job_items::id, job_items::job_id))
+        .load(conn)?;
+    for (id, job_id) in orphaned {
+        problem(
+            ProblemKind::OrphanedJobItem,
+            "job_items",
+            id.to_string(),
+            format!("job {} does not exist", job_id),
+        );
+    }
+
+    // Files of profiles under legal hold keep even a malformed digest
+    let mut held = Vec::new();
+    let digests: Vec<(i32, String, String, i32)> = files::table
+        .select((
+            files::id,
+            files::sha256,
+            files::hash_algorithm,
+            files::profile_id,
+        ))
+        .load(conn)?;
+    for (id, digest, algorithm, profile_id) in digests {
+        if !is_digest(&digest) {
+            if is_on_hold(conn, &profile_id)? {
+                held.push(id.to_string());
+            }
+            problem(
+                ProblemKind::MalformedDigest,
+                "files",
+                id.to_string(),
+                digest,
+            );
+        }
+        if HashAlgorithm::parse(&algorithm).is_none() {
+            problem(
+                ProblemKind::UnknownHashAlgorithm,
+                "files",
+                id.to_string(),
+                algorithm,
+            );
+        }
+    }
+    let digests: Vec<String> = chunks::table.select(chunks::digest).load(conn)?;
+    for digest in digests.into_iter().filter(|digest| !is_digest(digest)) {
+        problem(
+            ProblemKind::MalformedDigest,
+            "chunks",
+            digest.clone(),
+            digest,
+        );
+    }
+    for problem in &mut problems {
+        if problem.kind == ProblemKind::MalformedDigest
+            && problem.table == "files"
+            && held.contains(&problem.row)
+        {
+            problem.repairable = false;
+        }
+    }
+
+    Ok(problems)
+}
+
+// Every digest in the catalog, whole-file or chunk, is a 256-bit hash
+// written as lowercase hex
+fn is_digest(digest: &str) -> bool {
+    digest.len() == 64
+        && digest
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn normalize_digest(digest: &str) -> String {
+    digest.trim().to_ascii_lowercase()
+}
+
+/// Fix the problems [`check_catalog`] finds that have a safe fix, after
+/// backing an SQLite catalog up:
+///
+/// - a missing profile is created again, named after its id, so its files,
+///   legal holds and jobs are kept rather than deleted;
+/// - chunk lists, verifications and job items of rows that are gone are
+///   deleted, as they describe nothing;
+/// - a files digest in capitals or with spaces around it is normalised,
+///   unless its profile is under legal hold.
+///
+/// Missing chunks, malformed chunk digests and unknown hash algorithms are
+/// left alone: fixing them needs the original files. A catalog whose file
+/// SQLite finds damaged is not touched at all; restore it from a backup.
+pub fn repair_catalog(conn: DbPool) -> Result<RepairReport, ArchiverError> {
+    let report = check_catalog(conn.clone())?;
+    if !report.is_intact() {
+        return Err(ArchiverError::InvalidInput(
+            "The database file is damaged; restore it from a backup instead".to_string(),
+        ));
+    }
+    let repairs: Vec<CatalogProblem> = report
+        .problems
+        .into_iter()
+        .filter(|problem| problem.repairable)
+        .collect();
+    if repairs.is_empty() {
+        return Ok(RepairReport {
+            backup: None,
+            repaired: Vec::new(),
+            remaining: check_catalog(conn)?,
+        });
+    }
+
+    let is_sqlite = matches!(*conn.get()?, AnyConnection::Sqlite(_));
+    let backup = if is_sqlite {
+        Some(backup_catalog(conn.clone(), None)?)
+    } else {
+        None
+    };
+
+    {
+        let mut conn = conn.get()?;
+        db::retry_on_busy(|| {
+            db::write_transaction(&mut conn, |conn| {
+                for problem in &repairs {
+                    repair(conn, problem)?;
+                }
+                Ok(())
+            })
+        })?;
+    }
+
+    Ok(RepairReport {
+        backup,
+        repaired: repairs,
+        remaining: check_catalog(conn)?,
+    })
+}
+
+fn repair(conn: &mut AnyConnection, problem: &CatalogProblem) -> Result<(), ArchiverError> {
+    use schema::{file_chunks, files, job_items, profiles, verifications};
+
+    let invalid_row = || {
+        ArchiverError::InvalidInput(format!("{} is not a row of {}", problem.row, problem.table))
+    };
+    match problem.kind {
+        ProblemKind::MissingProfile => {
+            let id: i32 = problem.row.parse().map_err(|_| invalid_row())?;
+            let now = Utc::now().naive_utc();
+            diesel::insert_into(profiles::table)
+                .values(&Profile {
+                    id,
+                    profile_name: format!("Recovered profile {}", id),
+                    created_at: now,
+                    updated_at: now,
+                    storage_url: None,
+                })
+                .execute(conn)?;
+            // The id was given rather than drawn, so move the sequence past
+            // it as copying a catalog does
+            match conn {
+                AnyConnection::Sqlite(_) => {}
+                #[cfg(feature = "postgres")]
+                AnyConnection::Postgresql(pg) => {
+                    diesel::sql_query(
+                        "SELECT setval(pg_get_serial_sequence('profiles', 'id'), \
+                         GREATEST(MAX(id), nextval(pg_get_serial_sequence('profiles', 'id')))) \
+                         FROM profiles",
+                    )
+                    .execute(pg)?;
+                }
+            }
+        }
+        ProblemKind::OrphanedFileChunk => {
+            let id: i32 = problem.row.parse().map_err(|_| invalid_row())?;
+            diesel::delete(file_chunks::table.find(id)).execute(conn)?;
+        }
+        ProblemKind::OrphanedVerification => {
+            let id: i32 = problem.row.parse().map_err(|_| invalid_row())?;
+            diesel::delete(verifications::table.find(id)).execute(conn)?;
+        }
+        ProblemKind::OrphanedJobItem => {
+            let id: i32 = problem.row.parse().map_err(|_| invalid_row())?;
+            diesel::delete(job_items::table.find(id)).execute(conn)?;
+        }
+        ProblemKind::MalformedDigest => {
+            let id: i32 = problem.row.parse().map_err(|_| invalid_row())?;
+            let profile_id: i32 = files::table
+                .find(id)
+                .select(files::profile_id)
+                .first(conn)?;
+            ensure_not_on_hold(conn, &profile_id)?;
+            diesel::update(files::table.find(id))
+                .set(files::sha256.eq(normalize_digest(&problem.detail)))
+                .execute(conn)?;
+        }
+        ProblemKind::MissingChunk | ProblemKind::UnknownHashAlgorithm => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::File;
+    use chrono::NaiveDateTime;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn file(id: i32, profile_id: i32, path: &str, digest: &str, added: u32) -> File {
+        File {
+            id,
+            file_name: path.to_string(),
+            sha256: digest.repeat(64),
+            created_at: at(added),
+            updated_at: at(added),
+            profile_id,
+            removed_at: None,
+            last_verified_at: None,
+            hash_algorithm: "blake3".to_string(),
+            modified_at: None,
+        }
+    }
+
+    #[test]
+    fn digests_of_held_profiles_are_not_repaired() {
+        use schema::files;
+
+        let dir =
+            std::env::temp_dir().join(format!("tsk-maintenance-{}-digest", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let conn =
+            crate::establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        let free = crate::try_create_profile(conn.clone(), "free").unwrap().id;
+        let held = crate::try_create_profile(conn.clone(), "held").unwrap().id;
+        for (id, profile_id) in [(1, free), (2, held)] {
+            let mut row = file(id, profile_id, "/a", "A", 0);
+            row.sha256 = format!(" {} ", row.sha256);
+            diesel::insert_into(files::table)
+                .values(&row)
+                .execute(&mut *conn.get().unwrap())
+                .unwrap();
+        }
+        crate::place_legal_hold(conn.clone(), &held, "counsel", "litigation").unwrap();
+
+        let report = check_catalog(conn.clone()).unwrap();
+        let repairable: Vec<(&str, bool)> = report
+            .problems
+            .iter()
+            .map(|problem| (problem.row.as_str(), problem.repairable))
+            .collect();
+        assert_eq!(repairable, [("1", true), ("2", false)]);
+
+        let report = repair_catalog(conn.clone()).unwrap();
+        assert_eq!(report.repaired.len(), 1);
+        assert_eq!(report.remaining.problems.len(), 1);
+        let digests: Vec<String> = files::table
+            .select(files::sha256)
+            .order(files::id)
+            .load(&mut *conn.get().unwrap())
+            .unwrap();
+        assert_eq!(digests[0], "a".repeat(64));
+        assert_eq!(digests[1], format!(" {} ", "A".repeat(64)));
+
+        // Nor when a hold comes between the check and the repair
+        let problem = &report.remaining.problems[0];
+        let mut problem = problem.clone();
+        problem.repairable = true;
+        assert!(matches!(
+            repair(&mut conn.get().unwrap(), &problem),
+            Err(ArchiverError::LegalHold(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Runs against the PostgreSQL database named by POSTGRES_TEST_URL, which
+    // it empties first, as a role that may set session_replication_role to
+    // leave a file without its profile. Without it this does nothing.
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn recovered_profiles_move_the_postgres_sequence_on() {
+        use schema::files;
+
+        let Ok(url) = std::env::var("POSTGRES_TEST_URL") else {
+            eprintln!("POSTGRES_TEST_URL not set, skipping");
+            return;
+        };
+        let mut pg = diesel::PgConnection::establish(&url).unwrap();
+        diesel::sql_query("DROP SCHEMA public CASCADE")
+            .execute(&mut pg)
+            .unwrap();
+        diesel::sql_query("CREATE SCHEMA public")
+            .execute(&mut pg)
+            .unwrap();
+        drop(pg);
+
+        let conn = crate::establish_connection_to(&url).unwrap();
+        let profile_id = crate::try_create_profile(conn.clone(), "first").unwrap().id;
+        {
+            let mut conn = conn.get().unwrap();
+            diesel::sql_query("SET session_replication_role = replica")
+                .execute(&mut *conn)
+                .unwrap();
+            diesel::insert_into(files::table)
+                .values(&file(1, profile_id + 5, "/a", "a", 0))
+                .execute(&mut *conn)
+                .unwrap();
+            diesel::sql_query("SET session_replication_role = DEFAULT")
+                .execute(&mut *conn)
+                .unwrap();
+        }
+
+        let report = repair_catalog(conn.clone()).unwrap();
+        assert_eq!(report.repaired[0].kind, ProblemKind::MissingProfile);
+        assert!(report.remaining.is_ok());
+        let next = crate::try_create_profile(conn, "next").unwrap().id;
+        assert!(next > profile_id + 5);
+    }
+}
@@ -1,8 +1,12 @@
-use super::schema::{profiles, files};
+use super::schema::{
+    chunks, file_chunks, files, job_items, jobs, legal_holds, profiles, scrub_schedules,
+    verifications,
+};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
 
-#[derive(Queryable, Selectable, Debug, Clone, PartialEq, Eq)]
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[diesel(table_name = profiles)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Profile {
@@ -10,11 +14,12 @@ pub struct Profile {
     pub profile_name: String,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub storage_url: Option<String>,
 }
 
-impl ToString for Profile {
-    fn to_string(&self) -> String {
-        self.profile_name.clone()
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.profile_name)
     }
 }
 
@@ -24,7 +29,7 @@ pub struct NewProfile<'a> {
     pub profile_name: &'a str
 }
 
-#[derive(Queryable, Selectable, Debug, Clone)]
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, Serialize, Deserialize)]
 #[diesel(table_name = files)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct File {
@@ -34,6 +39,10 @@ pub struct File {
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
     pub profile_id: i32,
+    pub removed_at: Option<chrono::NaiveDateTime>,
+    pub last_verified_at: Option<chrono::NaiveDateTime>,
+    pub hash_algorithm: String,
+    pub modified_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -42,4 +51,161 @@ pub struct NewFile<'a> {
     pub file_name: &'a str,
     pub sha256: &'a str,
     pub profile_id: i32,
+    pub hash_algorithm: &'a str,
+    pub modified_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A file row copied from another catalog, keeping its original timestamps.
+#[derive(Insertable)]
+#[diesel(table_name = files)]
+pub struct CopiedFile<'a> {
+    pub file_name: &'a str,
+    pub sha256: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub profile_id: i32,
+    pub hash_algorithm: &'a str,
+    pub modified_at: Option<chrono::NaiveDateTime>,
+    pub removed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = legal_holds)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct LegalHold {
+    pub id: i32,
+    pub profile_id: i32,
+    pub held_by: String,
+    pub hold_reason: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub released_by: Option<String>,
+    pub release_reason: Option<String>,
+    pub released_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = legal_holds)]
+pub struct NewLegalHold<'a> {
+    pub profile_id: i32,
+    pub held_by: &'a str,
+    pub hold_reason: &'a str,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = chunks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Chunk {
+    pub digest: String,
+    pub size: i32,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = chunks)]
+pub struct NewChunk<'a> {
+    pub digest: &'a str,
+    pub size: i32,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = file_chunks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct FileChunk {
+    pub id: i32,
+    pub file_id: i32,
+    pub seq: i32,
+    pub chunk_digest: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = file_chunks)]
+pub struct NewFileChunk<'a> {
+    pub file_id: i32,
+    pub seq: i32,
+    pub chunk_digest: &'a str,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, Serialize, Deserialize)]
+#[diesel(table_name = verifications)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Verification {
+    pub id: i32,
+    pub file_id: i32,
+    pub verified_at: chrono::NaiveDateTime,
+    pub ok: bool,
+    pub detail: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = verifications)]
+pub struct NewVerification<'a> {
+    pub file_id: i32,
+    pub ok: bool,
+    pub detail: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = scrub_schedules)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScrubSchedule {
+    pub id: i32,
+    pub profile_id: i32,
+    pub interval_hours: i32,
+    pub percent_per_run: i32,
+    pub cycle_days: i32,
+    pub read_limit_mb: i32,
+    pub last_run_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = scrub_schedules)]
+pub struct NewScrubSchedule {
+    pub profile_id: i32,
+    pub interval_hours: i32,
+    pub percent_per_run: i32,
+    pub cycle_days: i32,
+    pub read_limit_mb: i32,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, Serialize, Deserialize)]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub profile_id: i32,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub owner: Option<String>,
+    pub heartbeat_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = jobs)]
+pub struct NewJob<'a> {
+    pub kind: &'a str,
+    pub profile_id: i32,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, Serialize, Deserialize)]
+#[diesel(table_name = job_items)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct JobItem {
+    pub id: i32,
+    pub job_id: i32,
+    pub seq: i32,
+    pub target: String,
+    pub state: String,
+    pub reason: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = job_items)]
+pub struct NewJobItem<'a> {
+    pub job_id: i32,
+    pub seq: i32,
+    pub target: &'a str,
 }
@@ -0,0 +1,609 @@
+//! Reed-Solomon recovery data for archived files.
+//!
+//! Recovery data covers a file's contents as archived: its chunks, read back
+//! from the repository and joined in order. That content is split into
+//! fixed-size blocks, and every run of up to `data_shards` blocks (a stripe)
+//! gets `parity_shards` parity blocks over GF(2^8). Any `parity_shards`
+//! damaged blocks of a stripe can be rebuilt, and with them the chunks that
+//! rotted in the repository.
+//!
+//! Recovery data is kept in the profile's repository, encrypted like every
+//! other object:
+//!
+//! - `parity/<file digest>/index`: the header and one SHA3-256 digest per
+//!   data block, whatever the file's own digest algorithm, used to find out
+//!   which blocks are damaged. All integers are
+//!   little-endian.
+//!
+//!   | bytes | field                                   |
+//!   |-------|-----------------------------------------|
+//!   | 8     | magic `TSKPAR01`                        |
+//!   | 4     | block size in bytes                     |
+//!   | 4     | data blocks per stripe                  |
+//!   | 4     | parity blocks per stripe                |
+//!   | 8     | file length in bytes                    |
+//!   | 32*n  | digest of each data block, zero-padded  |
+//!
+//! - `parity/<file digest>/<stripe>`: the stripe's parity blocks, back to
+//!   back. The last stripe is padded with all-zero data blocks so every
+//!   stripe uses the same code.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::digest::{FileHasher, HashAlgorithm};
+use crate::error::ArchiverError;
+use crate::store::{chunk_digest, ContentStore};
+
+const MAGIC: &[u8; 8] = b"TSKPAR01";
+const HEADER_LEN: usize = 28;
+const MAX_DATA_SHARDS: usize = 64;
+const MIN_BLOCK_SIZE: u64 = 4 * 1024;
+const MAX_BLOCK_SIZE: u64 = 1024 * 1024;
+
+pub const DEFAULT_REDUNDANCY_PERCENT: u32 = 10;
+
+pub struct ParityIndex {
+    pub block_size: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub file_length: u64,
+    pub block_digests: Vec<[u8; 32]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RepairOutcome {
+    pub damaged_blocks: usize,
+    pub total_blocks: usize,
+    /// Chunks rebuilt and stored again in the repository.
+    pub repaired_chunks: usize,
+}
+
+impl ParityIndex {
+    fn stripe_count(&self) -> usize {
+        self.block_digests.len().div_ceil(self.data_shards)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 32 * self.block_digests.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.block_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.data_shards as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.parity_shards as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.file_length.to_le_bytes());
+        for digest in &self.block_digests {
+            bytes.extend_from_slice(digest);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return None;
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        let block_size = u32_at(8);
+        let data_shards = u32_at(12);
+        let parity_shards = u32_at(16);
+        let file_length = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+
+        let digests = &bytes[HEADER_LEN..];
+        if block_size == 0
+            || data_shards == 0
+            || parity_shards == 0
+            || !digests.len().is_multiple_of(32)
+        {
+            return None;
+        }
+        let block_digests = digests
+            .chunks_exact(32)
+            .map(|digest| digest.try_into().unwrap())
+            .collect();
+
+        Some(ParityIndex {
+            block_size,
+            data_shards,
+            parity_shards,
+            file_length,
+            block_digests,
+        })
+    }
+}
+
+fn index_key(file_digest: &str) -> String {
+    format!("parity/{}/index", file_digest)
+}
+
+fn stripe_key(file_digest: &str, stripe: usize) -> String {
+    format!("parity/{}/{}", file_digest, stripe)
+}
+
+pub fn has_parity(store: &ContentStore, file_digest: &str) -> Result<bool, ArchiverError> {
+    store.has_object(&index_key(file_digest))
+}
+
+fn block_digest(block: &[u8]) -> [u8; 32] {
+    Sha3_256::digest(block).into()
+}
+
+/// Read up to `block_size` bytes, zero-padding a short final block.
+fn read_block(reader: &mut impl Read, block_size: usize) -> std::io::Result<(Vec<u8>, usize)> {
+    let mut block = vec![0u8; block_size];
+    let mut filled = 0;
+    while filled < block_size {
+        match reader.read(&mut block[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok((block, filled))
+}
+
+/// Reads a file's contents back from its chunks in the repository. Chunks
+/// are given with their sizes, as recorded in the catalog.
+struct ChunkReader<'a> {
+    store: &'a ContentStore,
+    chunks: &'a [(String, i32)],
+    next: usize,
+    current: Vec<u8>,
+    pos: usize,
+    /// Chunks that could not be read back intact, read as zeros instead.
+    /// `None` fails the read instead.
+    damaged: Option<Vec<usize>>,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(store: &'a ContentStore, chunks: &'a [(String, i32)]) -> Self {
+        ChunkReader {
+            store,
+            chunks,
+            next: 0,
+            current: Vec::new(),
+            pos: 0,
+            damaged: None,
+        }
+    }
+
+    fn tolerating_damage(store: &'a ContentStore, chunks: &'a [(String, i32)]) -> Self {
+        ChunkReader {
+            damaged: Some(Vec::new()),
+            ..Self::new(store, chunks)
+        }
+    }
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            let Some((digest, size)) = self.chunks.get(self.next) else {
+                return Ok(0);
+            };
+            let size = *size as usize;
+            self.current = match (self.store.get_chunk(digest), &mut self.damaged) {
+                (Ok(data), _) if data.len() == size => data,
+                (Ok(_), None) => {
+                    return Err(io::Error::other(ArchiverError::CorruptChunk(
+                        digest.clone(),
+                    )))
+                }
+                (Err(e), None) => return Err(io::Error::other(e)),
+                (_, Some(damaged)) => {
+                    damaged.push(self.next);
+                    vec![0u8; size]
+                }
+            };
+            self.pos = 0;
+            self.next += 1;
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Generate recovery data for an archived file from its chunks, which must
+/// hash to `file_digest` under `algorithm`. The index is written last, so a
+/// partial run never leaves recovery data that looks complete.
+pub fn create_parity(
+    store: &ContentStore,
+    file_digest: &str,
+    algorithm: HashAlgorithm,
+    chunks: &[(String, i32)],
+    redundancy_percent: u32,
+) -> Result<ParityIndex, ArchiverError> {
+    if redundancy_percent == 0 {
+        return Err(ArchiverError::InvalidInput(
+            "The redundancy percentage must be above zero".to_string(),
+        ));
+    }
+
+    let file_length: u64 = chunks.iter().map(|(_, size)| *size as u64).sum();
+    let block_size = file_length
+        .div_ceil(MAX_DATA_SHARDS as u64)
+        .next_multiple_of(MIN_BLOCK_SIZE)
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE) as usize;
+    let block_count = (file_length.div_ceil(block_size as u64) as usize).max(1);
+    let data_shards = block_count.min(MAX_DATA_SHARDS);
+    let parity_shards = (data_shards * redundancy_percent as usize)
+        .div_ceil(100)
+        .clamp(1, 256 - data_shards);
+    let codec = ReedSolomon::new(data_shards, parity_shards)
+        .map_err(|e| ArchiverError::InvalidInput(format!("{:?}", e)))?;
+
+    let mut reader = ChunkReader::new(store, chunks);
+    let mut hasher = FileHasher::new(algorithm);
+    let mut block_digests = Vec::with_capacity(block_count);
+
+    for stripe in 0..block_count.div_ceil(data_shards) {
+        let mut shards: Vec<Vec<u8>> = Vec::with_capacity(data_shards + parity_shards);
+        for _ in 0..data_shards {
+            let (block, filled) = read_block(&mut reader, block_size)?;
+            if block_digests.len() < block_count {
+                hasher.update(&block[..filled]);
+                block_digests.push(block_digest(&block));
+            }
+            shards.push(block);
+        }
+        shards.resize(data_shards + parity_shards, vec![0u8; block_size]);
+        codec
+            .encode(&mut shards)
+            .map_err(|e| ArchiverError::InvalidInput(format!("{:?}", e)))?;
+
+        store.put_object(
+            &stripe_key(file_digest, stripe),
+            &shards[data_shards..].concat(),
+        )?;
+    }
+
+    let actual = hasher.finalize();
+    if actual != file_digest {
+        return Err(ArchiverError::DigestMismatch {
+            expected: file_digest.to_string(),
+            actual,
+        });
+    }
+
+    let index = ParityIndex {
+        block_size,
+        data_shards,
+        parity_shards,
+        file_length,
+        block_digests,
+    };
+    store.put_object(&index_key(file_digest), &index.to_bytes())?;
+    Ok(index)
+}
+
+/// Rebuild the chunks of an archived file that are damaged or missing in the
+/// repository from its recovery data, and store them again.
+///
+/// Every rebuilt chunk must match its own digest before it is stored, and the
+/// whole file must hash to `file_digest` again for the repair to succeed.
+/// Chunks are shared between files, so the repair heals every file using them.
+pub fn repair_chunks(
+    store: &ContentStore,
+    file_digest: &str,
+    algorithm: HashAlgorithm,
+    chunks: &[(String, i32)],
+) -> Result<RepairOutcome, ArchiverError> {
+    let index = ParityIndex::from_bytes(&store.get_object(&index_key(file_digest))?)
+        .ok_or_else(|| ArchiverError::CorruptObject(index_key(file_digest)))?;
+    let codec = ReedSolomon::new(index.data_shards, index.parity_shards)
+        .map_err(|e| ArchiverError::InvalidInput(format!("{:?}", e)))?;
+
+    // Where each chunk starts and ends in the file
+    let mut ranges = Vec::with_capacity(chunks.len());
+    let mut file_length: u64 = 0;
+    for (_, size) in chunks {
+        ranges.push((file_length, file_length + *size as u64));
+        file_length += *size as u64;
+    }
+    if file_length != index.file_length {
+        return Err(ArchiverError::InvalidInput(format!(
+            "The recovery data covers {} bytes but the file has {}",
+            index.file_length, file_length
+        )));
+    }
+
+    let block_count = index.block_digests.len();
+    let mut reader = ChunkReader::tolerating_damage(store, chunks);
+    let mut hasher = FileHasher::new(algorithm);
+    let mut damaged_blocks = 0;
+    let mut written: u64 = 0;
+    // Damaged chunks being put back together, with how many bytes are in
+    let mut rebuilding: HashMap<usize, (Vec<u8>, u64)> = HashMap::new();
+    let mut repaired_chunks = 0;
+
+    for stripe in 0..index.stripe_count() {
+        let first_block = stripe * index.data_shards;
+        let mut shards: Vec<Option<Vec<u8>>> =
+            Vec::with_capacity(index.data_shards + index.parity_shards);
+        let mut stripe_damaged = false;
+
+        for block_index in first_block..first_block + index.data_shards {
+            if block_index >= block_count {
+                // Padding blocks are known to be all zeros
+                shards.push(Some(vec![0u8; index.block_size]));
+                continue;
+            }
+            let (block, _) = read_block(&mut reader, index.block_size)?;
+            if block_digest(&block) == index.block_digests[block_index] {
+                shards.push(Some(block));
+            } else {
+                damaged_blocks += 1;
+                stripe_damaged = true;
+                shards.push(None);
+            }
+        }
+
+        if stripe_damaged {
+            let parity = store.get_object(&stripe_key(file_digest, stripe))?;
+            if parity.len() != index.parity_shards * index.block_size {
+                return Err(ArchiverError::CorruptObject(stripe_key(
+                    file_digest,
+                    stripe,
+                )));
+            }
+            shards.extend(
+                parity
+                    .chunks_exact(index.block_size)
+                    .map(|block| Some(block.to_vec())),
+            );
+            codec.reconstruct_data(&mut shards).map_err(|_| {
+                ArchiverError::InvalidInput(format!(
+                    "Stripe {} has more damaged blocks than recovery data can rebuild",
+                    stripe
+                ))
+            })?;
+        }
+
+        // Every chunk overlapping this stripe has been read by now, so the
+        // reader knows which of them are damaged
+        let damaged = reader.damaged.as_deref().unwrap_or_default();
+        for block in shards.into_iter().take(index.data_shards).flatten() {
+            let len = (block.len() as u64).min(index.file_length - written);
+            if len == 0 {
+                break;
+            }
+            let (start, end) = (written, written + len);
+            hasher.update(&block[..len as usize]);
+
+            for &chunk in damaged {
+                let (chunk_start, chunk_end) = ranges[chunk];
+                if chunk_end <= start || chunk_start >= end {
+                    continue;
+                }
+                let (from, to) = (chunk_start.max(start), chunk_end.min(end));
+                let (data, filled) = rebuilding
+                    .entry(chunk)
+                    .or_insert_with(|| (vec![0u8; (chunk_end - chunk_start) as usize], 0));
+                data[(from - chunk_start) as usize..(to - chunk_start) as usize]
+                    .copy_from_slice(&block[(from - start) as usize..(to - start) as usize]);
+                *filled += to - from;
+
+                if *filled == chunk_end - chunk_start {
+                    let (data, _) = rebuilding.remove(&chunk).unwrap();
+                    let digest = &chunks[chunk].0;
+                    if chunk_digest(&data) != *digest {
+                        return Err(ArchiverError::CorruptChunk(digest.clone()));
+                    }
+                    store.replace_chunk(digest, &data)?;
+                    repaired_chunks += 1;
+                }
+            }
+            written = end;
+        }
+    }
+
+    let actual = hasher.finalize();
+    if actual != file_digest {
+        return Err(ArchiverError::DigestMismatch {
+            expected: file_digest.to_string(),
+            actual,
+        });
+    }
+    Ok(RepairOutcome {
+        damaged_blocks,
+        total_blocks: block_count,
+        repaired_chunks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LocalBackend;
+    use crate::chunking::Chunker;
+    use crate::store::DEFAULT_COMPRESSION_LEVEL;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    struct Archived {
+        dir: PathBuf,
+        store: ContentStore,
+        digest: String,
+        chunks: Vec<(String, i32)>,
+    }
+
+    impl Drop for Archived {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // Archive `len` bytes of varied content, as ingesting a file would
+    fn archive(name: &str, len: usize) -> Archived {
+        let dir = std::env::temp_dir().join(format!("tsk-parity-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let store = ContentStore::init(
+            Box::new(LocalBackend::new(dir.clone())),
+            "parity test",
+            DEFAULT_COMPRESSION_LEVEL,
+        )
+        .unwrap();
+
+        let mut state: u32 = 0x2545_f491;
+        let data: Vec<u8> = (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut hasher = FileHasher::new(HashAlgorithm::Blake3);
+        let mut chunks = Vec::new();
+        for chunk in Chunker::new(&data[..]) {
+            let chunk = chunk.unwrap();
+            hasher.update(&chunk);
+            let digest = chunk_digest(&chunk);
+            store.put_chunk(&digest, &chunk).unwrap();
+            chunks.push((digest, chunk.len() as i32));
+        }
+        Archived {
+            dir,
+            store,
+            digest: hasher.finalize(),
+            chunks,
+        }
+    }
+
+    fn chunk_objects(dir: &Path) -> Vec<PathBuf> {
+        let mut objects: Vec<PathBuf> = fs::read_dir(dir.join("chunks"))
+            .unwrap()
+            .flat_map(|prefix| fs::read_dir(prefix.unwrap().path()).unwrap())
+            .map(|object| object.unwrap().path())
+            .collect();
+        objects.sort();
+        objects
+    }
+
+    fn flip_byte(path: &Path) {
+        let mut object = fs::read(path).unwrap();
+        let middle = object.len() / 2;
+        object[middle] ^= 0xff;
+        fs::write(path, object).unwrap();
+    }
+
+    #[test]
+    fn index_round_trips() {
+        let index = ParityIndex {
+            block_size: 4096,
+            data_shards: 3,
+            parity_shards: 1,
+            file_length: 10_000,
+            block_digests: vec![[1; 32], [2; 32], [3; 32]],
+        };
+        let read = ParityIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(read.block_size, 4096);
+        assert_eq!((read.data_shards, read.parity_shards), (3, 1));
+        assert_eq!(read.file_length, 10_000);
+        assert_eq!(read.block_digests, index.block_digests);
+        assert!(ParityIndex::from_bytes(&index.to_bytes()[..20]).is_none());
+    }
+
+    #[test]
+    fn parity_covers_every_block() {
+        let archived = archive("covers", 1024 * 1024);
+        let index = create_parity(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+            10,
+        )
+        .unwrap();
+        assert_eq!(index.file_length, 1024 * 1024);
+        assert_eq!(index.block_digests.len(), 64);
+        assert_eq!((index.data_shards, index.parity_shards), (64, 7));
+        assert!(has_parity(&archived.store, &archived.digest).unwrap());
+    }
+
+    #[test]
+    fn rotten_and_missing_chunks_are_rebuilt() {
+        let archived = archive("rebuild", 1024 * 1024);
+        create_parity(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+            10,
+        )
+        .unwrap();
+
+        // The first and last chunks, which lie in different stripes
+        assert!(archived.chunks.len() > 4);
+        let object = |index: usize| {
+            let (digest, _) = &archived.chunks[index];
+            archived.dir.join(archived.store.chunk_key(digest))
+        };
+        flip_byte(&object(0));
+        fs::remove_file(object(archived.chunks.len() - 1)).unwrap();
+
+        let outcome = repair_chunks(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+        )
+        .unwrap();
+        assert_eq!(outcome.repaired_chunks, 2);
+        assert!(outcome.damaged_blocks > 0);
+        for (digest, _) in &archived.chunks {
+            archived.store.get_chunk(digest).unwrap();
+        }
+
+        // Nothing is left to repair
+        let outcome = repair_chunks(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+        )
+        .unwrap();
+        assert_eq!((outcome.damaged_blocks, outcome.repaired_chunks), (0, 0));
+    }
+
+    #[test]
+    fn too_much_damage_is_reported() {
+        let archived = archive("too-much", 1024 * 1024);
+        create_parity(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+            1,
+        )
+        .unwrap();
+
+        for object in chunk_objects(&archived.dir).iter().take(3) {
+            flip_byte(object);
+        }
+        assert!(repair_chunks(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn recovery_data_needs_intact_chunks() {
+        let archived = archive("intact", 256 * 1024);
+        flip_byte(&chunk_objects(&archived.dir)[0]);
+        assert!(create_parity(
+            &archived.store,
+            &archived.digest,
+            HashAlgorithm::Blake3,
+            &archived.chunks,
+            10,
+        )
+        .is_err());
+        assert!(!has_parity(&archived.store, &archived.digest).unwrap());
+    }
+}
@@ -0,0 +1,191 @@
+//! Byte-level progress for ingest, verification and scrub jobs.
+//!
+//! A [`ProgressTracker`] turns "this many bytes of the current file are done"
+//! into [`Progress`] events for the whole job, with throughput and an ETA. It
+//! hands each event to a callback instead of a channel, so a slow consumer
+//! never makes it drop the final state.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Events within a file are spaced at least this far apart.
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// The file being read, if any.
+    pub current_file: Option<String>,
+    pub files_done: usize,
+    pub files_total: usize,
+    /// Average throughput since the job (re)started.
+    pub bytes_per_sec: f64,
+    /// Estimated seconds left, once there is a throughput to go by.
+    pub eta_secs: Option<u64>,
+}
+
+impl Progress {
+    /// How far along the job is, from 0 to 1.
+    pub fn ratio(&self) -> f32 {
+        if self.bytes_total == 0 {
+            if self.files_total == 0 {
+                0.0
+            } else {
+                self.files_done as f32 / self.files_total as f32
+            }
+        } else {
+            (self.bytes_done as f64 / self.bytes_total as f64).min(1.0) as f32
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} / {}, {}/s",
+            format_bytes(self.bytes_done),
+            format_bytes(self.bytes_total),
+            format_bytes(self.bytes_per_sec as u64)
+        )?;
+        if let Some(eta) = self.eta_secs {
+            write!(f, ", {} left", format_duration(eta))?;
+        }
+        write!(f, " (file {} of {}", self.files_done, self.files_total)?;
+        if let Some(file) = &self.current_file {
+            write!(f, ": {}", file)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// `bytes` with a binary unit, e.g. `1.5 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// `secs` as e.g. `1h 02m`, `3m 07s` or `42s`.
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+pub struct ProgressTracker<F: FnMut(&Progress)> {
+    progress: Progress,
+    started: Instant,
+    // `bytes_done` when the current file was started
+    file_start: u64,
+    last_report: Option<Instant>,
+    report: F,
+}
+
+impl<F: FnMut(&Progress)> ProgressTracker<F> {
+    /// Track `bytes_total` bytes over `files_total` files, `files_done` of
+    /// which were finished before this run.
+    pub fn new(files_total: usize, files_done: usize, bytes_total: u64, report: F) -> Self {
+        let mut tracker = ProgressTracker {
+            progress: Progress {
+                bytes_total,
+                files_done,
+                files_total,
+                ..Progress::default()
+            },
+            started: Instant::now(),
+            file_start: 0,
+            last_report: None,
+            report,
+        };
+        tracker.emit();
+        tracker
+    }
+
+    pub fn start_file(&mut self, name: &str) {
+        self.progress.current_file = Some(name.to_string());
+        self.file_start = self.progress.bytes_done;
+        self.emit();
+    }
+
+    /// `bytes` of the current file have been read so far.
+    pub fn file_bytes(&mut self, bytes: u64) {
+        self.progress.bytes_done = self.file_start + bytes;
+        self.emit_if_due();
+    }
+
+    /// `bytes` more have been read from `name`, one of several files worked
+    /// on at once.
+    pub fn add_bytes(&mut self, name: &str, bytes: u64) {
+        if self.progress.current_file.as_deref() != Some(name) {
+            self.progress.current_file = Some(name.to_string());
+        }
+        self.progress.bytes_done += bytes;
+        self.emit_if_due();
+    }
+
+    /// The current file is done with, successfully or not. `size` is what it
+    /// was expected to contribute to `bytes_total`.
+    pub fn finish_file(&mut self, size: u64) {
+        self.progress.bytes_done = self.progress.bytes_done.max(self.file_start + size);
+        self.progress.files_done += 1;
+        self.emit();
+    }
+
+    /// One of several files worked on at once is done with. `unread` is what
+    /// it was expected to contribute to `bytes_total` beyond the bytes passed
+    /// to [`add_bytes`](Self::add_bytes) for it.
+    pub fn finish_concurrent_file(&mut self, name: &str, unread: u64) {
+        self.progress.current_file = Some(name.to_string());
+        self.progress.bytes_done += unread;
+        self.progress.files_done += 1;
+        self.emit_if_due();
+    }
+
+    pub fn finish(&mut self) {
+        self.progress.current_file = None;
+        self.emit();
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn emit_if_due(&mut self) {
+        let due = self
+            .last_report
+            .is_none_or(|last| last.elapsed() >= REPORT_INTERVAL);
+        if due {
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.progress.bytes_per_sec = self.progress.bytes_done as f64 / elapsed;
+        }
+        self.progress.eta_secs = (self.progress.bytes_per_sec > 0.0).then(|| {
+            let remaining = self
+                .progress
+                .bytes_total
+                .saturating_sub(self.progress.bytes_done);
+            (remaining as f64 / self.progress.bytes_per_sec).ceil() as u64
+        });
+        self.last_report = Some(Instant::now());
+        (self.report)(&self.progress);
+    }
+}
@@ -0,0 +1,545 @@
+use diesel::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::db::{self, DbPool};
+use crate::digest::FileHasher;
+use crate::error::ArchiverError;
+use crate::models::{CopiedFile, File, FileChunk, NewChunk, NewFileChunk, NewProfile, Profile};
+use crate::schema;
+use crate::{ensure_not_on_hold, open_store};
+
+/// What a replication run did. Running it again after an interruption picks up
+/// where it stopped: files already present on the destination are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationReport {
+    pub files_copied: usize,
+    pub files_already_present: usize,
+    pub files_removed: usize,
+    pub chunks_copied: usize,
+    pub chunks_already_present: usize,
+    /// Files that could not be replicated, with the reason.
+    pub failures: Vec<(String, String)>,
+}
+
+/// One catalog taking part in a replica comparison.
+pub struct Replica {
+    pub name: String,
+    pub conn: DbPool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplicaStatus {
+    pub name: String,
+    /// Tracked files, or `None` if the replica has no profile of that name.
+    pub file_count: Option<usize>,
+    /// `(path, digest)` pairs some other replica has and this one does not.
+    pub missing_files: Vec<(String, String)>,
+    /// Chunks the catalog references but the repository does not hold.
+    pub missing_chunks: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgreementReport {
+    pub profile_name: String,
+    pub replicas: Vec<ReplicaStatus>,
+}
+
+impl AgreementReport {
+    pub fn all_agree(&self) -> bool {
+        self.replicas.iter().all(|replica| {
+            replica.file_count.is_some()
+                && replica.missing_files.is_empty()
+                && replica.missing_chunks.is_empty()
+        })
+    }
+}
+
+/// Copy a profile's metadata and archived contents into another catalog.
+///
+/// The destination profile is matched by name and created if needed, archiving
+/// into `dst_storage_url`, which must be another repository than the source
+/// profile's. The whole history is copied, removed versions included. A file
+/// row is matched on its path, digest and creation time, so a path whose
+/// content went back to an earlier version keeps both versions. Every chunk
+/// is verified against its digest when read from the source and again after
+/// it is written to the destination, and each file's chunks must hash to the
+/// file's whole-file digest before its row is committed.
+pub fn replicate_profile(
+    src_conn: DbPool,
+    pid: &i32,
+    dst_conn: DbPool,
+    dst_storage_url: Option<&str>,
+) -> Result<ReplicationReport, ArchiverError> {
+    let same_catalog = {
+        let mut src = src_conn.get()?;
+        let mut dst = dst_conn.get()?;
+        db::database_path(&mut src)? == db::database_path(&mut dst)?
+    };
+    if same_catalog {
+        return Err(ArchiverError::InvalidInput(
+            "The replica must live in another catalog".to_string(),
+        ));
+    }
+
+    let (src_profile, src_files) = {
+        let mut conn = src_conn.get()?;
+        let profile = schema::profiles::table
+            .find(pid)
+            .first::<Profile>(&mut *conn)?;
+        let files = schema::files::table
+            .filter(schema::files::profile_id.eq(pid))
+            .order((schema::files::created_at.asc(), schema::files::id.asc()))
+            .load::<File>(&mut *conn)?;
+        (profile, files)
+    };
+
+    // A replica in the same repository would share every chunk with the
+    // source, leaving a single copy of the data
+    let src_store = open_store(src_profile.storage_url.as_deref())?;
+    let dst_store = open_store(dst_storage_url)?;
+    let repository = src_store.backend().describe();
+    if repository == dst_store.backend().describe() {
+        return Err(ArchiverError::InvalidInput(format!(
+            "The replica must archive to another repository than {}",
+            repository
+        )));
+    }
+
+    let dst_profile =
+        find_or_create_profile(&dst_conn, &src_profile.profile_name, dst_storage_url)?;
+    if dst_profile.storage_url.as_deref() != dst_storage_url {
+        return Err(ArchiverError::InvalidInput(format!(
+            "The replica profile archives to {}",
+            dst_profile
+                .storage_url
+                .as_deref()
+                .unwrap_or("the default repository")
+        )));
+    }
+
+    let dst_files = {
+        let mut conn = dst_conn.get()?;
+        schema::files::table
+            .filter(schema::files::profile_id.eq(dst_profile.id))
+            .order((schema::files::created_at.asc(), schema::files::id.asc()))
+            .load::<File>(&mut *conn)?
+    };
+    // Versions made within the same second share a creation time, so rows
+    // with equal keys are paired up in the order they were made
+    let mut dst_index: HashMap<FileIdentity, VecDeque<File>> = HashMap::new();
+    for file in dst_files {
+        dst_index
+            .entry(FileIdentity::of(&file))
+            .or_default()
+            .push_back(file);
+    }
+
+    let mut report = ReplicationReport::default();
+
+    for src_file in &src_files {
+        let replicated = dst_index
+            .get_mut(&FileIdentity::of(src_file))
+            .and_then(VecDeque::pop_front);
+
+        if let Some(dst_file) = replicated {
+            // Propagate removals, but never touch a replica under legal hold
+            if src_file.removed_at.is_some() && dst_file.removed_at.is_none() {
+                let mut conn = dst_conn.get()?;
+                match ensure_not_on_hold(&mut conn, &dst_profile.id) {
+                    Ok(()) => {
+                        diesel::update(schema::files::table.find(dst_file.id))
+                            .set(schema::files::removed_at.eq(src_file.removed_at))
+                            .execute(&mut *conn)?;
+                        report.files_removed += 1;
+                    }
+                    Err(e) => report
+                        .failures
+                        .push((src_file.file_name.clone(), e.to_string())),
+                }
+            } else {
+                report.files_already_present += 1;
+            }
+            continue;
+        }
+
+        let chunk_list = {
+            let mut conn = src_conn.get()?;
+            schema::file_chunks::table
+                .filter(schema::file_chunks::file_id.eq(src_file.id))
+                .order(schema::file_chunks::seq.asc())
+                .load::<FileChunk>(&mut *conn)?
+        };
+
+        let mut hasher = match src_file.digest_algorithm() {
+            Ok(algorithm) => FileHasher::new(algorithm),
+            Err(e) => {
+                report
+                    .failures
+                    .push((src_file.file_name.clone(), e.to_string()));
+                continue;
+            }
+        };
+        let mut chunk_sizes = Vec::with_capacity(chunk_list.len());
+        let mut copy_result: Result<(), ArchiverError> = Ok(());
+        for file_chunk in &chunk_list {
+            let digest = &file_chunk.chunk_digest;
+            let result = src_store.get_chunk(digest).and_then(|data| {
+                hasher.update(&data);
+                chunk_sizes.push(data.len() as i32);
+                if dst_store.has_chunk(digest)? {
+                    report.chunks_already_present += 1;
+                } else {
+                    dst_store.put_chunk(digest, &data)?;
+                    report.chunks_copied += 1;
+                }
+                // Read back so the destination copy is known to be good
+                dst_store.get_chunk(digest).map(|_| ())
+            });
+            if let Err(e) = result {
+                copy_result = Err(e);
+                break;
+            }
+        }
+
+        if copy_result.is_ok() {
+            let replicated_hash = hasher.finalize();
+            if replicated_hash != src_file.sha256 {
+                copy_result = Err(ArchiverError::DigestMismatch {
+                    expected: src_file.sha256.clone(),
+                    actual: replicated_hash,
+                });
+            }
+        }
+
+        match copy_result.and_then(|()| {
+            insert_replicated_file(
+                &dst_conn,
+                &dst_profile.id,
+                src_file,
+                &chunk_list,
+                &chunk_sizes,
+            )
+        }) {
+            Ok(()) => report.files_copied += 1,
+            Err(e) => report
+                .failures
+                .push((src_file.file_name.clone(), e.to_string())),
+        }
+    }
+
+    Ok(report)
+}
+
+/// What identifies a file row across catalogs, where ids differ.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileIdentity {
+    file_name: String,
+    digest: String,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl FileIdentity {
+    fn of(file: &File) -> Self {
+        FileIdentity {
+            file_name: file.file_name.clone(),
+            digest: file.sha256.clone(),
+            created_at: file.created_at,
+        }
+    }
+}
+
+/// Check whether every catalog holds the same files for a profile, and whether
+/// each catalog's repository still holds every chunk it references.
+pub fn compare_replicas(
+    replicas: &[Replica],
+    profile_name: &str,
+) -> Result<AgreementReport, ArchiverError> {
+    let mut contents: Vec<Option<HashSet<(String, String)>>> = Vec::new();
+    let mut statuses = Vec::new();
+
+    for replica in replicas {
+        let mut conn = replica.conn.get()?;
+        let profile = schema::profiles::table
+            .filter(schema::profiles::profile_name.eq(profile_name))
+            .first::<Profile>(&mut *conn)
+            .optional()?;
+
+        let Some(profile) = profile else {
+            contents.push(None);
+            statuses.push(ReplicaStatus {
+                name: replica.name.clone(),
+                file_count: None,
+                missing_files: Vec::new(),
+                missing_chunks: Vec::new(),
+            });
+            continue;
+        };
+
+        let files = schema::files::table
+            .filter(schema::files::profile_id.eq(profile.id))
+            .filter(schema::files::removed_at.is_null())
+            .load::<File>(&mut *conn)?;
+        let file_ids: Vec<i32> = files.iter().map(|file| file.id).collect();
+        let digests: Vec<String> = schema::file_chunks::table
+            .filter(schema::file_chunks::file_id.eq_any(&file_ids))
+            .select(schema::file_chunks::chunk_digest)
+            .distinct()
+            .load(&mut *conn)?;
+        drop(conn);
+
+        let store = open_store(profile.storage_url.as_deref())?;
+        let mut missing_chunks = Vec::new();
+        for digest in digests {
+            if !store.has_chunk(&digest)? {
+                missing_chunks.push(digest);
+            }
+        }
+
+        statuses.push(ReplicaStatus {
+            name: replica.name.clone(),
+            file_count: Some(files.len()),
+            missing_files: Vec::new(),
+            missing_chunks,
+        });
+        contents.push(Some(
+            files
+                .into_iter()
+                .map(|file| (file.file_name, file.sha256))
+                .collect(),
+        ));
+    }
+
+    let union: HashSet<(String, String)> = contents.iter().flatten().flatten().cloned().collect();
+    for (status, files) in statuses.iter_mut().zip(&contents) {
+        if let Some(files) = files {
+            let mut missing: Vec<(String, String)> = union.difference(files).cloned().collect();
+            missing.sort();
+            status.missing_files = missing;
+        }
+    }
+
+    Ok(AgreementReport {
+        profile_name: profile_name.to_string(),
+        replicas: statuses,
+    })
+}
+
+fn find_or_create_profile(
+    conn: &DbPool,
+    name: &str,
+    new_storage_url: Option<&str>,
+) -> Result<Profile, ArchiverError> {
+    use schema::profiles::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        if let Some(profile) = profiles
+            .filter(profile_name.eq(name))
+            .first::<Profile>(conn)
+            .optional()?
+        {
+            return Ok(profile);
+        }
+
+        diesel::insert_into(profiles)
+            .values(&NewProfile { profile_name: name })
+            .execute(conn)?;
+        let profile = profiles
+            .filter(profile_name.eq(name))
+            .first::<Profile>(conn)?;
+        diesel::update(profiles.find(profile.id))
+            .set(storage_url.eq(new_storage_url))
+            .execute(conn)?;
+        Ok(profiles.find(profile.id).first::<Profile>(conn)?)
+    })
+}
+
+fn insert_replicated_file(
+    conn: &DbPool,
+    pid: &i32,
+    src_file: &File,
+    chunk_list: &[FileChunk],
+    chunk_sizes: &[i32],
+) -> Result<(), ArchiverError> {
+    use schema::{file_chunks, files};
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        // A current copy is a newer version of a path the replica may still
+        // track. Removed versions are history and replace nothing.
+        let tracked = if src_file.removed_at.is_none() {
+            files::table
+                .filter(files::profile_id.eq(pid))
+                .filter(files::file_name.eq(&src_file.file_name))
+                .filter(files::removed_at.is_null())
+                .select(files::id)
+                .first::<i32>(conn)
+                .optional()?
+        } else {
+            None
+        };
+        if let Some(tracked) = tracked {
+            ensure_not_on_hold(conn, pid)?;
+            diesel::update(files::table.find(tracked))
+                .set(files::removed_at.eq(src_file.created_at))
+                .execute(conn)?;
+        }
+
+        let file_id = diesel::insert_into(files::table)
+            .values(&CopiedFile {
+                file_name: &src_file.file_name,
+                sha256: &src_file.sha256,
+                created_at: src_file.created_at,
+                updated_at: src_file.updated_at,
+                profile_id: *pid,
+                hash_algorithm: &src_file.hash_algorithm,
+                modified_at: src_file.modified_at,
+                removed_at: src_file.removed_at,
+            })
+            .returning(files::id)
+            .get_result::<i32>(conn)?;
+
+        for (file_chunk, size) in chunk_list.iter().zip(chunk_sizes) {
+            db::insert_chunk(
+                conn,
+                &NewChunk {
+                    digest: &file_chunk.chunk_digest,
+                    size: *size,
+                },
+            )?;
+            diesel::insert_into(file_chunks::table)
+                .values(&NewFileChunk {
+                    file_id,
+                    seq: file_chunk.seq,
+                    chunk_digest: &file_chunk.chunk_digest,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{add_file, create_profile, establish_connection_to, get_profiles};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn workspace(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("tsk-replication-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "replication test");
+        path
+    }
+
+    fn catalog(path: &Path) -> DbPool {
+        establish_connection_to(path.to_str().unwrap()).unwrap()
+    }
+
+    fn source_profile(conn: &DbPool, repository: &Path) -> i32 {
+        create_profile(conn.clone(), "replicated");
+        let pid = get_profiles(conn.clone())[0].id;
+        crate::set_profile_storage(conn.clone(), &pid, repository.to_str()).unwrap();
+        pid
+    }
+
+    fn ingest(conn: &DbPool, pid: &i32, path: &Path, contents: &str) {
+        fs::write(path, contents).unwrap();
+        add_file(conn.clone(), path.display().to_string(), pid, &mut |_| {}).unwrap();
+    }
+
+    type HistoryRow = (
+        String,
+        String,
+        chrono::NaiveDateTime,
+        Option<chrono::NaiveDateTime>,
+    );
+
+    fn history(conn: &DbPool, profile_name: &str) -> Vec<HistoryRow> {
+        use schema::{files, profiles};
+
+        let mut conn = conn.get().unwrap();
+        files::table
+            .inner_join(profiles::table)
+            .filter(profiles::profile_name.eq(profile_name))
+            .order((files::created_at.asc(), files::id.asc()))
+            .select((
+                files::file_name,
+                files::sha256,
+                files::created_at,
+                files::removed_at,
+            ))
+            .load(&mut *conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn content_going_back_to_an_earlier_version_replicates() {
+        let dir = workspace("a-b-a");
+        let (src, dst) = (catalog(&dir.join("src.db")), catalog(&dir.join("dst.db")));
+        let pid = source_profile(&src, &dir.join("src-repo"));
+        let dst_repo = dir.join("dst-repo").display().to_string();
+        let file = dir.join("file.txt");
+
+        ingest(&src, &pid, &file, "A");
+        ingest(&src, &pid, &file, "B");
+        let report = replicate_profile(src.clone(), &pid, dst.clone(), Some(&dst_repo)).unwrap();
+        assert_eq!(report.files_copied, 2);
+
+        ingest(&src, &pid, &file, "A");
+        for _ in 0..2 {
+            let report =
+                replicate_profile(src.clone(), &pid, dst.clone(), Some(&dst_repo)).unwrap();
+            assert!(report.failures.is_empty(), "{:?}", report.failures);
+            assert_eq!(history(&dst, "replicated"), history(&src, "replicated"));
+        }
+
+        let source = history(&src, "replicated");
+        assert_eq!(source.len(), 3);
+        assert_eq!(source.iter().filter(|row| row.3.is_none()).count(), 1);
+        assert_eq!(source[0].1, source[2].1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removed_history_is_replicated() {
+        let dir = workspace("removed");
+        let (src, dst) = (catalog(&dir.join("src.db")), catalog(&dir.join("dst.db")));
+        let pid = source_profile(&src, &dir.join("src-repo"));
+        let dst_repo = dir.join("dst-repo").display().to_string();
+        let file = dir.join("file.txt");
+
+        ingest(&src, &pid, &file, "first");
+        ingest(&src, &pid, &file, "second");
+        let fid = crate::get_files(src.clone(), &pid)[0].id;
+        crate::remove_file(src.clone(), &fid).unwrap();
+
+        let report = replicate_profile(src.clone(), &pid, dst.clone(), Some(&dst_repo)).unwrap();
+        assert_eq!(report.files_copied, 2);
+        let replica = history(&dst, "replicated");
+        assert_eq!(replica, history(&src, "replicated"));
+        assert!(replica.iter().all(|row| row.3.is_some()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_replica_in_the_same_repository_is_refused() {
+        let dir = workspace("same-repo");
+        let (src, dst) = (catalog(&dir.join("src.db")), catalog(&dir.join("dst.db")));
+        let repository = dir.join("repo");
+        let pid = source_profile(&src, &repository);
+        ingest(&src, &pid, &dir.join("file.txt"), "contents");
+
+        // The same directory, named differently
+        let same = dir.join(".").join("repo").display().to_string();
+        let result = replicate_profile(src.clone(), &pid, dst.clone(), Some(&same));
+        assert!(matches!(result, Err(ArchiverError::InvalidInput(_))));
+        assert!(history(&dst, "replicated").is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Expanding files and folders chosen for ingestion into the files that
+//! would be added, so the user can see what an ingest will do before
+//! starting it.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Settings};
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::get_files;
+
+/// What ingesting a set of paths would add to a profile.
+#[derive(Debug, Clone, Default)]
+pub struct IngestPreview {
+    /// Every regular file found, in the order they would be ingested.
+    pub files: Vec<String>,
+    pub total_bytes: u64,
+    /// How many of `files` the profile already tracks at the same path.
+    pub already_tracked: usize,
+    /// Files and folders left out by the `exclude` setting.
+    pub excluded: usize,
+    /// Paths that could not be read, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Walk `paths`, descending into folders, and count what ingesting them into
+/// the profile would add. Paths matching the `exclude` setting are left out.
+/// Symbolic links to folders are not followed, so a link cannot send the walk
+/// round in circles.
+pub fn preview_ingest(
+    conn: DbPool,
+    pid: &i32,
+    paths: &[PathBuf],
+) -> Result<IngestPreview, ArchiverError> {
+    let settings = config::settings();
+    let mut preview = IngestPreview::default();
+    for path in paths {
+        scan(path, &settings, &mut preview);
+    }
+    if preview.files.is_empty() && !preview.skipped.is_empty() {
+        let (path, reason) = &preview.skipped[0];
+        return Err(ArchiverError::InvalidInput(format!(
+            "cannot read {}: {}",
+            path, reason
+        )));
+    }
+
+    let tracked: HashSet<String> = get_files(conn, pid)
+        .into_iter()
+        .map(|file| file.file_name)
+        .collect();
+    preview.already_tracked = preview
+        .files
+        .iter()
+        .filter(|file| tracked.contains(*file))
+        .count();
+    Ok(preview)
+}
+
+fn scan(path: &Path, settings: &Settings, preview: &mut IngestPreview) {
+    if settings.is_excluded(path) {
+        preview.excluded += 1;
+        return;
+    }
+    let skip = |preview: &mut IngestPreview, e: std::io::Error| {
+        preview
+            .skipped
+            .push((path.to_string_lossy().into_owned(), e.to_string()))
+    };
+
+    let link_metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return skip(preview, e),
+    };
+    if link_metadata.is_dir() {
+        let mut children: Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect(),
+            Err(e) => return skip(preview, e),
+        };
+        children.sort();
+        for child in &children {
+            scan(child, settings, preview);
+        }
+        return;
+    }
+
+    // A link counts as the file it points to, if that is a regular file
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return skip(preview, e),
+    };
+    if metadata.is_file() {
+        preview.files.push(path.to_string_lossy().into_owned());
+        preview.total_bytes += metadata.len();
+    }
+}
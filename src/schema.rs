@@ -1,5 +1,22 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    chunks (digest) {
+        digest -> Text,
+        size -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    file_chunks (id) {
+        id -> Integer,
+        file_id -> Integer,
+        seq -> Integer,
+        chunk_digest -> Text,
+    }
+}
+
 diesel::table! {
     files (id) {
         id -> Integer,
@@ -8,6 +25,49 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         profile_id -> Integer,
+        removed_at -> Nullable<Timestamp>,
+        last_verified_at -> Nullable<Timestamp>,
+        hash_algorithm -> Text,
+        modified_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    job_items (id) {
+        id -> Integer,
+        job_id -> Integer,
+        seq -> Integer,
+        target -> Text,
+        state -> Text,
+        reason -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        kind -> Text,
+        profile_id -> Integer,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        owner -> Nullable<Text>,
+        heartbeat_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    legal_holds (id) {
+        id -> Integer,
+        profile_id -> Integer,
+        held_by -> Text,
+        hold_reason -> Text,
+        created_at -> Timestamp,
+        released_by -> Nullable<Text>,
+        release_reason -> Nullable<Text>,
+        released_at -> Nullable<Timestamp>,
     }
 }
 
@@ -17,12 +77,49 @@ diesel::table! {
         profile_name -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        storage_url -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    scrub_schedules (id) {
+        id -> Integer,
+        profile_id -> Integer,
+        interval_hours -> Integer,
+        percent_per_run -> Integer,
+        cycle_days -> Integer,
+        read_limit_mb -> Integer,
+        last_run_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    verifications (id) {
+        id -> Integer,
+        file_id -> Integer,
+        verified_at -> Timestamp,
+        ok -> Bool,
+        detail -> Nullable<Text>,
     }
 }
 
+diesel::joinable!(file_chunks -> chunks (chunk_digest));
+diesel::joinable!(file_chunks -> files (file_id));
 diesel::joinable!(files -> profiles (profile_id));
+diesel::joinable!(job_items -> jobs (job_id));
+diesel::joinable!(jobs -> profiles (profile_id));
+diesel::joinable!(legal_holds -> profiles (profile_id));
+diesel::joinable!(scrub_schedules -> profiles (profile_id));
+diesel::joinable!(verifications -> files (file_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    chunks,
+    file_chunks,
     files,
+    job_items,
+    jobs,
+    legal_holds,
     profiles,
+    scrub_schedules,
+    verifications,
 );
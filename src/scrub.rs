@@ -0,0 +1,400 @@
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::jobs::{create_scrub_job, get_job_items, run_job, ITEM_DONE, ITEM_FAILED};
+use crate::models::{File, NewScrubSchedule, ScrubSchedule};
+use crate::schema;
+
+// How often the scrub daemon looks for schedules that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits how fast a scrub reads from disk by sleeping whenever it gets ahead
+/// of the allowed rate.
+pub struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    consumed: u64,
+}
+
+impl Throttle {
+    /// A throttle allowing `mb_per_sec` MB/s, or `None` if that is zero.
+    pub fn new(mb_per_sec: u32) -> Option<Self> {
+        (mb_per_sec > 0).then(|| Throttle {
+            bytes_per_sec: mb_per_sec as u64 * 1024 * 1024,
+            started: Instant::now(),
+            consumed: 0,
+        })
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        self.consumed += bytes as u64;
+        let due = Duration::from_secs_f64(self.consumed as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    pub files_verified: usize,
+    /// Files that failed verification, with the reason.
+    pub failures: Vec<(String, String)>,
+}
+
+impl ScrubSchedule {
+    /// Whether the schedule should run at `now`.
+    pub fn is_due(&self, now: NaiveDateTime) -> bool {
+        match self.last_run_at {
+            Some(last_run_at) => {
+                now >= last_run_at + ChronoDuration::hours(self.interval_hours as i64)
+            }
+            None => true,
+        }
+    }
+
+    /// How many of `file_count` files one run verifies: the configured
+    /// percentage, or more if that would not cover every file within the cycle.
+    pub fn batch_size(&self, file_count: usize) -> usize {
+        let by_percent = (file_count * self.percent_per_run as usize).div_ceil(100);
+        let runs_per_cycle = (self.cycle_days as usize * 24 / self.interval_hours as usize).max(1);
+        by_percent.max(file_count.div_ceil(runs_per_cycle))
+    }
+}
+
+/// Create or replace a profile's scrub schedule.
+pub fn set_scrub_schedule(
+    conn: DbPool,
+    schedule: &NewScrubSchedule,
+) -> Result<ScrubSchedule, ArchiverError> {
+    use schema::scrub_schedules::dsl::*;
+
+    if schedule.interval_hours <= 0 || schedule.cycle_days <= 0 {
+        return Err(ArchiverError::InvalidInput(
+            "The scrub interval and cycle must be above zero".to_string(),
+        ));
+    }
+    if !(1..=100).contains(&schedule.percent_per_run) {
+        return Err(ArchiverError::InvalidInput(
+            "The percentage per run must be between 1 and 100".to_string(),
+        ));
+    }
+    if schedule.read_limit_mb < 0 {
+        return Err(ArchiverError::InvalidInput(
+            "The read limit cannot be negative".to_string(),
+        ));
+    }
+
+    let mut conn = conn.get()?;
+
+    conn.transaction(|conn| {
+        let updated = diesel::update(scrub_schedules.filter(profile_id.eq(schedule.profile_id)))
+            .set(schedule)
+            .execute(conn)?;
+        if updated == 0 {
+            diesel::insert_into(scrub_schedules)
+                .values(schedule)
+                .execute(conn)?;
+        }
+
+        Ok(scrub_schedules
+            .filter(profile_id.eq(schedule.profile_id))
+            .first::<ScrubSchedule>(conn)?)
+    })
+}
+
+pub fn get_scrub_schedule(conn: DbPool, pid: &i32) -> Option<ScrubSchedule> {
+    try_get_scrub_schedule(conn, pid).expect("Error loading scrub schedule")
+}
+
+pub fn try_get_scrub_schedule(
+    conn: DbPool,
+    pid: &i32,
+) -> Result<Option<ScrubSchedule>, ArchiverError> {
+    use schema::scrub_schedules::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    Ok(scrub_schedules
+        .filter(profile_id.eq(pid))
+        .first::<ScrubSchedule>(&mut *conn)
+        .optional()?)
+}
+
+pub fn remove_scrub_schedule(conn: DbPool, pid: &i32) -> Result<(), ArchiverError> {
+    use schema::scrub_schedules::dsl::*;
+
+    let mut conn = conn.get()?;
+
+    diesel::delete(scrub_schedules.filter(profile_id.eq(pid))).execute(&mut *conn)?;
+    Ok(())
+}
+
+/// Pick the next batch of a profile's files under its scrub schedule, and
+/// mark the schedule as run.
+///
+/// Files that were verified longest ago, or never, go first, so repeated runs
+/// rotate through the whole profile.
+pub fn next_scrub_batch(conn: DbPool, pid: &i32) -> Result<Vec<File>, ArchiverError> {
+    use schema::files::dsl::*;
+    use schema::{scrub_schedules, verifications};
+
+    let schedule = try_get_scrub_schedule(conn.clone(), pid)?.ok_or_else(|| {
+        ArchiverError::InvalidInput("The profile has no scrub schedule".to_string())
+    })?;
+
+    // Going by the history rather than `last_verified_at`, which is left
+    // alone while the profile is under legal hold
+    let last_verified = || {
+        verifications::table
+            .filter(verifications::file_id.eq(id))
+            .select(diesel::dsl::max(verifications::verified_at))
+            .single_value()
+    };
+
+    let mut conn = conn.get()?;
+
+    let file_count: i64 = files
+        .filter(profile_id.eq(pid))
+        .filter(removed_at.is_null())
+        .count()
+        .get_result(&mut *conn)?;
+    let batch = files
+        .filter(profile_id.eq(pid))
+        .filter(removed_at.is_null())
+        // PostgreSQL sorts NULL last and SQLite first, and diesel only has
+        // NULLS FIRST for PostgreSQL, so never-verified files are put first
+        // explicitly
+        .order((
+            last_verified().is_not_null().asc(),
+            last_verified().asc(),
+            id.asc(),
+        ))
+        .limit(schedule.batch_size(file_count as usize) as i64)
+        .load::<File>(&mut *conn)?;
+
+    diesel::update(scrub_schedules::table.find(schedule.id))
+        .set(scrub_schedules::last_run_at.eq(diesel::dsl::now))
+        .execute(&mut *conn)?;
+
+    Ok(batch)
+}
+
+/// Verify the next batch of a profile's files as a scrub job, waiting for it
+/// to finish. Results are recorded in each file's verification history.
+pub fn run_scrub(conn: DbPool, pid: &i32) -> Result<ScrubReport, ArchiverError> {
+    use schema::files::dsl::*;
+
+    let job = create_scrub_job(conn.clone(), pid)?;
+    run_job(conn.clone(), &job.id, |_| {})?;
+
+    let mut report = ScrubReport::default();
+    for item in get_job_items(conn.clone(), &job.id)? {
+        match item.state.as_str() {
+            ITEM_DONE => report.files_verified += 1,
+            ITEM_FAILED => {
+                report.files_verified += 1;
+                let name = item
+                    .target
+                    .parse::<i32>()
+                    .ok()
+                    .and_then(|fid| {
+                        let mut conn = conn.get().ok()?;
+                        files
+                            .find(fid)
+                            .select(file_name)
+                            .first::<String>(&mut *conn)
+                            .ok()
+                    })
+                    .unwrap_or(item.target);
+                report
+                    .failures
+                    .push((name, item.reason.unwrap_or_default()));
+            }
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// The outcome of a scrub for each profile id.
+pub type ScrubResults = Vec<(i32, Result<ScrubReport, ArchiverError>)>;
+
+/// Run every scrub schedule that is due, returning each profile's result.
+pub fn run_due_scrubs(conn: DbPool) -> Result<ScrubResults, ArchiverError> {
+    use schema::scrub_schedules::dsl::*;
+
+    let schedules = {
+        let mut conn = conn.get()?;
+        scrub_schedules.load::<ScrubSchedule>(&mut *conn)?
+    };
+
+    let now = Utc::now().naive_utc();
+    Ok(schedules
+        .into_iter()
+        .filter(|schedule| schedule.is_due(now))
+        .map(|schedule| {
+            (
+                schedule.profile_id,
+                run_scrub(conn.clone(), &schedule.profile_id),
+            )
+        })
+        .collect())
+}
+
+/// Run scrub schedules as they fall due, forever.
+pub fn run_scrub_daemon(conn: DbPool) {
+    loop {
+        match run_due_scrubs(conn.clone()) {
+            Ok(results) => {
+                for (pid, result) in results {
+                    match result {
+                        Ok(report) => {
+                            println!(
+                                "Scrubbed profile {}: {} files verified, {} failed",
+                                pid,
+                                report.files_verified,
+                                report.failures.len()
+                            );
+                            for (file_name, detail) in &report.failures {
+                                println!("  {}: {}", file_name, detail);
+                            }
+                        }
+                        Err(e) => println!("Scrub of profile {} failed: {}", pid, e),
+                    }
+                }
+            }
+            Err(e) => println!("Failed to load scrub schedules: {}", e),
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(interval_hours: i32, percent_per_run: i32, cycle_days: i32) -> ScrubSchedule {
+        ScrubSchedule {
+            id: 1,
+            profile_id: 1,
+            interval_hours,
+            percent_per_run,
+            cycle_days,
+            read_limit_mb: 0,
+            last_run_at: None,
+        }
+    }
+
+    fn at(hour: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn never_run_schedule_is_due() {
+        assert!(schedule(24, 5, 30).is_due(at(0)));
+    }
+
+    #[test]
+    fn schedule_is_due_once_the_interval_passed() {
+        let mut schedule = schedule(6, 5, 30);
+        schedule.last_run_at = Some(at(2));
+        assert!(!schedule.is_due(at(2)));
+        assert!(!schedule.is_due(at(7)));
+        assert!(schedule.is_due(at(8)));
+        assert!(schedule.is_due(at(20)));
+    }
+
+    #[test]
+    fn batch_is_the_percentage_rounded_up() {
+        // 5% nightly covers everything in 20 runs, within the 30 day cycle
+        let schedule = schedule(24, 5, 30);
+        assert_eq!(schedule.batch_size(1000), 50);
+        assert_eq!(schedule.batch_size(10), 1);
+        assert_eq!(schedule.batch_size(0), 0);
+    }
+
+    #[test]
+    fn batch_grows_to_finish_within_the_cycle() {
+        // 1% nightly would take 100 nights, the cycle allows 30
+        let schedule = schedule(24, 1, 30);
+        assert_eq!(schedule.batch_size(3000), 100);
+        assert_eq!(schedule.batch_size(3001), 101);
+    }
+
+    #[test]
+    fn an_interval_longer_than_the_cycle_verifies_everything() {
+        let schedule = schedule(24 * 7, 5, 1);
+        assert_eq!(schedule.batch_size(1000), 1000);
+    }
+
+    #[test]
+    fn throttle_is_off_at_zero() {
+        assert!(Throttle::new(0).is_none());
+        assert!(Throttle::new(10).is_some());
+    }
+
+    #[test]
+    fn never_verified_files_are_scrubbed_first() {
+        use crate::{
+            add_file, create_profile, establish_connection_to, get_profiles, set_profile_storage,
+        };
+        use schema::files::dsl::*;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("tsk-scrub-{}-order", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("ARCHIVE_PASSPHRASE", "scrub test");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        create_profile(conn.clone(), "scrubbed");
+        let pid = get_profiles(conn.clone())[0].id;
+        set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+        for name in ["a", "b", "c"] {
+            let path = dir.join(name);
+            fs::write(&path, name).unwrap();
+            add_file(conn.clone(), path.display().to_string(), &pid, &mut |_| {}).unwrap();
+        }
+        let ids: Vec<i32> = files
+            .select(id)
+            .order(id.asc())
+            .load(&mut *conn.get().unwrap())
+            .unwrap();
+        for (file, verified) in [(ids[0], at(5)), (ids[1], at(1)), (ids[1], at(3))] {
+            diesel::insert_into(schema::verifications::table)
+                .values((
+                    schema::verifications::file_id.eq(file),
+                    schema::verifications::verified_at.eq(verified),
+                    schema::verifications::ok.eq(true),
+                ))
+                .execute(&mut *conn.get().unwrap())
+                .unwrap();
+        }
+        set_scrub_schedule(
+            conn.clone(),
+            &NewScrubSchedule {
+                profile_id: pid,
+                interval_hours: 24,
+                percent_per_run: 50,
+                cycle_days: 365,
+                read_limit_mb: 0,
+            },
+        )
+        .unwrap();
+
+        let batch = next_scrub_batch(conn, &pid).unwrap();
+        assert_eq!(
+            batch.iter().map(|file| file.id).collect::<Vec<_>>(),
+            [ids[2], ids[1]]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,682 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use sha3::{Digest, Sha3_256};
+
+use crate::backend::StorageBackend;
+use crate::error::ArchiverError;
+
+/// Magic prefix of every stored chunk object, followed by a 24 byte nonce and
+/// the XChaCha20-Poly1305 ciphertext of the zstd-compressed chunk.
+const CHUNK_MAGIC: &[u8; 4] = b"TSK1";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+const CONFIG_KEY: &str = "config";
+/// The previous config, kept while a new one is written.
+const OLD_CONFIG_KEY: &str = "config.old";
+// Context for deriving the object name key from the data key
+const OBJECT_ID_CONTEXT: &str = "rs-timeskip-archiver 2026-10-19 object ids";
+
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// The Argon2id settings a passphrase is stretched with. They are written to
+/// the config, so a repository unlocks the same way whatever the argon2
+/// crate's defaults become.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KdfParams {
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    version: u32,
+}
+
+// What new configs are written with
+const DEFAULT_KDF: KdfParams = KdfParams {
+    memory_kib: 19 * 1024,
+    iterations: 2,
+    parallelism: 1,
+    version: 0x13,
+};
+
+/// Content-addressed chunk storage. Every chunk is stored once, no matter how
+/// many files or profiles reference it. Objects are named by a keyed BLAKE3 MAC
+/// of the chunk's SHA3-256 digest, so whoever holds the storage cannot check
+/// whether a known file is in the repository.
+///
+/// Chunks are compressed with zstd and encrypted with a random data key before
+/// they reach the storage backend. The data key is kept in the repository
+/// `config` object, wrapped with a key
+/// derived from the passphrase by Argon2id, so changing the passphrase only
+/// rewrites `config` and never the chunks themselves.
+pub struct ContentStore {
+    backend: Box<dyn StorageBackend>,
+    cipher: XChaCha20Poly1305,
+    compression_level: i32,
+    id_key: [u8; 32],
+}
+
+struct RepositoryConfig {
+    compression_level: i32,
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    wrapped_key: Vec<u8>,
+}
+
+impl ContentStore {
+    /// Create a new repository in `backend`, protected by `passphrase`.
+    /// Refuses unless the backend confirms there is no config yet, so a
+    /// backend that cannot be reached never has its repository replaced.
+    pub fn init(
+        backend: Box<dyn StorageBackend>,
+        passphrase: &str,
+        compression_level: i32,
+    ) -> Result<Self, ArchiverError> {
+        if Self::exists(backend.as_ref())? {
+            return Err(ArchiverError::InvalidInput(format!(
+                "A repository already exists at {}",
+                backend.describe()
+            )));
+        }
+        validate_compression_level(compression_level)?;
+
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let config = wrap_data_key(&data_key, passphrase, DEFAULT_KDF, compression_level)?;
+        write_new_config(backend.as_ref(), &config)?;
+
+        Ok(ContentStore {
+            backend,
+            cipher: XChaCha20Poly1305::new(&data_key),
+            compression_level,
+            id_key: object_id_key(&data_key),
+        })
+    }
+
+    /// Open an existing repository, unlocking its data key with `passphrase`.
+    pub fn open(backend: Box<dyn StorageBackend>, passphrase: &str) -> Result<Self, ArchiverError> {
+        let config = read_config(backend.as_ref())?;
+        let data_key = unwrap_data_key(&config, passphrase)?;
+
+        Ok(ContentStore {
+            backend,
+            cipher: XChaCha20Poly1305::new(&data_key),
+            compression_level: config.compression_level,
+            id_key: object_id_key(&data_key),
+        })
+    }
+
+    pub fn exists(backend: &dyn StorageBackend) -> Result<bool, ArchiverError> {
+        Ok(backend.exists(CONFIG_KEY)? || backend.exists(OLD_CONFIG_KEY)?)
+    }
+
+    pub fn backend(&self) -> &dyn StorageBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn compression_level(&self) -> i32 {
+        self.compression_level
+    }
+
+    /// Change the zstd level used for new chunks. Existing chunks keep the
+    /// level they were written with.
+    pub fn set_compression_level(&mut self, level: i32) -> Result<(), ArchiverError> {
+        validate_compression_level(level)?;
+        let mut config = read_config(self.backend.as_ref())?;
+        config.compression_level = level;
+        write_config(self.backend.as_ref(), &config)?;
+        self.compression_level = level;
+        Ok(())
+    }
+
+    /// Re-wrap the data key under a new passphrase. Chunks are untouched.
+    pub fn rotate_key(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<(), ArchiverError> {
+        let config = read_config(self.backend.as_ref())?;
+        let data_key = unwrap_data_key(&config, old_passphrase)?;
+        let new_config = wrap_data_key(
+            &data_key,
+            new_passphrase,
+            DEFAULT_KDF,
+            config.compression_level,
+        )?;
+        // Never write a config the new passphrase cannot unlock
+        if unwrap_data_key(&new_config, new_passphrase)? != data_key {
+            return Err(ArchiverError::Crypto("key wrapping failed".to_string()));
+        }
+        write_config(self.backend.as_ref(), &new_config)?;
+        Ok(())
+    }
+
+    pub(crate) fn chunk_key(&self, digest: &str) -> String {
+        let id = self.object_id("chunk", digest);
+        format!("chunks/{}/{}", &id[0..2], id)
+    }
+
+    fn object_key(&self, name: &str) -> String {
+        let id = self.object_id("object", name);
+        format!("objects/{}/{}", &id[0..2], id)
+    }
+
+    fn object_id(&self, kind: &str, name: &str) -> String {
+        let mut mac = blake3::Hasher::new_keyed(&self.id_key);
+        mac.update(kind.as_bytes());
+        mac.update(&[0]);
+        mac.update(name.as_bytes());
+        mac.finalize().to_hex().to_string()
+    }
+
+    pub fn has_chunk(&self, digest: &str) -> Result<bool, ArchiverError> {
+        self.backend.exists(&self.chunk_key(digest))
+    }
+
+    /// Store a chunk unless it is already present.
+    pub fn put_chunk(&self, digest: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        let key = self.chunk_key(digest);
+        if self.backend.exists(&key)? {
+            return Ok(());
+        }
+
+        let object = self.seal(digest, data)?;
+        self.backend.put(&key, &object)
+    }
+
+    /// Store a chunk again over a damaged or missing copy, e.g. one rebuilt
+    /// from recovery data. `data` must match `digest`.
+    pub fn replace_chunk(&self, digest: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        if chunk_digest(data) != digest {
+            return Err(ArchiverError::CorruptChunk(digest.to_string()));
+        }
+        let object = self.seal(digest, data)?;
+        self.backend.put(&self.chunk_key(digest), &object)
+    }
+
+    /// Read a chunk back, decrypt it and check it against its digest.
+    pub fn get_chunk(&self, digest: &str) -> Result<Vec<u8>, ArchiverError> {
+        let object = self.backend.get(&self.chunk_key(digest))?;
+        let data = self
+            .open_object(digest, &object)
+            .ok_or_else(|| ArchiverError::CorruptChunk(digest.to_string()))?;
+        if chunk_digest(&data) != digest {
+            return Err(ArchiverError::CorruptChunk(digest.to_string()));
+        }
+        Ok(data)
+    }
+
+    /// Store any other encrypted object, such as recovery data, under `key`.
+    pub fn put_object(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError> {
+        let object = self.seal(key, data)?;
+        self.backend.put(&self.object_key(key), &object)
+    }
+
+    pub fn get_object(&self, key: &str) -> Result<Vec<u8>, ArchiverError> {
+        let object = self.backend.get(&self.object_key(key))?;
+        self.open_object(key, &object)
+            .ok_or_else(|| ArchiverError::CorruptObject(key.to_string()))
+    }
+
+    pub fn has_object(&self, key: &str) -> Result<bool, ArchiverError> {
+        self.backend.exists(&self.object_key(key))
+    }
+
+    fn seal(&self, digest: &str, data: &[u8]) -> Result<Vec<u8>, ArchiverError> {
+        let compressed = zstd::bulk::compress(data, self.compression_level)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // The digest is bound as associated data so a chunk object cannot be
+        // swapped for another one without detection.
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &compressed,
+                    aad: digest.as_bytes(),
+                },
+            )
+            .map_err(|_| ArchiverError::Crypto("chunk encryption failed".to_string()))?;
+
+        let mut object = Vec::with_capacity(CHUNK_MAGIC.len() + NONCE_LEN + ciphertext.len());
+        object.extend_from_slice(CHUNK_MAGIC);
+        object.extend_from_slice(&nonce);
+        object.extend_from_slice(&ciphertext);
+        Ok(object)
+    }
+
+    fn open_object(&self, digest: &str, object: &[u8]) -> Option<Vec<u8>> {
+        if object.len() < CHUNK_MAGIC.len() + NONCE_LEN || &object[..4] != CHUNK_MAGIC {
+            return None;
+        }
+        let nonce = XNonce::from_slice(&object[4..4 + NONCE_LEN]);
+        let compressed = self
+            .cipher
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &object[4 + NONCE_LEN..],
+                    aad: digest.as_bytes(),
+                },
+            )
+            .ok()?;
+        zstd::stream::decode_all(&compressed[..]).ok()
+    }
+}
+
+pub fn chunk_digest(data: &[u8]) -> String {
+    format!("{:x}", Sha3_256::digest(data))
+}
+
+fn validate_compression_level(level: i32) -> Result<(), ArchiverError> {
+    if zstd::compression_level_range().contains(&level) {
+        Ok(())
+    } else {
+        Err(ArchiverError::InvalidInput(format!(
+            "Compression level must be within {:?}",
+            zstd::compression_level_range()
+        )))
+    }
+}
+
+fn object_id_key(data_key: &Key) -> [u8; 32] {
+    blake3::derive_key(OBJECT_ID_CONTEXT, data_key.as_slice())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], kdf: &KdfParams) -> Result<Key, ArchiverError> {
+    let crypto_error = |e: argon2::Error| ArchiverError::Crypto(e.to_string());
+    let mut key = Key::default();
+    let params = Params::new(
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(key.len()),
+    )
+    .map_err(crypto_error)?;
+    let version = Version::try_from(kdf.version).map_err(crypto_error)?;
+    Argon2::new(Algorithm::Argon2id, version, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(crypto_error)?;
+    Ok(key)
+}
+
+fn wrap_data_key(
+    data_key: &Key,
+    passphrase: &str,
+    kdf: KdfParams,
+    compression_level: i32,
+) -> Result<RepositoryConfig, ArchiverError> {
+    if passphrase.is_empty() {
+        return Err(ArchiverError::InvalidInput(
+            "The repository passphrase must not be empty".to_string(),
+        ));
+    }
+
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key_cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt, &kdf)?);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let wrapped_key = key_cipher
+        .encrypt(&nonce, data_key.as_slice())
+        .map_err(|_| ArchiverError::Crypto("key wrapping failed".to_string()))?;
+
+    Ok(RepositoryConfig {
+        compression_level,
+        kdf,
+        salt,
+        nonce: nonce.to_vec(),
+        wrapped_key,
+    })
+}
+
+fn unwrap_data_key(config: &RepositoryConfig, passphrase: &str) -> Result<Key, ArchiverError> {
+    let key_cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &config.salt, &config.kdf)?);
+    let data_key = key_cipher
+        .decrypt(
+            XNonce::from_slice(&config.nonce),
+            config.wrapped_key.as_slice(),
+        )
+        .map_err(|_| ArchiverError::WrongPassphrase)?;
+    Ok(*Key::from_slice(&data_key))
+}
+
+/// Read the repository config. Should writing a new config have failed
+/// halfway, the previous one kept in `config.old` is used instead.
+fn read_config(backend: &dyn StorageBackend) -> Result<RepositoryConfig, ArchiverError> {
+    let current = match backend.get(CONFIG_KEY) {
+        Ok(contents) => parse_config(&contents),
+        Err(e) if !backend.exists(OLD_CONFIG_KEY)? => return Err(e),
+        Err(_) => None,
+    };
+    match current {
+        Some(config) => Ok(config),
+        None if backend.exists(OLD_CONFIG_KEY)? => {
+            parse_config(&backend.get(OLD_CONFIG_KEY)?).ok_or_else(|| invalid_config(backend))
+        }
+        None => Err(invalid_config(backend)),
+    }
+}
+
+fn invalid_config(backend: &dyn StorageBackend) -> ArchiverError {
+    ArchiverError::InvalidInput(format!(
+        "Invalid repository config in {}",
+        backend.describe()
+    ))
+}
+
+fn parse_config(contents: &[u8]) -> Option<RepositoryConfig> {
+    let contents = std::str::from_utf8(contents).ok()?;
+
+    let mut compression_level = None;
+    let mut kdf = [None; 4];
+    let mut salt = None;
+    let mut nonce = None;
+    let mut wrapped_key = None;
+
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key.trim() {
+            "compression_level" => compression_level = value.trim().parse().ok(),
+            "kdf_memory_kib" => kdf[0] = Some(value.trim().parse().ok()),
+            "kdf_iterations" => kdf[1] = Some(value.trim().parse().ok()),
+            "kdf_parallelism" => kdf[2] = Some(value.trim().parse().ok()),
+            "kdf_version" => kdf[3] = Some(value.trim().parse().ok()),
+            "salt" => salt = hex::decode(value.trim()).ok(),
+            "nonce" => nonce = hex::decode(value.trim()).ok(),
+            "wrapped_key" => wrapped_key = hex::decode(value.trim()).ok(),
+            _ => {}
+        }
+    }
+
+    // Without every setting, unlocking would only guess
+    let [memory_kib, iterations, parallelism, version] = kdf;
+    let kdf = KdfParams {
+        memory_kib: memory_kib??,
+        iterations: iterations??,
+        parallelism: parallelism??,
+        version: version??,
+    };
+
+    Some(RepositoryConfig {
+        compression_level: compression_level?,
+        kdf,
+        salt: salt?,
+        nonce: nonce.filter(|nonce| nonce.len() == NONCE_LEN)?,
+        wrapped_key: wrapped_key?,
+    })
+}
+
+fn format_config(config: &RepositoryConfig) -> String {
+    format!(
+        "version=2\ncipher=xchacha20poly1305\nkdf=argon2id\nkdf_memory_kib={}\nkdf_iterations={}\nkdf_parallelism={}\nkdf_version={}\ncompression=zstd\ncompression_level={}\nsalt={}\nnonce={}\nwrapped_key={}\nobject_ids=blake3-keyed\n",
+        config.kdf.memory_kib,
+        config.kdf.iterations,
+        config.kdf.parallelism,
+        config.kdf.version,
+        config.compression_level,
+        hex::encode(&config.salt),
+        hex::encode(&config.nonce),
+        hex::encode(&config.wrapped_key),
+    )
+}
+
+/// Replace the repository config. The new config is written under a unique
+/// temporary name and read back before it is moved into place, and the
+/// previous one stays in `config.old` until the new one reads back intact, so
+/// a failure at any point leaves a config that unlocks the data key.
+fn write_config(
+    backend: &dyn StorageBackend,
+    config: &RepositoryConfig,
+) -> Result<(), ArchiverError> {
+    let contents = format_config(config);
+    let mut id = [0u8; 8];
+    OsRng.fill_bytes(&mut id);
+    let tmp_key = format!("config.{}.tmp", hex::encode(id));
+
+    let written = (|| {
+        backend.put(&tmp_key, contents.as_bytes())?;
+        if backend.get(&tmp_key)? != contents.as_bytes() {
+            return Err(invalid_config(backend));
+        }
+        if backend.exists(CONFIG_KEY)? {
+            backend.put(OLD_CONFIG_KEY, &backend.get(CONFIG_KEY)?)?;
+        }
+        backend.rename(&tmp_key, CONFIG_KEY)?;
+        if backend.get(CONFIG_KEY)? != contents.as_bytes() {
+            return Err(invalid_config(backend));
+        }
+        Ok(())
+    })();
+    if written.is_err() {
+        let _ = backend.delete(&tmp_key);
+        return written;
+    }
+    backend.delete(OLD_CONFIG_KEY)
+}
+
+/// Write the config of a new repository. Unlike [`write_config`] it never
+/// replaces or deletes a config: should one have appeared since
+/// [`ContentStore::init`] looked, or its absence not be confirmed, the new
+/// config is discarded.
+fn write_new_config(
+    backend: &dyn StorageBackend,
+    config: &RepositoryConfig,
+) -> Result<(), ArchiverError> {
+    let contents = format_config(config);
+    let mut id = [0u8; 8];
+    OsRng.fill_bytes(&mut id);
+    let tmp_key = format!("config.{}.tmp", hex::encode(id));
+
+    let written = (|| {
+        backend.put(&tmp_key, contents.as_bytes())?;
+        if backend.get(&tmp_key)? != contents.as_bytes() {
+            return Err(invalid_config(backend));
+        }
+        if ContentStore::exists(backend)? {
+            return Err(ArchiverError::InvalidInput(format!(
+                "A repository already exists at {}",
+                backend.describe()
+            )));
+        }
+        backend.rename(&tmp_key, CONFIG_KEY)
+    })();
+    if written.is_err() {
+        let _ = backend.delete(&tmp_key);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LocalBackend;
+    use std::path::{Path, PathBuf};
+
+    fn repository(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tsk-store-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn backend(path: &Path) -> Box<dyn StorageBackend> {
+        Box::new(LocalBackend::new(path.to_path_buf()))
+    }
+
+    fn stored_names(path: &Path) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn object_names_do_not_reveal_digests() {
+        let path = repository("keyed");
+        let store =
+            ContentStore::init(backend(&path), "secret", DEFAULT_COMPRESSION_LEVEL).unwrap();
+        let data = b"a known file".to_vec();
+        let digest = chunk_digest(&data);
+        store.put_chunk(&digest, &data).unwrap();
+        store
+            .put_object(&format!("parity/{}/index", digest), b"index")
+            .unwrap();
+
+        let names = stored_names(&path);
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert!(
+            names.iter().all(|name| !name.contains(&digest)),
+            "{:?}",
+            names
+        );
+
+        let store = ContentStore::open(backend(&path), "secret").unwrap();
+        assert_eq!(store.get_chunk(&digest).unwrap(), data);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    // A local directory whose lookups fail, as over a dropped connection
+    struct Unreachable(LocalBackend);
+
+    impl StorageBackend for Unreachable {
+        fn put(&self, key: &str, data: &[u8]) -> Result<(), ArchiverError> {
+            self.0.put(key, data)
+        }
+
+        fn get(&self, key: &str) -> Result<Vec<u8>, ArchiverError> {
+            self.0.get(key)
+        }
+
+        fn exists(&self, _key: &str) -> Result<bool, ArchiverError> {
+            Err(ArchiverError::Storage("connection reset".to_string()))
+        }
+
+        fn delete(&self, key: &str) -> Result<(), ArchiverError> {
+            self.0.delete(key)
+        }
+
+        fn describe(&self) -> String {
+            self.0.describe()
+        }
+    }
+
+    #[test]
+    fn init_refuses_when_the_config_may_exist() {
+        let path = repository("unreachable");
+        ContentStore::init(backend(&path), "secret", DEFAULT_COMPRESSION_LEVEL).unwrap();
+        let config = std::fs::read(path.join(CONFIG_KEY)).unwrap();
+
+        let unreachable = Box::new(Unreachable(LocalBackend::new(path.clone())));
+        assert!(matches!(
+            ContentStore::init(unreachable, "other", DEFAULT_COMPRESSION_LEVEL),
+            Err(ArchiverError::Storage(_))
+        ));
+        assert!(matches!(
+            ContentStore::init(backend(&path), "other", DEFAULT_COMPRESSION_LEVEL),
+            Err(ArchiverError::InvalidInput(_))
+        ));
+        assert_eq!(std::fs::read(path.join(CONFIG_KEY)).unwrap(), config);
+        assert_eq!(stored_names(&path), vec!["config".to_string()]);
+        ContentStore::open(backend(&path), "secret").unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_unreadable_repository_is_not_a_missing_one() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = repository("unreadable");
+        std::fs::create_dir_all(&path).unwrap();
+        let inner = path.join("repository");
+        ContentStore::init(backend(&inner), "secret", DEFAULT_COMPRESSION_LEVEL).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads anything, so there is nothing to check
+        let readable = std::fs::read_dir(&path).is_ok();
+        let exists = ContentStore::exists(backend(&inner).as_ref());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        if !readable {
+            assert!(exists.is_err(), "{:?}", exists.map(|_| ()));
+        }
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn the_config_keeps_the_kdf_settings() {
+        let path = repository("kdf");
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let kdf = KdfParams {
+            memory_kib: 8 * 1024,
+            iterations: 3,
+            parallelism: 2,
+            version: 0x13,
+        };
+        let config = wrap_data_key(&data_key, "secret", kdf, DEFAULT_COMPRESSION_LEVEL).unwrap();
+        write_config(backend(&path).as_ref(), &config).unwrap();
+
+        let contents = std::fs::read_to_string(path.join(CONFIG_KEY)).unwrap();
+        assert!(contents.contains("kdf_memory_kib=8192\n"), "{}", contents);
+        assert!(contents.contains("kdf_iterations=3\n"), "{}", contents);
+        assert!(contents.contains("kdf_parallelism=2\n"), "{}", contents);
+        assert!(contents.contains("kdf_version=19\n"), "{}", contents);
+        let store = ContentStore::open(backend(&path), "secret").unwrap();
+        assert_eq!(object_id_key(&data_key), store.id_key);
+
+        // Without the settings, unlocking is not guessed at
+        let missing: String = contents
+            .lines()
+            .filter(|line| !line.starts_with("kdf_"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(parse_config(missing.as_bytes()).is_none());
+        std::fs::write(path.join(CONFIG_KEY), missing).unwrap();
+        assert!(matches!(
+            ContentStore::open(backend(&path), "secret"),
+            Err(ArchiverError::InvalidInput(_))
+        ));
+
+        // With only some of the settings, unlocking is not guessed at
+        let partial = contents.replace("kdf_iterations=3\n", "");
+        assert!(parse_config(partial.as_bytes()).is_none());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn rotating_the_key_leaves_one_config() {
+        let path = repository("rotate");
+        let store = ContentStore::init(backend(&path), "old", DEFAULT_COMPRESSION_LEVEL).unwrap();
+        store.rotate_key("old", "new").unwrap();
+
+        assert_eq!(stored_names(&path), vec!["config".to_string()]);
+        assert!(matches!(
+            ContentStore::open(backend(&path), "old"),
+            Err(ArchiverError::WrongPassphrase)
+        ));
+        ContentStore::open(backend(&path), "new").unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn a_torn_config_falls_back_to_the_previous_one() {
+        let path = repository("torn");
+        ContentStore::init(backend(&path), "secret", DEFAULT_COMPRESSION_LEVEL).unwrap();
+        // As left behind by a crash after the old config was saved and while
+        // the new one was being written
+        std::fs::copy(path.join(CONFIG_KEY), path.join(OLD_CONFIG_KEY)).unwrap();
+        std::fs::write(path.join(CONFIG_KEY), "version=2\ncompression_level=3\nsa").unwrap();
+
+        ContentStore::open(backend(&path), "secret").unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,18 +1,41 @@
+use futures::channel::oneshot;
 use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
     sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         mpsc::{self, Receiver, Sender},
-        Arc, Mutex,
+        Arc, Mutex, PoisonError,
     },
+    task::{Context, Poll},
     thread,
 };
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    sender: Option<mpsc::Sender<Message>>,
+}
+
+// State the pool shares with its workers, so a dying worker can start its
+// own replacement.
+struct Shared {
+    receiver: Mutex<Receiver<Message>>,
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+    size: AtomicUsize,
+    next_id: AtomicUsize,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    Run(Job),
+    /// Ask one worker to exit, used when the pool shrinks.
+    Stop,
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -24,64 +47,357 @@ impl ThreadPool {
     pub fn new(size: usize) -> Self {
         assert!(size > 0);
 
-        let (sender, receiver): (Sender<Job>, Receiver<Job>) = mpsc::channel();
+        let (sender, receiver): (Sender<Message>, Receiver<Message>) = mpsc::channel();
 
-        let receiver: Arc<Mutex<Receiver<Job>>> = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            threads: Mutex::new(Vec::with_capacity(size)),
+            size: AtomicUsize::new(size),
+            next_id: AtomicUsize::new(0),
+        });
 
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        for _ in 0..size {
+            spawn_worker(&shared);
         }
 
         ThreadPool {
-            workers,
+            shared,
             sender: Some(sender),
         }
     }
 
+    /// Run `f` on the pool without waiting for it. A panic in `f` is printed
+    /// and does not take the worker down.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.send(Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                println!("A pool job panicked: {}", panic_message(&*payload));
+            }
+        }));
+    }
+
+    /// Run `f` on the pool, returning a handle to its result.
+    pub fn spawn<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_cancellable(move |_| f())
+    }
+
+    /// Run `f` on the pool with a token it should check now and then,
+    /// returning once it sees [`CancellationToken::is_cancelled`]. A job
+    /// cancelled before a worker picks it up does not run at all.
+    pub fn spawn_cancellable<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let job_token = token.clone();
+        let (sender, receiver) = oneshot::channel();
+
+        self.send(Box::new(move || {
+            let result = if job_token.is_cancelled() {
+                Err(JobError::Cancelled)
+            } else {
+                panic::catch_unwind(AssertUnwindSafe(|| f(&job_token)))
+                    .map_err(|payload| JobError::Panicked(panic_message(&*payload)))
+            };
+            // Nobody is waiting if the handle was dropped
+            let _ = sender.send(result);
+        }));
+
+        JobHandle { receiver, token }
+    }
+
+    /// The number of worker threads the pool is meant to have.
+    pub fn size(&self) -> usize {
+        self.shared.size.load(Ordering::SeqCst)
+    }
+
+    /// Change the number of worker threads. New workers start at once;
+    /// surplus workers exit once the jobs queued before the call have been
+    /// picked up, never in the middle of a job.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    pub fn resize(&self, size: usize) {
+        assert!(size > 0);
+
+        let old_size = self.shared.size.swap(size, Ordering::SeqCst);
+        for _ in old_size..size {
+            spawn_worker(&self.shared);
+        }
+        for _ in size..old_size {
+            self.sender.as_ref().unwrap().send(Message::Stop).unwrap();
+        }
+
+        lock(&self.shared.threads).retain(|thread| !thread.is_finished());
+    }
+
+    fn send(&self, job: Job) {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(Message::Run(job))
+            .unwrap();
     }
 }
 
 impl Drop for ThreadPool {
+    /// Let the workers finish every queued job, then wait for them to exit.
     fn drop(&mut self) {
         drop(self.sender.take());
 
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+        // Popping one at a time, as a worker that dies now adds its replacement
+        loop {
+            let Some(thread) = lock(&self.shared.threads).pop() else {
+                break;
+            };
+            if thread.join().is_err() {
+                println!("A pool worker panicked while shutting down");
             }
         }
     }
 }
 
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
-}
+fn spawn_worker(shared: &Arc<Shared>) {
+    let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+    let worker_shared = Arc::clone(shared);
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
-        Worker {
-            id,
-            thread: Some(thread::spawn(move || loop {
-                let message = receiver.lock().unwrap().recv();
+    let thread = thread::Builder::new()
+        .name(format!("pool-worker-{}", id))
+        .spawn(move || {
+            let _sentinel = Sentinel {
+                shared: Arc::clone(&worker_shared),
+                id,
+            };
+            loop {
+                let message = lock(&worker_shared.receiver).recv();
 
                 match message {
-                    Ok(job) => {
-                        job();
-                    }
-                    Err(_) => {
-                        break;
-                    }
+                    Ok(Message::Run(job)) => job(),
+                    Ok(Message::Stop) | Err(_) => break,
                 }
-            })),
+            }
+        })
+        .expect("failed to spawn a pool worker");
+
+    lock(&shared.threads).push(thread);
+}
+
+// Jobs catch their own panics, so this only fires if a worker dies some other
+// way. It starts a replacement to keep the pool at its size.
+struct Sentinel {
+    shared: Arc<Shared>,
+    id: usize,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            println!("Pool worker {} died, starting a replacement", self.id);
+            spawn_worker(&self.shared);
         }
     }
 }
+
+// A worker that died holding a lock must not take the rest of the pool down.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+/// Lets a job be asked to stop. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Why a job spawned on the pool produced no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// The job was cancelled before it started.
+    Cancelled,
+    /// The job panicked, with the panic message.
+    Panicked(String),
+    /// The job was dropped without running.
+    Lost,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Cancelled => write!(f, "cancelled before it started"),
+            JobError::Panicked(message) => write!(f, "panicked: {}", message),
+            JobError::Lost => write!(f, "dropped before it ran"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// The result of a job spawned on the pool. Wait for it with
+/// [`JobHandle::join`], or `.await` it.
+pub struct JobHandle<T> {
+    receiver: oneshot::Receiver<Result<T, JobError>>,
+    token: CancellationToken,
+}
+
+impl<T> JobHandle<T> {
+    /// Ask the job to stop; see [`ThreadPool::spawn_cancellable`].
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Block until the job has finished.
+    pub fn join(self) -> Result<T, JobError> {
+        futures::executor::block_on(self)
+    }
+
+    /// The job's result if it has finished, without waiting. The result is
+    /// handed out only once; later calls return [`JobError::Lost`].
+    pub fn try_join(&mut self) -> Option<Result<T, JobError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => result,
+            Err(oneshot::Canceled) => Some(Err(JobError::Lost)),
+        }
+    }
+}
+
+impl<T> Future for JobHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(JobError::Lost)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn a_panicking_job_does_not_take_the_pool_down() {
+        let pool = ThreadPool::new(1);
+        let panicked = pool.spawn(|| -> u32 { panic!("boom") });
+        assert_eq!(panicked.join(), Err(JobError::Panicked("boom".to_string())));
+
+        pool.execute(|| panic!("boom again"));
+        assert_eq!(pool.spawn(|| 6 * 7).join(), Ok(42));
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn a_job_cancelled_while_queued_does_not_run() {
+        let pool = ThreadPool::new(1);
+        let (release, wait) = channel::<()>();
+        let blocker = pool.spawn(move || wait.recv().unwrap());
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let job_ran = Arc::clone(&ran);
+        let queued = pool.spawn(move || job_ran.store(true, Ordering::SeqCst));
+        queued.cancel();
+        release.send(()).unwrap();
+
+        assert_eq!(blocker.join(), Ok(()));
+        assert_eq!(queued.join(), Err(JobError::Cancelled));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn a_running_job_sees_its_cancellation() {
+        let pool = ThreadPool::new(1);
+        let (started, wait_started) = channel();
+        let handle = pool.spawn_cancellable(move |token| {
+            started.send(()).unwrap();
+            let mut rounds = 0;
+            while !token.is_cancelled() {
+                rounds += 1;
+                thread::sleep(Duration::from_millis(1));
+            }
+            rounds
+        });
+
+        wait_started.recv().unwrap();
+        handle.cancel();
+        assert!(handle.token().is_cancelled());
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn try_join_hands_the_result_out_once() {
+        let pool = ThreadPool::new(1);
+        let mut handle = pool.spawn(|| "done");
+        let result = loop {
+            if let Some(result) = handle.try_join() {
+                break result;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(result, Ok("done"));
+        assert_eq!(handle.try_join(), Some(Err(JobError::Lost)));
+    }
+
+    #[test]
+    fn resizing_changes_the_number_of_workers() {
+        let pool = ThreadPool::new(1);
+        pool.resize(4);
+        assert_eq!(pool.size(), 4);
+
+        // Four jobs that wait for each other only finish on four workers
+        let (arrived, count) = channel();
+        let barrier = Arc::new(std::sync::Barrier::new(4));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (arrived, barrier) = (arrived.clone(), Arc::clone(&barrier));
+                pool.spawn(move || {
+                    barrier.wait();
+                    arrived.send(()).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join(), Ok(()));
+        }
+        assert_eq!(count.try_iter().count(), 4);
+
+        pool.resize(2);
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.spawn(|| 1).join(), Ok(1));
+    }
+}
@@ -0,0 +1,264 @@
+//! A profile's history as a series of capture points, and what the profile
+//! held at each of them.
+//!
+//! There is no snapshot table: files are added and removed one at a time, at
+//! the times in `files.created_at` and `files.removed_at`. Changes that follow
+//! each other with less than [`CAPTURE_GAP_SECS`] between them are taken as one
+//! capture, so an ingest of many files is a single point on the timeline.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::models::File;
+use crate::schema::files;
+
+/// The longest pause, in seconds, between two changes that still counts as
+/// one capture.
+pub const CAPTURE_GAP_SECS: i64 = 5 * 60;
+
+/// A moment the profile changed, with how much changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturePoint {
+    /// When the first change of the capture was made.
+    pub started_at: NaiveDateTime,
+    /// When the last change was made; the profile "as of" the capture is its
+    /// state at this moment.
+    pub at: NaiveDateTime,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// How the profile changed between two capture points.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<File>,
+    pub removed: Vec<File>,
+    /// The path's file at the earlier point and at the later one.
+    pub modified: Vec<(File, File)>,
+}
+
+#[derive(Clone, Default)]
+pub struct Timeline {
+    /// Every file the profile has tracked, including removed ones.
+    files: Vec<File>,
+    pub captures: Vec<CapturePoint>,
+}
+
+impl fmt::Debug for Timeline {
+    // The file list is far too long to print
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timeline({} captures)", self.captures.len())
+    }
+}
+
+impl Timeline {
+    /// Build the timeline of a profile from all of its files, removed or not.
+    pub fn new(files: Vec<File>) -> Self {
+        // Additions sort before removals made at the same time
+        let mut changes: Vec<(NaiveDateTime, bool)> = files
+            .iter()
+            .map(|file| (file.created_at, true))
+            .chain(
+                files
+                    .iter()
+                    .filter_map(|file| file.removed_at.map(|removed_at| (removed_at, false))),
+            )
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let mut captures: Vec<CapturePoint> = Vec::new();
+        for (at, added) in changes {
+            let capture = match captures.last_mut() {
+                Some(capture) if at - capture.at < Duration::seconds(CAPTURE_GAP_SECS) => capture,
+                _ => {
+                    captures.push(CapturePoint {
+                        started_at: at,
+                        at,
+                        added: 0,
+                        removed: 0,
+                    });
+                    captures.last_mut().unwrap()
+                }
+            };
+            capture.at = at;
+            if added {
+                capture.added += 1;
+            } else {
+                capture.removed += 1;
+            }
+        }
+
+        Timeline { files, captures }
+    }
+
+    /// The files the profile tracked at `at`, one per path, sorted by path.
+    /// Where a path was ingested more than once, the latest version counts.
+    pub fn as_of(&self, at: NaiveDateTime) -> Vec<&File> {
+        let mut by_path: BTreeMap<&str, &File> = BTreeMap::new();
+        for file in self.files.iter().filter(|file| {
+            file.created_at <= at && file.removed_at.is_none_or(|removed| removed > at)
+        }) {
+            let newer = by_path
+                .get(file.file_name.as_str())
+                .is_none_or(|known| (file.created_at, file.id) > (known.created_at, known.id));
+            if newer {
+                by_path.insert(&file.file_name, file);
+            }
+        }
+        by_path.into_values().collect()
+    }
+
+    /// What changed between `from` and `to`. A path counts as modified when
+    /// its content digest differs between the two.
+    pub fn diff(&self, from: NaiveDateTime, to: NaiveDateTime) -> SnapshotDiff {
+        let before: BTreeMap<&str, &File> = self
+            .as_of(from)
+            .into_iter()
+            .map(|file| (file.file_name.as_str(), file))
+            .collect();
+        let after: BTreeMap<&str, &File> = self
+            .as_of(to)
+            .into_iter()
+            .map(|file| (file.file_name.as_str(), file))
+            .collect();
+
+        let mut diff = SnapshotDiff::default();
+        for (path, file) in &after {
+            match before.get(path) {
+                None => diff.added.push((*file).clone()),
+                Some(old) if old.sha256 != file.sha256 => {
+                    diff.modified.push(((*old).clone(), (*file).clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = before
+            .iter()
+            .filter(|(path, _)| !after.contains_key(*path))
+            .map(|(_, file)| (*file).clone())
+            .collect();
+        diff
+    }
+}
+
+/// Load the timeline of a profile.
+pub fn load_timeline(conn: DbPool, pid: &i32) -> Result<Timeline, ArchiverError> {
+    let mut conn = conn.get()?;
+    let history = files::table
+        .filter(files::profile_id.eq(pid))
+        .order(files::created_at.asc())
+        .load::<File>(&mut *conn)?;
+    Ok(Timeline::new(history))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Minutes after a fixed start, so captures are easy to tell apart
+    fn at(minutes: i64) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::minutes(minutes)
+    }
+
+    fn file(id: i32, path: &str, digest: &str, added: i64, removed: Option<i64>) -> File {
+        File {
+            id,
+            file_name: path.to_string(),
+            sha256: digest.to_string(),
+            created_at: at(added),
+            updated_at: at(added),
+            profile_id: 1,
+            removed_at: removed.map(at),
+            last_verified_at: None,
+            hash_algorithm: "blake3".to_string(),
+            modified_at: None,
+        }
+    }
+
+    fn paths(files: &[File]) -> Vec<&str> {
+        files.iter().map(|file| file.file_name.as_str()).collect()
+    }
+
+    // a and b are ingested together, a is changed an hour later, b removed
+    // and c added two hours later
+    fn history() -> Timeline {
+        Timeline::new(vec![
+            file(1, "/a", "a1", 0, Some(60)),
+            file(2, "/b", "b1", 1, Some(120)),
+            file(3, "/a", "a2", 60, None),
+            file(4, "/c", "c1", 121, None),
+        ])
+    }
+
+    #[test]
+    fn changes_close_together_are_one_capture() {
+        let captures = history().captures;
+        assert_eq!(captures.len(), 3);
+        assert_eq!((captures[0].started_at, captures[0].at), (at(0), at(1)));
+        assert_eq!((captures[0].added, captures[0].removed), (2, 0));
+        assert_eq!((captures[1].added, captures[1].removed), (1, 1));
+        assert_eq!((captures[2].added, captures[2].removed), (1, 1));
+    }
+
+    #[test]
+    fn as_of_shows_the_files_tracked_at_the_time() {
+        let timeline = history();
+        assert!(timeline.as_of(at(-1)).is_empty());
+
+        let first: Vec<File> = timeline.as_of(at(1)).into_iter().cloned().collect();
+        assert_eq!(paths(&first), ["/a", "/b"]);
+        assert_eq!(first[0].sha256, "a1");
+
+        // A file counts until the moment it is removed
+        let second: Vec<File> = timeline.as_of(at(60)).into_iter().cloned().collect();
+        assert_eq!(paths(&second), ["/a", "/b"]);
+        assert_eq!(second[0].sha256, "a2");
+
+        let last: Vec<File> = timeline.as_of(at(121)).into_iter().cloned().collect();
+        assert_eq!(paths(&last), ["/a", "/c"]);
+    }
+
+    #[test]
+    fn as_of_prefers_the_latest_of_overlapping_versions() {
+        // Re-ingested without removing the older row, as a replica might be
+        let timeline = Timeline::new(vec![
+            file(1, "/a", "old", 0, None),
+            file(2, "/a", "new", 0, None),
+        ]);
+        assert_eq!(timeline.as_of(at(0))[0].sha256, "new");
+    }
+
+    #[test]
+    fn diff_sorts_changes_into_added_removed_and_modified() {
+        let timeline = history();
+
+        let diff = timeline.diff(at(1), at(121));
+        assert_eq!(paths(&diff.added), ["/c"]);
+        assert_eq!(paths(&diff.removed), ["/b"]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(
+            (
+                diff.modified[0].0.sha256.as_str(),
+                diff.modified[0].1.sha256.as_str()
+            ),
+            ("a1", "a2")
+        );
+
+        // Backwards, the same changes the other way round
+        let diff = timeline.diff(at(121), at(1));
+        assert_eq!(paths(&diff.added), ["/b"]);
+        assert_eq!(paths(&diff.removed), ["/c"]);
+        assert_eq!(diff.modified[0].0.sha256, "a2");
+
+        let diff = timeline.diff(at(60), at(60));
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty());
+    }
+}
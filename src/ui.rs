@@ -1,39 +1,196 @@
-use diesel::SqliteConnection;
-use futures::channel::mpsc::{self, Sender};
+mod file_table;
+
+use chrono::NaiveDateTime;
+use iced::keyboard::{self, KeyCode, Modifiers};
 use iced::widget::{
-    text_input, Button, Column, Container, PickList, ProgressBar, Row, Rule, Scrollable, Space,
-    Text,
+    scrollable, text_input, Button, Column, Container, PickList, ProgressBar, Row, Rule,
+    Scrollable, Space, Text,
+};
+use iced::{event, mouse, subscription, theme, window, Event, Subscription};
+use iced::{Alignment, Application, Color, Command, Element, Length, Settings};
+use rs_timeskip_archiver::config::{self, Config, Source, UiTheme, KEYS};
+#[cfg(unix)]
+use rs_timeskip_archiver::daemon::client::DaemonClient;
+use rs_timeskip_archiver::db::DbPool;
+use rs_timeskip_archiver::file_tree::{
+    load_file_tree, FileTree, Folder, TreeKey, TreeNode, VerificationStatus,
 };
-use iced::Subscription;
-use iced::{Alignment, Application, Command, Element, Length, Settings};
-use rs_timeskip_archiver::models::{File, Profile};
+use rs_timeskip_archiver::jobs::{
+    cancel_job, create_files_verify_job, create_ingest_job, get_job, get_job_items,
+    interrupted_jobs, job_progress, list_jobs, run_job, JobKind, JobSummary, ITEM_FAILED,
+};
+use rs_timeskip_archiver::models::{File, Job, LegalHold, Profile, ScrubSchedule, Verification};
+use rs_timeskip_archiver::progress::{format_bytes, Progress};
+use rs_timeskip_archiver::scan::{preview_ingest, IngestPreview};
+use rs_timeskip_archiver::scrub::try_get_scrub_schedule;
 use rs_timeskip_archiver::thread_pool::ThreadPool;
-use rs_timeskip_archiver::{get_files, get_profiles};
+use rs_timeskip_archiver::timeline::{load_timeline, SnapshotDiff, Timeline};
+use rs_timeskip_archiver::{get_files, get_profiles, get_verification_history, try_get_legal_hold};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use file_table::{FileTable, TableColumn};
+
+// Files listed per folder before a "show more" row, and the rows Page Up
+// and Page Down move by
+const FILES_SHOWN: usize = 500;
+const PAGE_ROWS: usize = 20;
+
+// Print a diagnostic on stdout if ARCHIVER_DEBUG is set. The window shows
+// everything the user needs to know.
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if debug_logging() {
+            println!($($arg)*);
+        }
+    };
+}
 
-pub fn run_ui(connection: SqliteConnection) -> Result<(), iced::Error> {
-    // Run the UI with the given database connection
-    Archiver::run(Settings::with_flags(Arc::new(Mutex::new(connection))))
+fn debug_logging() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var_os("ARCHIVER_DEBUG").is_some())
+}
+
+pub fn run_ui(connection: DbPool) -> Result<(), iced::Error> {
+    // Run the UI with the given database connections
+    Archiver::run(Settings::with_flags(connection))
 }
 
 // Define the possible messages that can be sent to the UI
 #[derive(Debug, Clone)]
 pub enum Message {
     ProfileInputChanged(String),
-    ProgressTick(usize),
+    ProgressTick,
     CreateProfile,
     LoadProfiles,
     ProfilesLoaded(Vec<Profile>),
     ProfileSelected(Profile),
     LoadFiles,
-    FilesLoaded(Vec<File>),
-    FileSelected(File),
+    TreeLoaded(i32, FileTree),
+    TreeRowClicked(TreeKey),
+    TreeKeyPressed(KeyCode, Modifiers),
+    ModifiersChanged(Modifiers),
+    ShowMoreFiles(String),
+    SortTable(TableColumn),
+    TableScrolled(f32),
+    TableRowClicked(i32),
+    CopyDigest(String),
+    ColumnResizeStarted(TableColumn),
+    ColumnResized(f32),
+    ColumnResizeEnded,
+    VerifySelection,
     OpenFileDialog,
+    OpenFolderDialog,
     FileChosen(Result<Vec<PathBuf>, String>),
-    ProfileRefresh,
+    FilesHovered(bool),
+    FileDropped(PathBuf),
+    IngestPreviewed(Vec<PathBuf>, Result<IngestPreview, String>),
+    ConfirmIngest,
+    CancelIngest,
+    TimelineLoaded(i32, Arc<Timeline>),
+    CaptureClicked(NaiveDateTime),
+    SnapshotLoaded(Snapshot),
+    CloseSnapshot,
+    ToggleSettings,
+    SettingChanged(usize, String),
+    SaveSettings,
+    SettingsSaved(Result<Config, String>),
     Refresh,
-    UpdateFileUploadProgress(Arc<Mutex<mpsc::Receiver<(usize, usize)>>>),
+    LegalHoldLoaded(i32, Option<LegalHold>),
+    HoldNameChanged(String),
+    HoldReasonChanged(String),
+    PlaceLegalHold,
+    ReleaseLegalHold,
+    LegalHoldUpdated(Result<Option<LegalHold>, String>),
+    RestoreSelectedFile,
+    RestoreDestinationChosen(Option<PathBuf>),
+    Restored(Result<PathBuf, String>),
+    ScrubScheduleLoaded(i32, Option<ScrubSchedule>),
+    VerificationsLoaded(i32, Vec<Verification>),
+    VerifySelectedFile,
+    JobStarted(Result<i32, String>),
+    JobProgress(Vec<(i32, JobPoll)>),
+    JobsLoaded(i32, Vec<JobSummary>),
+    CancelJob(i32),
+    TaskFailed(String),
+    Notify(Vec<Notification>),
+    DismissNotification(usize),
+    RetryNotification(usize),
+    ToggleErrorLog,
+}
+
+// A job's summary and, while it runs, its byte-level progress
+type JobPoll = Result<(JobSummary, Option<Progress>), String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    Info,
+    Error,
+    // A file no longer matches its digest, or could not be read to check
+    Alert,
+}
+
+// What a notification's retry button does
+#[derive(Debug, Clone)]
+pub enum Retry {
+    Ingest { profile_id: i32, path: String },
+    Verify { profile_id: i32, file_id: i32 },
+}
+
+// A message for the notification area. Errors and alerts also stay in the
+// error log for the rest of the session.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    id: usize,
+    kind: NotificationKind,
+    text: String,
+    retry: Option<Retry>,
+    dismissed: bool,
+}
+
+impl Notification {
+    fn new(kind: NotificationKind, text: String) -> Self {
+        Notification {
+            id: 0,
+            kind,
+            text,
+            retry: None,
+            dismissed: false,
+        }
+    }
+
+    fn info(text: String) -> Self {
+        Self::new(NotificationKind::Info, text)
+    }
+
+    fn error(text: String) -> Self {
+        Self::new(NotificationKind::Error, text)
+    }
+
+    fn alert(text: String) -> Self {
+        Self::new(NotificationKind::Alert, text)
+    }
+
+    fn with_retry(mut self, retry: Option<Retry>) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+// Files and folders chosen or dropped for ingestion, waiting for the user to
+// confirm. The preview is `None` while they are being scanned.
+pub struct PendingIngest {
+    paths: Vec<PathBuf>,
+    preview: Option<IngestPreview>,
+}
+
+// The profile at one capture point, or what changed between two
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+    AsOf(NaiveDateTime, Vec<File>),
+    Diff(NaiveDateTime, NaiveDateTime, SnapshotDiff),
 }
 
 // Define the possible loading states for the UI
@@ -49,30 +206,441 @@ pub struct Archiver {
     input_value: String,
     profiles: Vec<Profile>,
     selected_profile: Option<Profile>,
-    connection: Arc<Mutex<SqliteConnection>>,
-    scrollable_state_left: iced::widget::scrollable::State,
-    scrollable_state_right: iced::widget::scrollable::State,
-    files: Vec<File>,
+    connection: DbPool,
+    tree: FileTree,
+    expanded: HashSet<String>,
+    // Files listed per folder where more than FILES_SHOWN were asked for
+    shown_files: HashMap<String, usize>,
+    // The row keyboard navigation moves, and where shift-selection starts
+    cursor: Option<TreeKey>,
+    anchor: Option<TreeKey>,
+    selection: HashSet<i32>,
+    modifiers: Modifiers,
+    // The same files as the tree, as a sortable table
+    table: FileTable,
+    // The properties panel shows this when exactly one file is selected
     selected_file: Option<File>,
     loading_state: LoadingState,
-    file_upload_progress: FileUploadProgress,
-    pool: ThreadPool,
+    upload_progress: Option<Progress>,
+    pending_ingest: Option<PendingIngest>,
+    timeline: Arc<Timeline>,
+    // The capture points picked on the timeline, at most two, and what they
+    // show once worked out
+    selected_captures: Vec<NaiveDateTime>,
+    snapshot: Option<Snapshot>,
+    // The settings screen: one input per setting in KEYS order, and for each
+    // where an environment variable or flag overrides it
+    show_settings: bool,
+    setting_inputs: Vec<String>,
+    setting_overrides: Vec<Option<Source>>,
+    theme: UiTheme,
+    // Files are being dragged over the window
+    files_hovered: bool,
+    // Runs ingest and other jobs, which can take hours
+    pool: Arc<ThreadPool>,
+    // Runs queries and other short work for the window, so a long job never
+    // holds it up
+    tasks: ThreadPool,
+    legal_hold: Option<LegalHold>,
+    hold_name_input: String,
+    hold_reason_input: String,
+    scrub_schedule: Option<ScrubSchedule>,
+    verifications: Vec<Verification>,
+    jobs: Vec<JobSummary>,
+    // Jobs started from this window; the first one's progress is shown under
+    // the progress bar
+    watched_jobs: Vec<i32>,
+    polling_jobs: bool,
+    notifications: Vec<Notification>,
+    next_notification_id: usize,
+    show_error_log: bool,
 }
 
-// Define the file upload progress struct
-#[derive(Clone)]
-struct FileUploadProgress {
-    current: usize,
-    total: usize,
-}
+impl Archiver {
+    // Run `task` on the task pool and turn its result into a message, so the
+    // window keeps drawing while it waits
+    fn background<T>(
+        &self,
+        task: impl FnOnce(DbPool) -> T + Send + 'static,
+        message: impl FnOnce(T) -> Message + Send + 'static,
+    ) -> Command<Message>
+    where
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let handle = self.tasks.spawn(move || task(connection));
+        Command::perform(handle, move |result| match result {
+            Ok(value) => message(value),
+            Err(e) => Message::TaskFailed(e.to_string()),
+        })
+    }
+
+    // Add notifications to the notification area and the session log
+    fn notify(&mut self, notifications: Vec<Notification>) {
+        for mut notification in notifications {
+            debug_log!("{}", notification.text);
+            notification.id = self.next_notification_id;
+            self.next_notification_id += 1;
+            self.notifications.push(notification);
+        }
+    }
+
+    // Report the failed items of `jobs` once they are done with
+    fn report_failures(&self, jobs: Vec<Job>) -> Command<Message> {
+        if jobs.is_empty() {
+            return Command::none();
+        }
+        self.background(
+            move |connection| {
+                jobs.iter()
+                    .flat_map(|job| job_failures(connection.clone(), job))
+                    .collect()
+            },
+            Message::Notify,
+        )
+    }
+
+    // Verify files in a job, followed like an ingest
+    fn start_verify(&mut self, profile_id: i32, file_ids: Vec<i32>) -> Command<Message> {
+        if file_ids.is_empty() {
+            return Command::none();
+        }
+        self.loading_state = LoadingState::Loading(String::from("Starting verification"));
+        let pool = Arc::clone(&self.pool);
+        self.background(
+            move |connection| start_verify_job(connection, &pool, profile_id, &file_ids),
+            Message::JobStarted,
+        )
+    }
+
+    // How many files of a folder the tree lists
+    fn file_limit(&self) -> impl Fn(&Folder) -> usize + '_ {
+        |folder| {
+            self.shown_files
+                .get(&folder.path)
+                .copied()
+                .unwrap_or(FILES_SHOWN)
+        }
+    }
+
+    // Replace the selection, showing the properties of a lone selected file
+    fn select(&mut self, selection: HashSet<i32>) -> Command<Message> {
+        self.selection = selection;
+        let lone = match self.selection.len() {
+            1 => self.selection.iter().next().copied(),
+            _ => None,
+        };
+        match lone.and_then(|file_id| self.tree.entry(file_id)) {
+            Some(entry) => {
+                let file_id = entry.file.id;
+                if self.selected_file.as_ref().map(|file| file.id) == Some(file_id) {
+                    return Command::none();
+                }
+                self.selected_file = Some(entry.file.clone());
+                self.verifications.clear();
+                self.background(
+                    move |connection| get_verification_history(connection, &file_id),
+                    move |verifications| Message::VerificationsLoaded(file_id, verifications),
+                )
+            }
+            None => {
+                self.selected_file = None;
+                self.verifications.clear();
+                Command::none()
+            }
+        }
+    }
+
+    // Add or remove every file under a row from the selection
+    fn toggle_selected(&mut self, key: &TreeKey) -> Command<Message> {
+        let file_ids = match key {
+            TreeKey::File(file_id) => vec![*file_id],
+            TreeKey::Folder(path) => match self.tree.folder(path) {
+                Some(folder) => self.tree.file_ids_under(folder),
+                None => Vec::new(),
+            },
+        };
+        let mut selection = self.selection.clone();
+        if file_ids.iter().all(|file_id| selection.contains(file_id)) {
+            for file_id in &file_ids {
+                selection.remove(file_id);
+            }
+        } else {
+            selection.extend(file_ids);
+        }
+        self.select(selection)
+    }
+
+    // Select the files between the anchor and `key`
+    fn select_range(&mut self, key: &TreeKey) -> Command<Message> {
+        let keys: Vec<TreeKey> = self
+            .tree
+            .rows(&self.expanded, self.file_limit())
+            .iter()
+            .map(|row| row.key())
+            .collect();
+        let end = keys.iter().position(|row| row == key);
+        let start = self
+            .anchor
+            .as_ref()
+            .and_then(|anchor| keys.iter().position(|row| row == anchor))
+            .or(end);
+        let (Some(start), Some(end)) = (start, end) else {
+            return Command::none();
+        };
+        let selection = keys[start.min(end)..=start.max(end)]
+            .iter()
+            .filter_map(|row| match row {
+                TreeKey::File(file_id) => Some(*file_id),
+                TreeKey::Folder(_) => None,
+            })
+            .collect();
+        self.select(selection)
+    }
+
+    // Whether results loaded for `profile_id` still belong on screen
+    fn is_selected(&self, profile_id: i32) -> bool {
+        self.selected_profile
+            .as_ref()
+            .is_some_and(|profile| profile.id == profile_id)
+    }
+
+    fn load_tree(&self, profile_id: i32) -> Command<Message> {
+        self.background(
+            move |connection| load_file_tree(connection, &profile_id),
+            move |tree| match tree {
+                Ok(tree) => Message::TreeLoaded(profile_id, tree),
+                Err(e) => Message::Notify(vec![Notification::error(format!(
+                    "Failed to load files: {}",
+                    e
+                ))]),
+            },
+        )
+    }
+
+    fn load_timeline(&self, profile_id: i32) -> Command<Message> {
+        self.background(
+            move |connection| load_timeline(connection, &profile_id),
+            move |timeline| match timeline {
+                Ok(timeline) => Message::TimelineLoaded(profile_id, Arc::new(timeline)),
+                Err(e) => Message::Notify(vec![Notification::error(format!(
+                    "Failed to load the timeline: {}",
+                    e
+                ))]),
+            },
+        )
+    }
+
+    // Work out the profile as of the picked capture point, or the changes
+    // between the two picked
+    fn show_snapshot(&mut self) -> Command<Message> {
+        let mut picked = self.selected_captures.clone();
+        picked.sort();
+        let timeline = Arc::clone(&self.timeline);
+        match picked[..] {
+            [at] => {
+                self.loading_state =
+                    LoadingState::Loading(format!("Loading the profile as of {}", at));
+                self.background(
+                    move |_| {
+                        let files = timeline.as_of(at).into_iter().cloned().collect();
+                        Snapshot::AsOf(at, files)
+                    },
+                    Message::SnapshotLoaded,
+                )
+            }
+            [from, to] => {
+                self.loading_state =
+                    LoadingState::Loading(format!("Comparing {} with {}", from, to));
+                self.background(
+                    move |_| Snapshot::Diff(from, to, timeline.diff(from, to)),
+                    Message::SnapshotLoaded,
+                )
+            }
+            _ => {
+                self.snapshot = None;
+                Command::none()
+            }
+        }
+    }
+
+    // Scan chosen or dropped files and folders for what ingesting them into
+    // the selected profile would add
+    fn preview_ingest(&mut self, paths: Vec<PathBuf>) -> Command<Message> {
+        let Some(profile) = &self.selected_profile else {
+            self.notify(vec![Notification::info(String::from(
+                "Select a profile before adding files",
+            ))]);
+            return Command::none();
+        };
+        let profile_id = profile.id;
+        self.pending_ingest = Some(PendingIngest {
+            paths: paths.clone(),
+            preview: None,
+        });
+        self.background(
+            move |connection| {
+                let preview =
+                    preview_ingest(connection, &profile_id, &paths).map_err(|e| e.to_string());
+                (paths, preview)
+            },
+            |(paths, preview)| Message::IngestPreviewed(paths, preview),
+        )
+    }
+
+    // Select the files between the anchor and `file_id` as the table lists
+    // them
+    fn select_table_range(&mut self, file_id: i32) -> Command<Message> {
+        let file_ids = self.table.file_ids(&self.tree);
+        let Some(end) = file_ids.iter().position(|id| *id == file_id) else {
+            return Command::none();
+        };
+        let start = match &self.anchor {
+            Some(TreeKey::File(anchor)) => file_ids.iter().position(|id| id == anchor),
+            _ => None,
+        }
+        .unwrap_or(end);
+        let selection = file_ids[start.min(end)..=start.max(end)]
+            .iter()
+            .copied()
+            .collect();
+        self.select(selection)
+    }
+
+    fn toggle_expanded(&mut self, path: &str) {
+        if !self.expanded.remove(path) {
+            self.expanded.insert(path.to_string());
+        }
+    }
+
+    // Move around the tree with the arrow keys, Home, End and the page keys.
+    // Left and Right close and open folders, Enter opens a folder or selects
+    // a file, Space adds to or takes from the selection, and shift or the
+    // command key extend the selection while moving.
+    fn tree_key_pressed(&mut self, key_code: KeyCode, modifiers: Modifiers) -> Command<Message> {
+        let rows: Vec<(TreeKey, usize)> = self
+            .tree
+            .rows(&self.expanded, self.file_limit())
+            .iter()
+            .map(|row| (row.key(), row.depth))
+            .collect();
+        if rows.is_empty() {
+            return Command::none();
+        }
+        let last = rows.len() - 1;
+        let current = self
+            .cursor
+            .as_ref()
+            .and_then(|cursor| rows.iter().position(|(key, _)| key == cursor));
+        let on_folder = match &self.cursor {
+            Some(TreeKey::Folder(path)) if current.is_some() => Some(path.clone()),
+            _ => None,
+        };
+
+        let target = match key_code {
+            KeyCode::Up => current.map_or(0, |index| index.saturating_sub(1)),
+            KeyCode::Down => current.map_or(0, |index| (index + 1).min(last)),
+            KeyCode::PageUp => current.map_or(0, |index| index.saturating_sub(PAGE_ROWS)),
+            KeyCode::PageDown => current.map_or(0, |index| (index + PAGE_ROWS).min(last)),
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            KeyCode::Left => match (on_folder, current) {
+                (Some(path), _) if self.expanded.contains(&path) => {
+                    self.expanded.remove(&path);
+                    return Command::none();
+                }
+                // Up to the enclosing folder
+                (_, Some(index)) => {
+                    let depth = rows[index].1;
+                    match rows[..index]
+                        .iter()
+                        .rposition(|(_, row_depth)| *row_depth < depth)
+                    {
+                        Some(parent) => parent,
+                        None => return Command::none(),
+                    }
+                }
+                _ => return Command::none(),
+            },
+            KeyCode::Right => match (on_folder, current) {
+                (Some(path), _) if !self.expanded.contains(&path) => {
+                    self.expanded.insert(path);
+                    return Command::none();
+                }
+                (Some(_), Some(index)) => (index + 1).min(last),
+                _ => return Command::none(),
+            },
+            KeyCode::Enter => {
+                return match (&self.cursor, on_folder) {
+                    (_, Some(path)) => {
+                        self.toggle_expanded(&path);
+                        Command::none()
+                    }
+                    (Some(TreeKey::File(file_id)), None) => {
+                        self.anchor = self.cursor.clone();
+                        self.select(HashSet::from([*file_id]))
+                    }
+                    _ => Command::none(),
+                };
+            }
+            KeyCode::Space => {
+                return match self.cursor.clone() {
+                    Some(key) if current.is_some() => {
+                        self.anchor = Some(key.clone());
+                        self.toggle_selected(&key)
+                    }
+                    _ => Command::none(),
+                };
+            }
+            KeyCode::A if modifiers.command() => {
+                let all = self
+                    .tree
+                    .entries
+                    .iter()
+                    .map(|entry| entry.file.id)
+                    .collect();
+                return self.select(all);
+            }
+            _ => return Command::none(),
+        };
 
-impl FileUploadProgress {
-    // Calculate the current progress ratio
-    fn ratio(&self) -> f32 {
-        if self.total == 0 {
+        let key = rows[target].0.clone();
+        self.cursor = Some(key.clone());
+        let command = if modifiers.shift() {
+            self.select_range(&key)
+        } else if modifiers.command() {
+            Command::none()
+        } else {
+            self.anchor = Some(key.clone());
+            match key {
+                TreeKey::File(file_id) => self.select(HashSet::from([file_id])),
+                TreeKey::Folder(_) => Command::none(),
+            }
+        };
+
+        // Keep the cursor in view
+        let offset = if last == 0 {
             0.0
         } else {
-            self.current as f32 / self.total as f32
+            target as f32 / last as f32
+        };
+        Command::batch(vec![
+            command,
+            scrollable::snap_to(
+                file_tree_scroll_id(),
+                scrollable::RelativeOffset { x: 0.0, y: offset },
+            ),
+        ])
+    }
+}
+
+// Load the most recent jobs of a profile
+fn load_jobs(connection: DbPool, profile_id: &i32) -> Vec<JobSummary> {
+    match list_jobs(connection, Some(profile_id)) {
+        Ok(jobs) => jobs.into_iter().take(5).collect(),
+        Err(e) => {
+            debug_log!("Failed to load jobs: {}", e);
+            Vec::new()
         }
     }
 }
@@ -81,51 +649,88 @@ impl Application for Archiver {
     // Define the application type, message type, flags type, and theme type
     type Executor = iced::executor::Default;
     type Message = Message;
-    type Flags = Arc<Mutex<SqliteConnection>>;
+    type Flags = DbPool;
     type Theme = iced::theme::Theme;
 
-    // Initialize the UI with the given flags (database connection)
+    // Initialize the UI with the given flags (database connections)
     fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
+        let settings = config::settings();
+        let pool = Arc::new(ThreadPool::new(settings.workers));
+        let tasks = ThreadPool::new(2);
+        let connection = flags.clone();
+        let resume_pool = Arc::clone(&pool);
+        tasks.execute(move || resume_interrupted_jobs(&connection, &resume_pool));
         (
             Self {
                 input_value: String::new(),
                 profiles: Vec::new(),
                 selected_profile: None,
                 connection: flags,
-                scrollable_state_left: iced::widget::scrollable::State::new(),
-                scrollable_state_right: iced::widget::scrollable::State::new(),
-                files: Vec::new(),
+                tree: FileTree::default(),
+                table: FileTable::new(),
+                expanded: HashSet::new(),
+                shown_files: HashMap::new(),
+                cursor: None,
+                anchor: None,
+                selection: HashSet::new(),
+                modifiers: Modifiers::default(),
                 selected_file: None,
                 loading_state: LoadingState::Idle,
-                file_upload_progress: FileUploadProgress {
-                    current: 0,
-                    total: 0,
-                },
-                pool: ThreadPool::new(4),
+                upload_progress: None,
+                pending_ingest: None,
+                timeline: Arc::new(Timeline::default()),
+                selected_captures: Vec::new(),
+                snapshot: None,
+                show_settings: false,
+                setting_inputs: Vec::new(),
+                setting_overrides: Vec::new(),
+                theme: settings.theme,
+                files_hovered: false,
+                pool,
+                tasks,
+                legal_hold: None,
+                hold_name_input: std::env::var("USER").unwrap_or_default(),
+                hold_reason_input: String::new(),
+                scrub_schedule: None,
+                verifications: Vec::new(),
+                jobs: Vec::new(),
+                watched_jobs: Vec::new(),
+                polling_jobs: false,
+                notifications: Vec::new(),
+                next_notification_id: 0,
+                show_error_log: false,
             },
             // Load the profiles asynchronously and send a message when done
             Command::perform(async { Message::LoadProfiles }, |_| Message::LoadProfiles),
         )
     }
 
+    fn theme(&self) -> Self::Theme {
+        match self.theme {
+            UiTheme::Light => iced::Theme::Light,
+            UiTheme::Dark => iced::Theme::Dark,
+        }
+    }
+
     // Set the title of the UI window
     fn title(&self) -> String {
         String::from("Archiver")
     }
 
-    // Handle incoming messages and return a command to execute
+    // Handle incoming messages and return a command to execute. Anything that
+    // touches the database or reads files runs on the task pool.
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
-        println!("Received message: {:?}", message);
+        debug_log!("Received message: {:?}", message);
         match message {
             Message::LoadProfiles => {
                 // Load the profiles from the database and send a message when done
-                let connection = Arc::clone(&self.connection);
-                let profiles = get_profiles(connection);
-                Command::perform(async { profiles }, Message::ProfilesLoaded)
+                self.loading_state = LoadingState::Loading(String::from("Loading profiles"));
+                self.background(get_profiles, Message::ProfilesLoaded)
             }
             Message::ProfilesLoaded(profiles) => {
                 // Update the UI with the loaded profiles
                 self.profiles = profiles;
+                self.loading_state = LoadingState::Loaded;
                 Command::none()
             }
             Message::ProfileInputChanged(value) => {
@@ -134,130 +739,735 @@ impl Application for Archiver {
                 Command::none()
             }
             Message::CreateProfile => {
-                // Create a new profile with the given name
-                let connection = Arc::clone(&self.connection);
-                let _ = rs_timeskip_archiver::create_profile(connection, &self.input_value);
+                // Create a new profile with the given name, then reload the list
+                let profile_name = self.input_value.trim().to_string();
+                if profile_name.is_empty() {
+                    self.notify(vec![Notification::info(String::from(
+                        "A profile needs a name",
+                    ))]);
+                    return Command::none();
+                }
                 self.input_value.clear();
-                Command::perform(async { Message::ProfileRefresh }, |msg| msg)
+                self.background(
+                    move |connection| {
+                        rs_timeskip_archiver::try_create_profile(
+                            connection.clone(),
+                            &profile_name,
+                        )?;
+                        rs_timeskip_archiver::try_get_profiles(connection)
+                    },
+                    |profiles| match profiles {
+                        Ok(profiles) => Message::ProfilesLoaded(profiles),
+                        Err(e) => Message::Notify(vec![Notification::error(format!(
+                            "Failed to create profile: {}",
+                            e
+                        ))]),
+                    },
+                )
             }
             Message::ProfileSelected(profile) => {
                 // Select a profile and load its files
                 self.selected_profile = Some(profile);
+                self.tree = FileTree::default();
+                self.expanded.clear();
+                self.shown_files.clear();
+                self.cursor = None;
+                self.anchor = None;
+                self.selection.clear();
+                self.selected_file = None;
+                self.verifications.clear();
+                self.timeline = Arc::new(Timeline::default());
+                self.selected_captures.clear();
+                self.snapshot = None;
+                self.legal_hold = None;
+                self.scrub_schedule = None;
+                self.jobs.clear();
                 Command::perform(async { Message::LoadFiles }, |msg| msg)
             }
             Message::LoadFiles => {
-                // Load the files for the selected profile
+                // Load the files and legal hold status for the selected profile
                 if let Some(profile) = &self.selected_profile {
-                    let connection = Arc::clone(&self.connection);
-                    let files = get_files(connection, &profile.id);
-                    Command::perform(async { files }, Message::FilesLoaded)
+                    let profile_id = profile.id;
+                    self.loading_state =
+                        LoadingState::Loading(format!("Loading files of {}", profile.profile_name));
+                    Command::batch(vec![
+                        self.load_tree(profile_id),
+                        self.load_timeline(profile_id),
+                        self.background(
+                            move |connection| try_get_legal_hold(connection, &profile_id),
+                            move |hold| match hold {
+                                Ok(hold) => Message::LegalHoldLoaded(profile_id, hold),
+                                Err(e) => Message::Notify(vec![Notification::error(format!(
+                                    "Failed to load the legal hold: {}",
+                                    e
+                                ))]),
+                            },
+                        ),
+                        self.background(
+                            move |connection| try_get_scrub_schedule(connection, &profile_id),
+                            move |schedule| match schedule {
+                                Ok(schedule) => Message::ScrubScheduleLoaded(profile_id, schedule),
+                                Err(e) => Message::Notify(vec![Notification::error(format!(
+                                    "Failed to load the scrub schedule: {}",
+                                    e
+                                ))]),
+                            },
+                        ),
+                        self.background(
+                            move |connection| load_jobs(connection, &profile_id),
+                            move |jobs| Message::JobsLoaded(profile_id, jobs),
+                        ),
+                    ])
+                } else {
+                    Command::none()
+                }
+            }
+            Message::LegalHoldLoaded(profile_id, hold) => {
+                if self.is_selected(profile_id) {
+                    self.legal_hold = hold;
+                }
+                Command::none()
+            }
+            Message::ScrubScheduleLoaded(profile_id, schedule) => {
+                if self.is_selected(profile_id) {
+                    self.scrub_schedule = schedule;
+                }
+                Command::none()
+            }
+            Message::VerificationsLoaded(file_id, verifications) => {
+                // The history of a file that is no longer selected is dropped
+                if self.selected_file.as_ref().map(|file| file.id) == Some(file_id) {
+                    self.verifications = verifications;
+                }
+                Command::none()
+            }
+            Message::HoldNameChanged(value) => {
+                self.hold_name_input = value;
+                Command::none()
+            }
+            Message::HoldReasonChanged(value) => {
+                self.hold_reason_input = value;
+                Command::none()
+            }
+            Message::PlaceLegalHold | Message::ReleaseLegalHold => {
+                // Place or release the legal hold on the selected profile
+                if let Some(profile) = &self.selected_profile {
+                    let profile_id = profile.id;
+                    let place = matches!(message, Message::PlaceLegalHold);
+                    let held_by = self.hold_name_input.clone();
+                    let reason = self.hold_reason_input.clone();
+                    self.background(
+                        move |connection| {
+                            let result = if place {
+                                rs_timeskip_archiver::place_legal_hold(
+                                    connection.clone(),
+                                    &profile_id,
+                                    &held_by,
+                                    &reason,
+                                )
+                            } else {
+                                rs_timeskip_archiver::release_legal_hold(
+                                    connection.clone(),
+                                    &profile_id,
+                                    &held_by,
+                                    &reason,
+                                )
+                            };
+                            result
+                                .and_then(|_| try_get_legal_hold(connection, &profile_id))
+                                .map_err(|e| e.to_string())
+                        },
+                        Message::LegalHoldUpdated,
+                    )
+                } else {
+                    Command::none()
+                }
+            }
+            Message::LegalHoldUpdated(result) => {
+                match result {
+                    Ok(hold) => {
+                        self.legal_hold = hold;
+                        self.hold_reason_input.clear();
+                    }
+                    Err(e) => self.notify(vec![Notification::error(format!(
+                        "Failed to update legal hold: {}",
+                        e
+                    ))]),
+                }
+                Command::none()
+            }
+            Message::ProgressTick => {
+                // Check on the watched jobs, unless the last check is still running
+                if self.watched_jobs.is_empty() || self.polling_jobs {
+                    return Command::none();
+                }
+                self.polling_jobs = true;
+                let job_ids = self.watched_jobs.clone();
+                self.background(
+                    move |connection| {
+                        job_ids
+                            .into_iter()
+                            .map(|job_id| (job_id, poll_job(connection.clone(), job_id)))
+                            .collect()
+                    },
+                    Message::JobProgress,
+                )
+            }
+            Message::TreeLoaded(profile_id, tree) => {
+                // Show the reloaded files, keeping what was open and selected,
+                // unless another profile was selected meanwhile
+                if !self.is_selected(profile_id) {
+                    return Command::none();
+                }
+                self.tree = tree;
+                self.table.sort(&self.tree);
+                self.loading_state = LoadingState::Loaded;
+                let selection = self
+                    .selection
+                    .iter()
+                    .copied()
+                    .filter(|file_id| self.tree.entry(*file_id).is_some())
+                    .collect();
+                self.select(selection)
+            }
+            Message::TreeRowClicked(key) => {
+                // Folders open and close; files are selected, or added to the
+                // selection with the command key or shift
+                self.cursor = Some(key.clone());
+                match &key {
+                    TreeKey::Folder(_) if self.modifiers.command() => self.toggle_selected(&key),
+                    TreeKey::Folder(path) => {
+                        self.toggle_expanded(&path.clone());
+                        Command::none()
+                    }
+                    TreeKey::File(_) if self.modifiers.shift() => self.select_range(&key),
+                    TreeKey::File(_) if self.modifiers.command() => {
+                        self.anchor = Some(key.clone());
+                        self.toggle_selected(&key)
+                    }
+                    TreeKey::File(file_id) => {
+                        self.anchor = Some(key.clone());
+                        self.select(HashSet::from([*file_id]))
+                    }
+                }
+            }
+            Message::TreeKeyPressed(key_code, modifiers) => {
+                if self.selected_profile.is_some() {
+                    self.tree_key_pressed(key_code, modifiers)
                 } else {
                     Command::none()
                 }
             }
-            Message::ProgressTick(_) => Command::none(),
-            Message::FilesLoaded(files) => {
-                // Update the UI with the loaded files
-                self.files = files;
+            Message::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers;
+                Command::none()
+            }
+            Message::ShowMoreFiles(path) => {
+                let shown = self.shown_files.entry(path).or_insert(FILES_SHOWN);
+                *shown += FILES_SHOWN;
+                Command::none()
+            }
+            Message::SortTable(column) => {
+                self.table.sort_by(column, &self.tree);
+                Command::none()
+            }
+            Message::TableScrolled(offset) => {
+                self.table.scrolled(offset);
+                Command::none()
+            }
+            Message::TableRowClicked(file_id) => {
+                // Rows select like files in the tree, with shift taking the
+                // range in table order
+                let key = TreeKey::File(file_id);
+                self.cursor = Some(key.clone());
+                if self.modifiers.shift() {
+                    self.select_table_range(file_id)
+                } else if self.modifiers.command() {
+                    self.anchor = Some(key.clone());
+                    self.toggle_selected(&key)
+                } else {
+                    self.anchor = Some(key);
+                    self.select(HashSet::from([file_id]))
+                }
+            }
+            Message::CopyDigest(digest) => {
+                self.notify(vec![Notification::info(format!(
+                    "Copied digest {} to the clipboard",
+                    digest
+                ))]);
+                iced::clipboard::write(digest)
+            }
+            Message::ColumnResizeStarted(column) => {
+                self.table.start_resize(column);
                 Command::none()
             }
-            Message::FileSelected(file) => {
-                // Select a file
-                self.selected_file = Some(file);
+            Message::ColumnResized(x) => {
+                self.table.cursor_moved(x);
+                Command::none()
+            }
+            Message::ColumnResizeEnded => {
+                self.table.end_resize();
+                Command::none()
+            }
+            Message::VerifySelectedFile => {
+                // Re-hash the selected file and show the updated history
+                if let Some(file) = &self.selected_file {
+                    self.start_verify(file.profile_id, vec![file.id])
+                } else {
+                    Command::none()
+                }
+            }
+            Message::VerifySelection => {
+                let Some(profile_id) = self.selected_profile.as_ref().map(|profile| profile.id)
+                else {
+                    return Command::none();
+                };
+                let file_ids = self
+                    .tree
+                    .entries
+                    .iter()
+                    .filter(|entry| self.selection.contains(&entry.file.id))
+                    .map(|entry| entry.file.id)
+                    .collect();
+                self.start_verify(profile_id, file_ids)
+            }
+            Message::RestoreSelectedFile => {
+                // Ask where to restore the selected file to
+                if let Some(file) = &self.selected_file {
+                    let file_name = PathBuf::from(&file.file_name)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    Command::perform(
+                        save_file_dialog(file_name),
+                        Message::RestoreDestinationChosen,
+                    )
+                } else {
+                    Command::none()
+                }
+            }
+            Message::RestoreDestinationChosen(dest) => {
+                // Reassemble the selected file at the chosen destination
+                if let (Some(file), Some(dest)) = (&self.selected_file, dest) {
+                    let file_id = file.id;
+                    let file_name = file.file_name.clone();
+                    self.loading_state = LoadingState::Loading(format!("Restoring {}", file_name));
+                    self.background(
+                        move |connection| {
+                            rs_timeskip_archiver::restore_file(connection, &file_id, &dest)
+                                .map(|()| dest)
+                                .map_err(|e| format!("Failed to restore {}: {}", file_name, e))
+                        },
+                        Message::Restored,
+                    )
+                } else {
+                    Command::none()
+                }
+            }
+            Message::Restored(result) => {
+                self.notify(vec![match result {
+                    Ok(dest) => Notification::info(format!("Restored to {}", dest.display())),
+                    Err(e) => Notification::error(e),
+                }]);
+                self.loading_state = LoadingState::Loaded;
                 Command::none()
             }
             Message::OpenFileDialog => {
                 // Open a file dialog to choose files to upload
-                println!("Open file dialog called.");
                 Command::perform(open_file_dialog(), Message::FileChosen)
             }
-            Message::FileChosen(file_paths_result) => {
-                // Upload the chosen files for the selected profile
-                if let Some(profile) = &self.selected_profile {
-                    if let Ok(file_paths) = file_paths_result.clone() {
-                        // Clone necessary data for thread.
-                        let profile_id = profile.id.clone();
-                        let file_paths_for_length = file_paths.clone();
-                        let file_paths_for_thread = file_paths.clone();
-
-                        // Create a channel for communication.
-                        let (tx, mut rx) = mpsc::channel::<(usize, usize)>(1);
-
-                        // Start a single worker thread to handle all file uploads.
-                        for (index, file_path) in file_paths_for_thread.iter().enumerate() {
-                            let len = file_paths_for_thread.len();
-                            let mut tx_clone = tx.clone(); // clone the sender and declare it as mutable
-                            let connection = Arc::clone(&self.connection);
-                            let file_path_str = file_path.to_str().unwrap_or("").to_string();
-
-                            self.pool.execute(move || {
-                                let _ = rs_timeskip_archiver::add_file(
-                                    connection,
-                                    file_path_str,
-                                    &profile_id,
-                                    &mut tx_clone,
-                                    index,
-                                    len,
-                                );
-                            })
+            Message::OpenFolderDialog => {
+                Command::perform(open_folder_dialog(), Message::FileChosen)
+            }
+            Message::FileChosen(file_paths_result) => match file_paths_result {
+                // Show what the chosen files and folders would add first
+                Ok(file_paths) => self.preview_ingest(file_paths),
+                Err(e) => {
+                    self.notify(vec![Notification::info(e)]);
+                    Command::none()
+                }
+            },
+            Message::FilesHovered(hovered) => {
+                self.files_hovered = hovered;
+                Command::none()
+            }
+            Message::FileDropped(path) => {
+                // Each dropped item arrives on its own, so add it to any
+                // drop still waiting to be confirmed
+                self.files_hovered = false;
+                let mut paths = match self.pending_ingest.take() {
+                    Some(pending) => pending.paths,
+                    None => Vec::new(),
+                };
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+                self.preview_ingest(paths)
+            }
+            Message::IngestPreviewed(paths, result) => {
+                // Ignore a scan that a later drop has replaced
+                match &mut self.pending_ingest {
+                    Some(pending) if pending.paths == paths => match result {
+                        Ok(preview) => pending.preview = Some(preview),
+                        Err(e) => {
+                            self.pending_ingest = None;
+                            self.notify(vec![Notification::error(format!(
+                                "Could not add files: {}",
+                                e
+                            ))]);
                         }
-
-                        self.file_upload_progress.total = file_paths_for_length.len();
-
-                        let rx_clone = Arc::new(Mutex::new(rx));
-
-                        Command::perform(async { rx_clone }, Message::UpdateFileUploadProgress)
-                    } else {
-                        // Handle file dialog error here...
-                        Command::none()
+                    },
+                    _ => {}
+                }
+                Command::none()
+            }
+            Message::ConfirmIngest => {
+                let preview = self
+                    .pending_ingest
+                    .take()
+                    .and_then(|pending| pending.preview);
+                match (preview, &self.selected_profile) {
+                    (Some(preview), Some(profile)) if !preview.files.is_empty() => {
+                        let profile_id = profile.id;
+                        self.upload_progress = Some(Progress {
+                            files_total: preview.files.len(),
+                            bytes_total: preview.total_bytes,
+                            ..Progress::default()
+                        });
+                        self.loading_state = LoadingState::Loading(String::from("Starting upload"));
+                        let pool = Arc::clone(&self.pool);
+                        self.background(
+                            move |connection| {
+                                start_ingest(connection, &pool, profile_id, &preview.files)
+                            },
+                            Message::JobStarted,
+                        )
                     }
+                    _ => Command::none(),
+                }
+            }
+            Message::CancelIngest => {
+                self.pending_ingest = None;
+                Command::none()
+            }
+            Message::TimelineLoaded(profile_id, timeline) => {
+                // Keep the picked capture points that are still there; the
+                // latest one grows while files keep being added. A timeline of
+                // a profile no longer selected is dropped.
+                if !self.is_selected(profile_id) {
+                    return Command::none();
+                }
+                self.timeline = timeline;
+                let before = self.selected_captures.len();
+                self.selected_captures.retain(|at| {
+                    self.timeline
+                        .captures
+                        .iter()
+                        .any(|capture| capture.at == *at)
+                });
+                if self.selected_captures.len() != before {
+                    self.show_snapshot()
                 } else {
                     Command::none()
                 }
             }
-
-            Message::UpdateFileUploadProgress(rx_clone) => {
-                let mut rx = rx_clone.lock().unwrap();
-
-                match rx.try_next() {
-                    Ok(val) => match val {
-                        Some(msg) => {
-                            println!("Received progress: {} / {}", msg.0, msg.1);
-                            self.file_upload_progress.current += 1;
-                            if self.file_upload_progress.current >= self.file_upload_progress.total
-                            {
-                                self.file_upload_progress.current = 0;
-                                self.file_upload_progress.total = 0;
-
-                                self.loading_state = LoadingState::Loaded;
-                                return Command::perform(async { Message::Refresh }, |msg| msg);
-                            }
-
-                            let rx_clone = Arc::clone(&rx_clone);
-                            return Command::perform(
-                                async { rx_clone },
-                                Message::UpdateFileUploadProgress,
-                            );
+            Message::CaptureClicked(at) => {
+                // A click picks one capture point; with the command key or
+                // shift, a second one to compare with
+                if self.modifiers.command() || self.modifiers.shift() {
+                    if let Some(index) =
+                        self.selected_captures.iter().position(|known| *known == at)
+                    {
+                        self.selected_captures.remove(index);
+                    } else {
+                        self.selected_captures.push(at);
+                        if self.selected_captures.len() > 2 {
+                            self.selected_captures.remove(0);
                         }
-                        None => Command::none(),
+                    }
+                } else if self.selected_captures == [at] {
+                    self.selected_captures.clear();
+                } else {
+                    self.selected_captures = vec![at];
+                }
+                self.show_snapshot()
+            }
+            Message::SnapshotLoaded(snapshot) => {
+                // Ignore a snapshot for capture points no longer picked
+                let current = match &snapshot {
+                    Snapshot::AsOf(at, _) => self.selected_captures == [*at],
+                    Snapshot::Diff(from, to, _) => {
+                        self.selected_captures.len() == 2
+                            && self.selected_captures.contains(from)
+                            && self.selected_captures.contains(to)
+                    }
+                };
+                if current {
+                    self.loading_state = LoadingState::Loaded;
+                    self.snapshot = Some(snapshot);
+                }
+                Command::none()
+            }
+            Message::CloseSnapshot => {
+                self.selected_captures.clear();
+                self.snapshot = None;
+                Command::none()
+            }
+            Message::ToggleSettings => {
+                // Fill the form from the config file, and note the settings
+                // the environment or a flag overrides
+                self.show_settings = !self.show_settings;
+                if self.show_settings {
+                    let file = config::file_config();
+                    self.setting_inputs = KEYS
+                        .iter()
+                        .map(|key| file.get(key).ok().flatten().unwrap_or_default())
+                        .collect();
+                    self.setting_overrides = KEYS
+                        .iter()
+                        .map(|key| match config::lookup(key) {
+                            Ok((_, source @ (Source::Environment | Source::Flag))) => Some(source),
+                            _ => None,
+                        })
+                        .collect();
+                }
+                Command::none()
+            }
+            Message::SettingChanged(index, value) => {
+                self.setting_inputs[index] = value;
+                Command::none()
+            }
+            Message::SaveSettings => {
+                let mut new = Config::default();
+                for (key, value) in KEYS.iter().zip(&self.setting_inputs) {
+                    if let Err(e) = new.set(key, value) {
+                        self.notify(vec![Notification::error(e.to_string())]);
+                        return Command::none();
+                    }
+                }
+                self.background(
+                    move |_| {
+                        config::save_file(&new)
+                            .map(|()| {
+                                reload_daemon_settings();
+                                new
+                            })
+                            .map_err(|e| e.to_string())
                     },
+                    Message::SettingsSaved,
+                )
+            }
+            Message::SettingsSaved(result) => {
+                match result {
+                    Ok(_) => {
+                        // The theme and worker count change now; the rest is
+                        // read when next needed, or at the next start for the
+                        // database
+                        let settings = config::settings();
+                        self.theme = settings.theme;
+                        self.pool.resize(settings.workers);
+                        self.show_settings = false;
+                        self.notify(vec![Notification::info(format!(
+                            "Saved settings to {}. A new database applies after a restart.",
+                            config::config_path().display()
+                        ))]);
+                    }
+                    Err(e) => self.notify(vec![Notification::error(format!(
+                        "Failed to save settings: {}",
+                        e
+                    ))]),
+                }
+                Command::none()
+            }
+            Message::JobStarted(result) => {
+                self.loading_state = LoadingState::Loaded;
+                match result {
+                    Ok(job_id) => {
+                        // List it with the profile's jobs, where it can be cancelled
+                        self.watched_jobs.push(job_id);
+                        if let Some(profile) = &self.selected_profile {
+                            let profile_id = profile.id;
+                            return self.background(
+                                move |connection| load_jobs(connection, &profile_id),
+                                move |jobs| Message::JobsLoaded(profile_id, jobs),
+                            );
+                        }
+                    }
                     Err(e) => {
-                        let rx_clone = Arc::clone(&rx_clone);
-                        Command::perform(async { rx_clone }, Message::UpdateFileUploadProgress)
+                        self.notify(vec![Notification::error(format!(
+                            "Failed to start job: {}",
+                            e
+                        ))]);
+                        if self.watched_jobs.is_empty() {
+                            self.upload_progress = None;
+                        }
                     }
                 }
+                Command::none()
             }
+            Message::JobProgress(results) => {
+                self.polling_jobs = false;
+                let mut finished = Vec::new();
+                for (job_id, progress) in results {
+                    match progress {
+                        Ok((job, progress)) => {
+                            if self.watched_jobs.first() == Some(&job_id) {
+                                // Before the runner has started there is only the item count
+                                self.upload_progress = Some(progress.unwrap_or_else(|| Progress {
+                                    files_done: job.processed(),
+                                    files_total: job.total,
+                                    ..Progress::default()
+                                }));
+                            }
+                            if let Some(known) =
+                                self.jobs.iter_mut().find(|known| known.job.id == job_id)
+                            {
+                                *known = job.clone();
+                            }
+                            if job.is_finished() {
+                                self.watched_jobs.retain(|watched| *watched != job_id);
+                                finished.push(job);
+                            }
+                        }
+                        Err(e) => {
+                            self.watched_jobs.retain(|watched| *watched != job_id);
+                            self.notify(vec![Notification::error(format!(
+                                "Lost track of job {}: {}",
+                                job_id, e
+                            ))]);
+                        }
+                    }
+                }
+                if self.watched_jobs.is_empty() {
+                    self.upload_progress = None;
+                }
+                if finished.is_empty() {
+                    return Command::none();
+                }
 
-            Message::ProfileRefresh => {
-                // Refresh the profiles
-                Command::perform(async { Message::LoadProfiles }, |_| Message::LoadProfiles)
+                // Show what finished jobs changed, and report their failures
+                let mut commands = vec![Command::perform(async { Message::Refresh }, |msg| msg)];
+                let verified = finished
+                    .iter()
+                    .any(|job| JobKind::parse(&job.job.kind) == Some(JobKind::Verify));
+                for job in &finished {
+                    if JobKind::parse(&job.job.kind) == Some(JobKind::Verify) {
+                        self.notify(vec![Notification::info(format!(
+                            "{} of {} files verified",
+                            job.done, job.total
+                        ))]);
+                    }
+                }
+                if let Some(file_id) = self.selected_file.as_ref().map(|file| file.id) {
+                    if verified {
+                        commands.push(self.background(
+                            move |connection| get_verification_history(connection, &file_id),
+                            move |verifications| {
+                                Message::VerificationsLoaded(file_id, verifications)
+                            },
+                        ));
+                    }
+                }
+                let failed = finished
+                    .into_iter()
+                    .filter(|job| job.failed > 0)
+                    .map(|job| job.job)
+                    .collect();
+                commands.push(self.report_failures(failed));
+                Command::batch(commands)
+            }
+            Message::JobsLoaded(profile_id, jobs) => {
+                // Jobs that were running when last loaded, such as scrubs run by
+                // archiverd, may have failed items to report now
+                if !self.is_selected(profile_id) {
+                    return Command::none();
+                }
+                let finished: Vec<Job> = jobs
+                    .iter()
+                    .filter(|job| job.is_finished() && job.failed > 0)
+                    .filter(|job| {
+                        self.jobs
+                            .iter()
+                            .any(|known| known.job.id == job.job.id && !known.is_finished())
+                    })
+                    .map(|job| job.job.clone())
+                    .collect();
+                self.jobs = jobs;
+                self.report_failures(finished)
+            }
+            Message::Notify(notifications) => {
+                self.notify(notifications);
+                Command::none()
+            }
+            Message::DismissNotification(id) => {
+                if let Some(notification) = self.notifications.iter_mut().find(|n| n.id == id) {
+                    notification.dismissed = true;
+                }
+                Command::none()
+            }
+            Message::RetryNotification(id) => {
+                let Some(notification) = self.notifications.iter_mut().find(|n| n.id == id) else {
+                    return Command::none();
+                };
+                notification.dismissed = true;
+                match notification.retry.clone() {
+                    Some(Retry::Ingest { profile_id, path }) => {
+                        let pool = Arc::clone(&self.pool);
+                        self.background(
+                            move |connection| start_ingest(connection, &pool, profile_id, &[path]),
+                            Message::JobStarted,
+                        )
+                    }
+                    Some(Retry::Verify {
+                        profile_id,
+                        file_id,
+                    }) => self.start_verify(profile_id, vec![file_id]),
+                    None => Command::none(),
+                }
+            }
+            Message::ToggleErrorLog => {
+                self.show_error_log = !self.show_error_log;
+                Command::none()
+            }
+            Message::CancelJob(job_id) => {
+                // The runner, here or in archiverd, stops after its current item.
+                // Jobs are only listed for the selected profile.
+                let Some(profile_id) = self.selected_profile.as_ref().map(|profile| profile.id)
+                else {
+                    return Command::none();
+                };
+                self.background(
+                    move |connection| {
+                        cancel_job(connection.clone(), &job_id)
+                            .map_err(|e| format!("Failed to cancel job {}: {}", job_id, e))?;
+                        Ok(load_jobs(connection, &profile_id))
+                    },
+                    move |result| match result {
+                        Ok(jobs) => Message::JobsLoaded(profile_id, jobs),
+                        Err(e) => Message::Notify(vec![Notification::error(e)]),
+                    },
+                )
+            }
+            Message::TaskFailed(e) => {
+                self.notify(vec![Notification::error(format!(
+                    "A background task failed: {}",
+                    e
+                ))]);
+                self.loading_state = LoadingState::Idle;
+                Command::none()
             }
             Message::Refresh => {
                 // Refresh the files for the selected profile
                 if let Some(profile) = &self.selected_profile {
-                    let connection = Arc::clone(&self.connection);
-                    let files = get_files(connection, &profile.id);
-                    Command::perform(async { files }, Message::FilesLoaded)
+                    let profile_id = profile.id;
+                    Command::batch(vec![
+                        self.load_tree(profile_id),
+                        self.load_timeline(profile_id),
+                        self.background(
+                            move |connection| load_jobs(connection, &profile_id),
+                            move |jobs| Message::JobsLoaded(profile_id, jobs),
+                        ),
+                    ])
                 } else {
                     Command::none()
                 }
@@ -265,13 +1475,23 @@ impl Application for Archiver {
         }
     }
 
-    // Subscribe to progress updates if there are any ongoing file uploads
+    // Poll the watched jobs while there are any, listen for keys that move
+    // around the file tree, and follow the mouse while a column is resized
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::none()
+        let mut subscriptions = vec![subscription::events_with(tree_key_event)];
+        if !self.watched_jobs.is_empty() {
+            subscriptions
+                .push(iced::time::every(Duration::from_millis(250)).map(|_| Message::ProgressTick));
+        }
+        subscriptions.push(subscription::events_with(file_drop_event));
+        if self.table.is_resizing() {
+            subscriptions.push(subscription::events_with(column_resize_event));
+        }
+        Subscription::batch(subscriptions)
     }
 
     // Define the UI view
-    fn view(&self) -> Element<Self::Message> {
+    fn view(&self) -> Element<'_, Self::Message> {
         let pick_list = PickList::new(
             &self.profiles,
             self.selected_profile.clone(),
@@ -288,24 +1508,87 @@ impl Application for Archiver {
             .push(Text::new("Select Profile:"))
             .push(pick_list.width(Length::FillPortion(1)))
             .push(profile_text_input)
-            .push(Button::new(Text::new("Create Profile")).on_press(Message::CreateProfile));
+            .push(Button::new(Text::new("Create Profile")).on_press(Message::CreateProfile))
+            .push(
+                Button::new(Text::new("Settings"))
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::ToggleSettings),
+            );
 
         if self.selected_profile.is_some() {
             top_bar = top_bar
-                .push(Button::new(Text::new("Upload File")).on_press(Message::OpenFileDialog));
+                .push(Button::new(Text::new("Upload File")).on_press(Message::OpenFileDialog))
+                .push(Button::new(Text::new("Upload Folder")).on_press(Message::OpenFolderDialog));
         }
 
-        let file_names_panel = self.files.iter().fold(Column::new(), |column, file| {
-            let truncated_file_name = if file.file_name.len() > 20 {
-                file.file_name[0..20].to_string() + "..."
-            } else {
-                file.file_name.clone()
-            };
-            column.push(
-                Button::new(Text::new(truncated_file_name.clone()))
-                    .on_press(Message::FileSelected(file.clone())),
-            )
-        });
+        // The file tree, folders first, with the selection highlighted
+        let file_tree_panel = self
+            .tree
+            .rows(&self.expanded, self.file_limit())
+            .iter()
+            .fold(Column::new(), |column, row| {
+                let key = row.key();
+                let (label, status, message) = match &row.node {
+                    TreeNode::Folder(folder) => (
+                        format!(
+                            "{} {}  ({} files, {}, {})",
+                            if self.expanded.contains(&folder.path) {
+                                "[-]"
+                            } else {
+                                "[+]"
+                            },
+                            folder.name,
+                            folder.file_count,
+                            format_bytes(folder.total_size),
+                            folder.status.as_str()
+                        ),
+                        folder.status,
+                        Message::TreeRowClicked(key.clone()),
+                    ),
+                    TreeNode::File(entry) => (
+                        format!(
+                            "{}  ({}, {})",
+                            entry.name(),
+                            format_bytes(entry.size),
+                            entry.status.as_str()
+                        ),
+                        entry.status,
+                        Message::TreeRowClicked(key.clone()),
+                    ),
+                    TreeNode::More { folder, hidden } => (
+                        format!(
+                            "Show {} more of {} hidden files",
+                            FILES_SHOWN.min(*hidden),
+                            hidden
+                        ),
+                        VerificationStatus::Ok,
+                        Message::ShowMoreFiles(folder.path.clone()),
+                    ),
+                };
+                let selected =
+                    matches!(key, TreeKey::File(file_id) if self.selection.contains(&file_id));
+                let style = if selected {
+                    theme::Button::Primary
+                } else if self.cursor.as_ref() == Some(&key) {
+                    theme::Button::Secondary
+                } else {
+                    theme::Button::Text
+                };
+                let mut text = Text::new(label).size(14);
+                if status == VerificationStatus::Failed && !selected {
+                    text = text.style(Color::from_rgb(0.8, 0.1, 0.1));
+                }
+                column.push(
+                    Row::new()
+                        .push(Space::with_width(Length::Fixed(row.depth as f32 * 16.0)))
+                        .push(
+                            Button::new(text)
+                                .style(style)
+                                .padding([2, 6])
+                                .on_press(message),
+                        ),
+                )
+            });
 
         let file_properties_panel = if let Some(file) = &self.selected_file {
             Column::new()
@@ -313,36 +1596,230 @@ impl Application for Archiver {
                 .push(Text::new(format!("SHA256: {}", &file.sha256)))
                 .push(Text::new(format!("Created At: {}", &file.created_at)))
                 .push(Text::new(format!("Updated At: {}", &file.updated_at)))
+                .push(Button::new(Text::new("Restore")).on_press(Message::RestoreSelectedFile))
+                .push(Button::new(Text::new("Verify")).on_press(Message::VerifySelectedFile))
+                .push(Text::new("Verification history:"))
+                .push(self.verifications.iter().take(10).fold(
+                    Column::new(),
+                    |column, verification| {
+                        column.push(Text::new(format!(
+                            "{}: {}",
+                            verification.verified_at,
+                            verification.detail.as_deref().unwrap_or("ok")
+                        )))
+                    },
+                ))
+        } else if self.selection.len() > 1 {
+            let size: u64 = self
+                .tree
+                .entries
+                .iter()
+                .filter(|entry| self.selection.contains(&entry.file.id))
+                .map(|entry| entry.size)
+                .sum();
+            Column::new()
+                .push(Text::new(format!(
+                    "{} files selected, {}",
+                    self.selection.len(),
+                    format_bytes(size)
+                )))
+                .push(Button::new(Text::new("Verify selected")).on_press(Message::VerifySelection))
         } else {
             Column::new()
         };
 
         let mut content = Column::new().spacing(10).padding(10).push(top_bar);
 
+        if let LoadingState::Loading(what) = &self.loading_state {
+            content = content
+                .push(Container::new(Text::new(format!("{}...", what)).size(14)).padding([0, 15]));
+        }
+
+        // What dropping or the upload buttons would add, for the user to
+        // confirm
+        if let Some(profile) = self
+            .selected_profile
+            .as_ref()
+            .filter(|_| self.files_hovered)
+        {
+            content = content.push(
+                Container::new(Text::new(format!(
+                    "Drop files or folders to add them to {}",
+                    profile.profile_name
+                )))
+                .padding([0, 15]),
+            );
+        }
+        if let Some(pending) = &self.pending_ingest {
+            content = content.push(Container::new(ingest_preview_panel(pending)).padding([0, 15]));
+        }
+
+        // The notification area: recent messages, and the session's errors on
+        // request
+        let logged = self
+            .notifications
+            .iter()
+            .filter(|notification| notification.kind != NotificationKind::Info)
+            .count();
+        let mut notification_panel = self
+            .notifications
+            .iter()
+            .rev()
+            .filter(|notification| !notification.dismissed)
+            .take(5)
+            .fold(Column::new().spacing(5), |column, notification| {
+                column.push(notification_row(notification, true))
+            });
+        if logged > 0 {
+            let label = if self.show_error_log {
+                String::from("Hide error log")
+            } else {
+                format!("Error log ({})", logged)
+            };
+            notification_panel = notification_panel
+                .push(Button::new(Text::new(label).size(14)).on_press(Message::ToggleErrorLog));
+        }
+        if self.show_error_log {
+            notification_panel = self
+                .notifications
+                .iter()
+                .filter(|notification| notification.kind != NotificationKind::Info)
+                .fold(notification_panel, |column, notification| {
+                    column.push(notification_row(notification, false))
+                });
+        }
+        content = content.push(Container::new(notification_panel).padding([0, 15]));
+
         if self.selected_profile.is_some() {
-            content = content.push(Rule::horizontal(10)).push(
+            let hold_status = match &self.legal_hold {
+                Some(hold) => format!(
+                    "Legal hold: set by {} at {} ({})",
+                    hold.held_by, hold.created_at, hold.hold_reason
+                ),
+                None => String::from("Legal hold: none"),
+            };
+            let storage = self
+                .selected_profile
+                .as_ref()
+                .and_then(|profile| profile.storage_url.clone())
+                .unwrap_or_else(|| String::from("default repository"));
+            let scrub_status = match &self.scrub_schedule {
+                Some(schedule) => format!(
+                    "Scrub: {}% every {}h, last run {}",
+                    schedule.percent_per_run,
+                    schedule.interval_hours,
+                    schedule
+                        .last_run_at
+                        .map(|last_run_at| last_run_at.to_string())
+                        .unwrap_or_else(|| String::from("never"))
+                ),
+                None => String::from("Scrub: not scheduled"),
+            };
+            let hold_button = if self.legal_hold.is_some() {
+                Button::new(Text::new("Release Hold")).on_press(Message::ReleaseLegalHold)
+            } else {
+                Button::new(Text::new("Place Hold")).on_press(Message::PlaceLegalHold)
+            };
+
+            content = content.push(
                 Row::new()
-                    .push(Scrollable::new(file_names_panel).width(Length::FillPortion(1)))
-                    .push(Scrollable::new(file_properties_panel).width(Length::FillPortion(1))),
+                    .padding([0, 15])
+                    .spacing(20)
+                    .align_items(Alignment::Center)
+                    .push(Text::new(format!("Storage: {}", storage)).width(Length::FillPortion(1)))
+                    .push(Text::new(scrub_status).width(Length::FillPortion(1)))
+                    .push(Text::new(hold_status).width(Length::FillPortion(2)))
+                    .push(
+                        text_input("Your name", &self.hold_name_input)
+                            .on_input(Message::HoldNameChanged)
+                            .width(Length::FillPortion(1)),
+                    )
+                    .push(
+                        text_input("Reason", &self.hold_reason_input)
+                            .on_input(Message::HoldReasonChanged)
+                            .width(Length::FillPortion(1)),
+                    )
+                    .push(hold_button),
             );
         }
 
+        if !self.jobs.is_empty() {
+            let jobs_panel = self
+                .jobs
+                .iter()
+                .fold(Column::new().spacing(5), |column, job| {
+                    let mut row =
+                        Row::new()
+                            .spacing(20)
+                            .align_items(Alignment::Center)
+                            .push(Text::new(format!(
+                                "Job {} ({}): {}, {} / {} done, {} failed",
+                                job.job.id,
+                                job.job.kind,
+                                job.job.status,
+                                job.processed(),
+                                job.total,
+                                job.failed
+                            )));
+                    if !job.is_finished() {
+                        row = row.push(
+                            Button::new(Text::new("Cancel"))
+                                .on_press(Message::CancelJob(job.job.id)),
+                        );
+                    }
+                    column.push(row)
+                });
+            content = content.push(Container::new(jobs_panel).padding([0, 15]));
+        }
+
+        if self.show_settings {
+            content = content.push(Rule::horizontal(10)).push(settings_panel(
+                &self.setting_inputs,
+                &self.setting_overrides,
+            ));
+        } else if self.selected_profile.is_some() {
+            if !self.timeline.captures.is_empty() {
+                content = content.push(timeline_panel(&self.timeline, &self.selected_captures));
+            }
+
+            if let Some(snapshot) = &self.snapshot {
+                // A past state of the profile takes the place of its files
+                content = content
+                    .push(Rule::horizontal(10))
+                    .push(Scrollable::new(snapshot_panel(snapshot)).height(Length::Fill));
+            } else {
+                content = content.push(Rule::horizontal(10)).push(
+                    Row::new()
+                        .push(
+                            Scrollable::new(file_tree_panel)
+                                .id(file_tree_scroll_id())
+                                .width(Length::FillPortion(1)),
+                        )
+                        .push(
+                            Container::new(self.table.view(&self.tree, &self.selection))
+                                .width(Length::FillPortion(3)),
+                        )
+                        .push(Scrollable::new(file_properties_panel).width(Length::FillPortion(1))),
+                );
+            }
+        }
+
         // This is a filler container to push everything else down
         content = content.push(Container::new(Space::new(Length::Fill, Length::Shrink)));
 
         // The progress bar part
-        println!(
-            "Rendering progress bar with ratio: {}",
-            self.file_upload_progress.ratio()
-        );
+        let (ratio, status) = match &self.upload_progress {
+            Some(progress) => (progress.ratio(), progress.to_string()),
+            None => (0.0, String::new()),
+        };
         content = content.push(
             Column::new()
                 .push(Space::new(Length::Fill, Length::Fill)) // Add this line
-                .push(Text::new("File Upload Progress"))
+                .push(Text::new("Job Progress"))
                 .push(
-                    ProgressBar::new(0.0..=1.0, self.file_upload_progress.ratio())
-                        .width(Length::Fill), // Make it span the width of the window
-                ),
+                    ProgressBar::new(0.0..=1.0, ratio).width(Length::Fill), // Make it span the width of the window
+                )
+                .push(Text::new(status).size(14)),
         );
 
         Container::new(content)
@@ -352,11 +1829,550 @@ impl Application for Archiver {
     }
 }
 
+// Keys for the file tree, unless a text input has taken them
+fn tree_key_event(event: Event, status: event::Status) -> Option<Message> {
+    if let event::Status::Captured = status {
+        return None;
+    }
+    match event {
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            key_code,
+            modifiers,
+        }) => match key_code {
+            KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::PageUp
+            | KeyCode::PageDown
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::Enter
+            | KeyCode::Space
+            | KeyCode::A => Some(Message::TreeKeyPressed(key_code, modifiers)),
+            _ => None,
+        },
+        Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+            Some(Message::ModifiersChanged(modifiers))
+        }
+        _ => None,
+    }
+}
+
+fn file_drop_event(event: Event, _status: event::Status) -> Option<Message> {
+    match event {
+        Event::Window(window::Event::FileHovered(_)) => Some(Message::FilesHovered(true)),
+        Event::Window(window::Event::FilesHoveredLeft) => Some(Message::FilesHovered(false)),
+        Event::Window(window::Event::FileDropped(path)) => Some(Message::FileDropped(path)),
+        _ => None,
+    }
+}
+
+// Turn mouse events into column resizing while a column edge is dragged
+fn column_resize_event(event: Event, _status: event::Status) -> Option<Message> {
+    match event {
+        Event::Mouse(mouse::Event::CursorMoved { position }) => {
+            Some(Message::ColumnResized(position.x))
+        }
+        Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+            Some(Message::ColumnResizeEnded)
+        }
+        _ => None,
+    }
+}
+
+fn file_tree_scroll_id() -> scrollable::Id {
+    scrollable::Id::new("file-tree")
+}
+
+// One notification, with its retry button and, in the notification area, a
+// dismiss button
+fn notification_row(notification: &Notification, dismissable: bool) -> Row<'_, Message> {
+    let (label, color) = match notification.kind {
+        NotificationKind::Info => ("", Color::from_rgb(0.3, 0.3, 0.3)),
+        NotificationKind::Error => ("Error: ", Color::from_rgb(0.8, 0.1, 0.1)),
+        NotificationKind::Alert => ("Alert: ", Color::from_rgb(0.85, 0.45, 0.0)),
+    };
+    let mut row = Row::new().spacing(10).align_items(Alignment::Center).push(
+        Text::new(format!("{}{}", label, notification.text))
+            .size(14)
+            .style(color)
+            .width(Length::Fill),
+    );
+    if notification.retry.is_some() {
+        row = row.push(
+            Button::new(Text::new("Retry").size(14))
+                .on_press(Message::RetryNotification(notification.id)),
+        );
+    }
+    if dismissable {
+        row = row.push(
+            Button::new(Text::new("Dismiss").size(14))
+                .on_press(Message::DismissNotification(notification.id)),
+        );
+    }
+    row
+}
+
 // Open a file dialog to choose files to upload
 async fn open_file_dialog() -> Result<Vec<PathBuf>, String> {
     if let Some(paths) = rfd::FileDialog::new().pick_files() {
-        Ok(paths.into_iter().map(|path| path.into()).collect()) // Convert into Vec<PathBuf>
+        Ok(paths)
     } else {
         Err("No file was selected or an error occurred".into())
     }
 }
+
+// Choices offered for the settings that take one of a few names
+const HASH_ALGORITHMS: [&str; 2] = ["blake3", "sha3-256"];
+const THEMES: [&str; 2] = ["light", "dark"];
+
+// A form with one row per setting, in KEYS order. Empty fields fall back to
+// the built-in defaults.
+fn settings_panel<'a>(inputs: &[String], overrides: &[Option<Source>]) -> Element<'a, Message> {
+    let rows = KEYS
+        .iter()
+        .enumerate()
+        .fold(Column::new().spacing(10), |column, (index, key)| {
+            let value = inputs[index].clone();
+            let input: Element<'a, Message> = match *key {
+                "hash_algorithm" | "theme" => {
+                    let choices: &'static [&'static str] = if *key == "theme" {
+                        &THEMES
+                    } else {
+                        &HASH_ALGORITHMS
+                    };
+                    let selected = choices.iter().copied().find(|choice| *choice == value);
+                    PickList::new(choices, selected, move |choice: &str| {
+                        Message::SettingChanged(index, choice.to_string())
+                    })
+                    .placeholder("default")
+                    .width(Length::Fill)
+                    .into()
+                }
+                _ => {
+                    let placeholder = match *key {
+                        "database_url" => "path to the SQLite database",
+                        "workers" => "4",
+                        "exclude" => "comma-separated patterns, e.g. *.tmp,.git",
+                        "repository" => "path to the archive repository",
+                        _ => "",
+                    };
+                    text_input(placeholder, &value)
+                        .on_input(move |value| Message::SettingChanged(index, value))
+                        .width(Length::Fill)
+                        .into()
+                }
+            };
+            let note = match overrides[index] {
+                Some(Source::Environment) => {
+                    format!("overridden by {}", config::env_vars()[index])
+                }
+                Some(Source::Flag) => String::from("overridden on the command line"),
+                _ => String::new(),
+            };
+            column.push(
+                Row::new()
+                    .spacing(20)
+                    .align_items(Alignment::Center)
+                    .push(Text::new(key.replace('_', " ")).width(Length::Fixed(140.0)))
+                    .push(input)
+                    .push(Text::new(note).size(14).width(Length::Fixed(260.0))),
+            )
+        });
+
+    Column::new()
+        .spacing(15)
+        .padding([0, 15])
+        .push(Text::new(format!(
+            "Settings are saved to {}",
+            config::config_path().display()
+        )))
+        .push(rows)
+        .push(
+            Row::new()
+                .spacing(20)
+                .push(
+                    Button::new(Text::new("Save"))
+                        .style(theme::Button::Primary)
+                        .on_press(Message::SaveSettings),
+                )
+                .push(
+                    Button::new(Text::new("Cancel"))
+                        .style(theme::Button::Secondary)
+                        .on_press(Message::ToggleSettings),
+                ),
+        )
+        .into()
+}
+
+// The profile's capture points as a row of markers, oldest first, with the
+// picked ones highlighted
+fn timeline_panel<'a>(timeline: &Timeline, selected: &[NaiveDateTime]) -> Element<'a, Message> {
+    let markers = timeline
+        .captures
+        .iter()
+        .fold(Row::new().spacing(5), |row, capture| {
+            let mut label = format!(
+                "{}\n+{}",
+                capture.at.format("%Y-%m-%d %H:%M"),
+                capture.added
+            );
+            if capture.removed > 0 {
+                label.push_str(&format!(" -{}", capture.removed));
+            }
+            let style = if selected.contains(&capture.at) {
+                theme::Button::Primary
+            } else {
+                theme::Button::Secondary
+            };
+            row.push(
+                Button::new(Text::new(label).size(13))
+                    .style(style)
+                    .on_press(Message::CaptureClicked(capture.at)),
+            )
+        });
+    Column::new()
+        .spacing(5)
+        .padding([0, 15])
+        .push(
+            Text::new(
+                "Timeline: click a capture to see the profile as it was then, \
+             Ctrl- or Shift-click a second one to compare",
+            )
+            .size(14),
+        )
+        .push(
+            Scrollable::new(Container::new(markers).padding([0, 0, 12, 0])).direction(
+                scrollable::Direction::Horizontal(scrollable::Properties::default()),
+            ),
+        )
+        .into()
+}
+
+// Lines listed for each part of a snapshot before the rest are counted
+const SNAPSHOT_LINES: usize = 500;
+
+// The files of the profile as of a capture point, or the files added,
+// removed and modified between two
+fn snapshot_panel(snapshot: &Snapshot) -> Column<'static, Message> {
+    let close = Button::new(Text::new("Back to current files"))
+        .style(theme::Button::Secondary)
+        .on_press(Message::CloseSnapshot);
+    let section = |column: Column<'static, Message>, lines: Vec<String>, color: Option<Color>| {
+        let hidden = lines.len().saturating_sub(SNAPSHOT_LINES);
+        let column = lines
+            .into_iter()
+            .take(SNAPSHOT_LINES)
+            .fold(column, |column, line| {
+                let text = Text::new(line).size(14);
+                column.push(match color {
+                    Some(color) => text.style(color),
+                    None => text,
+                })
+            });
+        if hidden > 0 {
+            column.push(Text::new(format!("and {} more", hidden)).size(14))
+        } else {
+            column
+        }
+    };
+
+    match snapshot {
+        Snapshot::AsOf(at, files) => {
+            let header = Row::new()
+                .spacing(20)
+                .align_items(Alignment::Center)
+                .push(Text::new(format!("As of {}: {} files", at, files.len())))
+                .push(close);
+            let lines = files
+                .iter()
+                .map(|file| {
+                    format!(
+                        "{}  {}",
+                        &file.sha256[..12.min(file.sha256.len())],
+                        file.file_name
+                    )
+                })
+                .collect();
+            section(
+                Column::new().spacing(2).padding(10).push(header),
+                lines,
+                None,
+            )
+        }
+        Snapshot::Diff(from, to, diff) => {
+            let header = Row::new()
+                .spacing(20)
+                .align_items(Alignment::Center)
+                .push(Text::new(format!(
+                    "From {} to {}: {} added, {} removed, {} modified",
+                    from,
+                    to,
+                    diff.added.len(),
+                    diff.removed.len(),
+                    diff.modified.len()
+                )))
+                .push(close);
+            let column = Column::new().spacing(2).padding(10).push(header);
+            let column = section(
+                column,
+                diff.added
+                    .iter()
+                    .map(|file| format!("+ {}", file.file_name))
+                    .collect(),
+                Some(Color::from_rgb(0.1, 0.5, 0.1)),
+            );
+            let column = section(
+                column,
+                diff.removed
+                    .iter()
+                    .map(|file| format!("- {}", file.file_name))
+                    .collect(),
+                Some(Color::from_rgb(0.8, 0.2, 0.2)),
+            );
+            section(
+                column,
+                diff.modified
+                    .iter()
+                    .map(|(_, file)| format!("~ {}", file.file_name))
+                    .collect(),
+                Some(Color::from_rgb(0.7, 0.5, 0.0)),
+            )
+        }
+    }
+}
+
+// Open a folder dialog to choose a folder to upload with everything in it
+async fn open_folder_dialog() -> Result<Vec<PathBuf>, String> {
+    match rfd::FileDialog::new().pick_folder() {
+        Some(path) => Ok(vec![path]),
+        None => Err("No folder was selected".into()),
+    }
+}
+
+// The files and bytes an upload would add, with buttons to go ahead or not
+fn ingest_preview_panel(pending: &PendingIngest) -> Column<'static, Message> {
+    let Some(preview) = &pending.preview else {
+        return Column::new().push(Text::new(format!(
+            "Looking for files in {} chosen item(s)...",
+            pending.paths.len()
+        )));
+    };
+
+    let mut summary = format!(
+        "Add {} file(s), {} in all",
+        preview.files.len(),
+        format_bytes(preview.total_bytes)
+    );
+    if preview.already_tracked > 0 {
+        summary.push_str(&format!(
+            "; {} are already tracked and are only added again if they changed",
+            preview.already_tracked
+        ));
+    }
+    if preview.excluded > 0 {
+        summary.push_str(&format!("; {} excluded by the settings", preview.excluded));
+    }
+    if !preview.skipped.is_empty() {
+        summary.push_str(&format!("; {} could not be read", preview.skipped.len()));
+    }
+
+    let mut add = Button::new(Text::new("Add")).style(theme::Button::Primary);
+    if !preview.files.is_empty() {
+        add = add.on_press(Message::ConfirmIngest);
+    }
+    let mut panel = Column::new().spacing(5).push(
+        Row::new()
+            .spacing(20)
+            .align_items(Alignment::Center)
+            .push(Text::new(summary))
+            .push(add)
+            .push(
+                Button::new(Text::new("Cancel"))
+                    .style(theme::Button::Secondary)
+                    .on_press(Message::CancelIngest),
+            ),
+    );
+    for file in preview.files.iter().take(5) {
+        panel = panel.push(Text::new(file.clone()).size(14));
+    }
+    if preview.files.len() > 5 {
+        panel = panel.push(Text::new(format!("and {} more", preview.files.len() - 5)).size(14));
+    }
+    for (path, reason) in preview.skipped.iter().take(5) {
+        panel = panel.push(
+            Text::new(format!("Skipped {}: {}", path, reason))
+                .size(14)
+                .style(Color::from_rgb(0.8, 0.2, 0.2)),
+        );
+    }
+    panel
+}
+
+// Open a save dialog to choose where a restored file is written
+async fn save_file_dialog(file_name: String) -> Option<PathBuf> {
+    rfd::FileDialog::new().set_file_name(&file_name).save_file()
+}
+
+// Start an ingest job, in archiverd if it is running so closing the window
+// does not stop it, else on our own pool
+fn start_ingest(
+    connection: DbPool,
+    pool: &ThreadPool,
+    profile_id: i32,
+    paths: &[String],
+) -> Result<i32, String> {
+    if let Some(job_id) = submit_ingest_to_daemon(profile_id, paths) {
+        return Ok(job_id);
+    }
+    let job =
+        create_ingest_job(connection.clone(), &profile_id, paths).map_err(|e| e.to_string())?;
+    pool.execute(move || {
+        if let Err(e) = run_job(connection, &job.id, |_| {}) {
+            debug_log!("Ingest job {} failed: {}", job.id, e);
+        }
+    });
+    Ok(job.id)
+}
+
+// Start a job verifying files on our own pool
+fn start_verify_job(
+    connection: DbPool,
+    pool: &ThreadPool,
+    profile_id: i32,
+    file_ids: &[i32],
+) -> Result<i32, String> {
+    let job = create_files_verify_job(connection.clone(), &profile_id, file_ids)
+        .map_err(|e| e.to_string())?;
+    pool.execute(move || {
+        if let Err(e) = run_job(connection, &job.id, |_| {}) {
+            debug_log!("Verify job {} failed: {}", job.id, e);
+        }
+    });
+    Ok(job.id)
+}
+
+// Hand files to archiverd, if it is running, returning the job it started
+#[cfg(unix)]
+fn submit_ingest_to_daemon(profile_id: i32, paths: &[String]) -> Option<i32> {
+    let mut client = DaemonClient::connect().ok()?;
+    match client.add_files(profile_id, paths.to_vec()) {
+        Ok(job_id) => Some(job_id),
+        Err(e) => {
+            debug_log!("archiverd refused the files, adding them here: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn submit_ingest_to_daemon(_profile_id: i32, _paths: &[String]) -> Option<i32> {
+    None
+}
+
+// Let archiverd, if it is running, pick up saved settings
+#[cfg(unix)]
+fn reload_daemon_settings() {
+    if let Ok(mut client) = DaemonClient::connect() {
+        if let Err(e) = client.reload_settings() {
+            debug_log!("archiverd did not reload its settings: {}", e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn reload_daemon_settings() {}
+
+// Pick up jobs that were interrupted, unless archiverd is running and has
+// already done so
+fn resume_interrupted_jobs(connection: &DbPool, pool: &ThreadPool) {
+    #[cfg(unix)]
+    if DaemonClient::connect().is_ok() {
+        return;
+    }
+
+    match interrupted_jobs(connection.clone()) {
+        Ok(jobs) => {
+            for job in jobs {
+                debug_log!("Resuming {} job {}", job.kind, job.id);
+                let connection = connection.clone();
+                pool.execute(move || {
+                    if let Err(e) = run_job(connection, &job.id, |_| {}) {
+                        debug_log!("Job {} failed: {}", job.id, e);
+                    }
+                });
+            }
+        }
+        Err(e) => debug_log!("Failed to load interrupted jobs: {}", e),
+    }
+}
+
+// Turn the failed items of a finished job into notifications: ingest errors,
+// and alerts for files that failed verification or scrubbing
+fn job_failures(connection: DbPool, job: &Job) -> Vec<Notification> {
+    let items = match get_job_items(connection.clone(), &job.id) {
+        Ok(items) => items,
+        Err(e) => {
+            return vec![Notification::error(format!(
+                "Failed to load the results of job {}: {}",
+                job.id, e
+            ))]
+        }
+    };
+    let kind = JobKind::parse(&job.kind);
+    let names: HashMap<i32, String> = match kind {
+        Some(JobKind::Ingest) => HashMap::new(),
+        _ => get_files(connection, &job.profile_id)
+            .into_iter()
+            .map(|file| (file.id, file.file_name))
+            .collect(),
+    };
+
+    items
+        .into_iter()
+        .filter(|item| item.state == ITEM_FAILED)
+        .map(|item| {
+            let reason = item.reason.unwrap_or_default();
+            if kind == Some(JobKind::Ingest) {
+                return Notification::error(format!(
+                    "Failed to ingest {}: {}",
+                    item.target, reason
+                ))
+                .with_retry(Some(Retry::Ingest {
+                    profile_id: job.profile_id,
+                    path: item.target,
+                }));
+            }
+            let file_id = item.target.parse::<i32>().ok();
+            let file_name = file_id
+                .and_then(|file_id| names.get(&file_id).cloned())
+                .unwrap_or_else(|| item.target.clone());
+            Notification::alert(format!("Verification of {} failed: {}", file_name, reason))
+                .with_retry(file_id.map(|file_id| Retry::Verify {
+                    profile_id: job.profile_id,
+                    file_id,
+                }))
+        })
+        .collect()
+}
+
+// Check how far a job has got. Jobs run by archiverd are in the same
+// database, so the item counts work wherever the job runs; byte progress
+// comes from whichever process is running it.
+fn poll_job(connection: DbPool, job_id: i32) -> JobPoll {
+    let job = get_job(connection, &job_id).map_err(|e| e.to_string())?;
+    let progress = job_progress(job_id).or_else(|| daemon_job_progress(job_id));
+    Ok((job, progress))
+}
+
+#[cfg(unix)]
+fn daemon_job_progress(job_id: i32) -> Option<Progress> {
+    let mut client = DaemonClient::connect().ok()?;
+    client.job_status(job_id).ok()?.1
+}
+
+#[cfg(not(unix))]
+fn daemon_job_progress(_job_id: i32) -> Option<Progress> {
+    None
+}
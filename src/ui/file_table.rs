@@ -0,0 +1,314 @@
+// A sortable table of a profile's files. Only the rows in view are built,
+// with spacers standing in for the rest, so it keeps up with 100k files.
+
+use iced::widget::{
+    mouse_area, scrollable, Button, Column, Container, Row, Rule, Scrollable, Space, Text,
+};
+use iced::{alignment, theme, Element, Length};
+use rs_timeskip_archiver::file_tree::{FileEntry, FileTree, VerificationStatus};
+use rs_timeskip_archiver::progress::format_bytes;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use unicode_width::UnicodeWidthChar;
+
+use super::Message;
+
+const ROW_HEIGHT: f32 = 26.0;
+// Rows built past the top of the view; enough for a tall window
+const ROWS_BUILT: usize = 80;
+const TEXT_SIZE: f32 = 13.0;
+// Roughly how wide one character column of TEXT_SIZE text is
+const CHAR_WIDTH: f32 = 7.0;
+const MIN_COLUMN_WIDTH: f32 = 40.0;
+const HANDLE_WIDTH: f32 = 6.0;
+const DIGEST_SHOWN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableColumn {
+    Name,
+    Path,
+    Digest,
+    Size,
+    Modified,
+    Ingested,
+    Verified,
+}
+
+impl TableColumn {
+    const ALL: [TableColumn; 7] = [
+        TableColumn::Name,
+        TableColumn::Path,
+        TableColumn::Digest,
+        TableColumn::Size,
+        TableColumn::Modified,
+        TableColumn::Ingested,
+        TableColumn::Verified,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            TableColumn::Name => "Name",
+            TableColumn::Path => "Path",
+            TableColumn::Digest => "Digest",
+            TableColumn::Size => "Size",
+            TableColumn::Modified => "Modified",
+            TableColumn::Ingested => "Ingested",
+            TableColumn::Verified => "Last verification",
+        }
+    }
+
+    fn default_width(&self) -> f32 {
+        match self {
+            TableColumn::Name => 200.0,
+            TableColumn::Path => 320.0,
+            TableColumn::Digest => 120.0,
+            TableColumn::Size => 90.0,
+            TableColumn::Modified | TableColumn::Ingested => 160.0,
+            TableColumn::Verified => 130.0,
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        match self {
+            TableColumn::Name => a.name().cmp(b.name()),
+            TableColumn::Path => a.file.file_name.cmp(&b.file.file_name),
+            TableColumn::Digest => a.file.sha256.cmp(&b.file.sha256),
+            TableColumn::Size => a.size.cmp(&b.size),
+            TableColumn::Modified => a.file.modified_at.cmp(&b.file.modified_at),
+            TableColumn::Ingested => a.file.created_at.cmp(&b.file.created_at),
+            TableColumn::Verified => a
+                .status
+                .cmp(&b.status)
+                .then(a.file.last_verified_at.cmp(&b.file.last_verified_at)),
+        }
+    }
+}
+
+// A column being resized by dragging its right edge. The drag starts from
+// the first cursor position seen after the press.
+struct Resize {
+    column: TableColumn,
+    start: Option<(f32, f32)>,
+}
+
+pub struct FileTable {
+    sort_column: TableColumn,
+    descending: bool,
+    // Indices into the tree's entries, in table order
+    order: Vec<usize>,
+    offset: f32,
+    widths: [f32; TableColumn::ALL.len()],
+    resizing: Option<Resize>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable {
+            sort_column: TableColumn::Path,
+            descending: false,
+            order: Vec::new(),
+            offset: 0.0,
+            widths: TableColumn::ALL.map(|column| column.default_width()),
+            resizing: None,
+        }
+    }
+
+    /// Put the tree's files in table order again, after they have changed.
+    pub fn sort(&mut self, tree: &FileTree) {
+        let column = self.sort_column;
+        let mut order: Vec<usize> = (0..tree.entries.len()).collect();
+        order.sort_by(|a, b| column.compare(&tree.entries[*a], &tree.entries[*b]));
+        if self.descending {
+            order.reverse();
+        }
+        self.order = order;
+    }
+
+    /// Sort by `column`, or reverse the order if it already is.
+    pub fn sort_by(&mut self, column: TableColumn, tree: &FileTree) {
+        if self.sort_column == column {
+            self.descending = !self.descending;
+        } else {
+            self.sort_column = column;
+            self.descending = false;
+        }
+        self.sort(tree);
+    }
+
+    pub fn scrolled(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    /// The ids of the files in table order.
+    pub fn file_ids(&self, tree: &FileTree) -> Vec<i32> {
+        self.order
+            .iter()
+            .map(|&index| tree.entries[index].file.id)
+            .collect()
+    }
+
+    pub fn is_resizing(&self) -> bool {
+        self.resizing.is_some()
+    }
+
+    pub fn start_resize(&mut self, column: TableColumn) {
+        self.resizing = Some(Resize {
+            column,
+            start: None,
+        });
+    }
+
+    pub fn cursor_moved(&mut self, x: f32) {
+        let Some(resize) = &mut self.resizing else {
+            return;
+        };
+        let width = &mut self.widths[resize.column.index()];
+        match resize.start {
+            None => resize.start = Some((x, *width)),
+            Some((start_x, start_width)) => {
+                *width = (start_width + x - start_x).max(MIN_COLUMN_WIDTH)
+            }
+        }
+    }
+
+    pub fn end_resize(&mut self) {
+        self.resizing = None;
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        tree: &'a FileTree,
+        selection: &HashSet<i32>,
+    ) -> Element<'a, Message> {
+        let total_width: f32 = self.widths.iter().sum();
+
+        let header = TableColumn::ALL.iter().fold(Row::new(), |row, column| {
+            let width = self.widths[column.index()];
+            let arrow = match (self.sort_column == *column, self.descending) {
+                (true, false) => " ^",
+                (true, true) => " v",
+                (false, _) => "",
+            };
+            row.push(
+                Button::new(
+                    Text::new(fit(
+                        &format!("{}{}", column.title(), arrow),
+                        width - HANDLE_WIDTH,
+                    ))
+                    .size(TEXT_SIZE),
+                )
+                .style(theme::Button::Secondary)
+                .width(Length::Fixed(width - HANDLE_WIDTH))
+                .on_press(Message::SortTable(*column)),
+            )
+            .push(
+                mouse_area(
+                    Container::new(Rule::vertical(1))
+                        .width(Length::Fixed(HANDLE_WIDTH))
+                        .height(Length::Fixed(ROW_HEIGHT))
+                        .center_x(),
+                )
+                .on_press(Message::ColumnResizeStarted(*column)),
+            )
+        });
+
+        // Only the rows around the scroll position are built
+        let first = ((self.offset / ROW_HEIGHT) as usize).min(self.order.len());
+        let last = (first + ROWS_BUILT).min(self.order.len());
+        let rows = self.order[first..last]
+            .iter()
+            .map(|&index| &tree.entries[index])
+            .fold(
+                Column::new().push(Space::with_height(Length::Fixed(first as f32 * ROW_HEIGHT))),
+                |column, entry| column.push(self.row(entry, selection.contains(&entry.file.id))),
+            )
+            .push(Space::with_height(Length::Fixed(
+                (self.order.len() - last) as f32 * ROW_HEIGHT,
+            )));
+
+        let body = Scrollable::new(rows.width(Length::Fixed(total_width)))
+            .on_scroll(|viewport| Message::TableScrolled(viewport.absolute_offset().y));
+
+        Scrollable::new(
+            Column::new()
+                .width(Length::Fixed(total_width))
+                .push(header)
+                .push(body),
+        )
+        .direction(scrollable::Direction::Horizontal(
+            scrollable::Properties::default(),
+        ))
+        .into()
+    }
+
+    fn row<'a>(&self, entry: &'a FileEntry, selected: bool) -> Element<'a, Message> {
+        let file = &entry.file;
+        let cell = |column: TableColumn, text: String| {
+            let width = self.widths[column.index()];
+            Container::new(Text::new(fit(&text, width - HANDLE_WIDTH)).size(TEXT_SIZE))
+                .width(Length::Fixed(width))
+                .padding([0, 4])
+        };
+
+        let digest: String = file.sha256.chars().take(DIGEST_SHOWN).collect();
+        let digest_width = self.widths[TableColumn::Digest.index()];
+        let verified = match (&entry.status, &file.last_verified_at) {
+            (VerificationStatus::Unverified, _) | (_, None) => String::from("never"),
+            (status, Some(at)) => format!("{} {}", status.as_str(), at.format("%Y-%m-%d")),
+        };
+
+        let cells = Row::new()
+            .align_items(alignment::Alignment::Center)
+            .push(cell(TableColumn::Name, entry.name().to_string()))
+            .push(cell(TableColumn::Path, file.file_name.clone()))
+            .push(
+                Button::new(Text::new(format!("{}...", digest)).size(TEXT_SIZE))
+                    .style(theme::Button::Text)
+                    .padding([0, 4])
+                    .width(Length::Fixed(digest_width))
+                    .on_press(Message::CopyDigest(file.sha256.clone())),
+            )
+            .push(cell(TableColumn::Size, format_bytes(entry.size)))
+            .push(cell(
+                TableColumn::Modified,
+                file.modified_at
+                    .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| String::from("-")),
+            ))
+            .push(cell(
+                TableColumn::Ingested,
+                file.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            ))
+            .push(cell(TableColumn::Verified, verified));
+
+        let mut row = Container::new(cells)
+            .height(Length::Fixed(ROW_HEIGHT))
+            .center_y();
+        if selected {
+            row = row.style(theme::Container::Box);
+        }
+        mouse_area(row)
+            .on_press(Message::TableRowClicked(file.id))
+            .into()
+    }
+}
+
+// Cut `text` to about `width` pixels, marking the cut
+fn fit(text: &str, width: f32) -> String {
+    let columns = (width / CHAR_WIDTH).max(1.0) as usize;
+    let mut used = 0;
+    let mut fitted = String::new();
+    for c in text.chars() {
+        used += c.width().unwrap_or(0);
+        if used + 1 > columns {
+            fitted.push('…');
+            return fitted;
+        }
+        fitted.push(c);
+    }
+    fitted
+}
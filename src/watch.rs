@@ -0,0 +1,138 @@
+//! Watching folders for new and changed files, so `archiverd` can archive
+//! them as they appear.
+//!
+//! Watching polls: each round walks the folder as an ingest would and picks
+//! the files the profile does not track yet, or tracks with a different
+//! modification time. Files deleted from the folder stay archived.
+
+use chrono::{NaiveDateTime, Timelike};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::db::DbPool;
+use crate::error::ArchiverError;
+use crate::scan::preview_ingest;
+use crate::schema::files;
+
+/// How often watched folders are checked unless `ARCHIVERD_WATCH_INTERVAL`
+/// gives another number of seconds.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A folder archived into a profile as it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub profile_id: i32,
+    pub path: String,
+}
+
+pub fn watch_interval() -> Duration {
+    env::var("ARCHIVERD_WATCH_INTERVAL")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_WATCH_INTERVAL)
+}
+
+/// The files below `path` the profile does not track yet, or tracks with
+/// another modification time, in the order an ingest would add them.
+pub fn changed_files(conn: DbPool, pid: &i32, path: &Path) -> Result<Vec<String>, ArchiverError> {
+    let preview = preview_ingest(conn.clone(), pid, &[PathBuf::from(path)])?;
+
+    let tracked: HashMap<String, Option<NaiveDateTime>> = {
+        let mut conn = conn.get()?;
+        files::table
+            .filter(files::profile_id.eq(pid))
+            .filter(files::removed_at.is_null())
+            .select((files::file_name, files::modified_at))
+            .load::<(String, Option<NaiveDateTime>)>(&mut *conn)?
+            .into_iter()
+            .collect()
+    };
+
+    Ok(preview
+        .files
+        .into_iter()
+        .filter(|file| match tracked.get(file) {
+            None => true,
+            Some(modified_at) => modified_at.map(to_micros) != modified_time(file),
+        })
+        .collect())
+}
+
+// The modification time as archiving records it, to the microsecond that
+// every catalog backend keeps
+fn modified_time(path: &str) -> Option<NaiveDateTime> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(to_micros(
+        chrono::DateTime::<chrono::Utc>::from(modified).naive_utc(),
+    ))
+}
+
+fn to_micros(time: NaiveDateTime) -> NaiveDateTime {
+    time.with_nanosecond(time.nanosecond() / 1000 * 1000)
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{add_file, create_profile, establish_connection_to, get_profiles};
+    use std::time::SystemTime;
+
+    fn workspace(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("tsk-watch-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("watched")).unwrap();
+        env::set_var("ARCHIVE_PASSPHRASE", "watch test");
+        path
+    }
+
+    #[test]
+    fn picks_new_and_modified_files() {
+        let dir = workspace("changes");
+        let conn = establish_connection_to(dir.join("catalog.db").to_str().unwrap()).unwrap();
+        create_profile(conn.clone(), "watched");
+        let pid = get_profiles(conn.clone())[0].id;
+        crate::set_profile_storage(conn.clone(), &pid, dir.join("repository").to_str()).unwrap();
+
+        let watched = dir.join("watched");
+        let archived = watched.join("archived.txt");
+        let added = watched.join("added.txt");
+        fs::write(&archived, "archived").unwrap();
+        add_file(
+            conn.clone(),
+            archived.display().to_string(),
+            &pid,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert!(changed_files(conn.clone(), &pid, &watched)
+            .unwrap()
+            .is_empty());
+
+        fs::write(&added, "added").unwrap();
+        assert_eq!(
+            changed_files(conn.clone(), &pid, &watched).unwrap(),
+            vec![added.display().to_string()]
+        );
+
+        fs::remove_file(&added).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&archived)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            changed_files(conn, &pid, &watched).unwrap(),
+            vec![archived.display().to_string()]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}